@@ -0,0 +1,14 @@
+// Links the runtime of the compiled programs into the tests with the `aux` feature,
+// so that `tests/math.rs` can compare its math functions with the ones of the
+// simulator. The other tests stay position independent without it.
+fn main() {
+	println!("cargo:rerun-if-changed=src/resources/aux.o");
+	let arch = std::env::var("CARGO_CFG_TARGET_ARCH").unwrap();
+	let os = std::env::var("CARGO_CFG_TARGET_OS").unwrap();
+	let aux = std::env::var_os("CARGO_FEATURE_AUX").is_some();
+	if aux && arch == "x86_64" && os == "linux" {
+		// It is built without `-fpic` like the compiled programs
+		println!("cargo:rustc-link-arg-tests=-no-pie");
+		println!("cargo:rustc-link-arg-tests=src/resources/aux.o");
+	}
+}
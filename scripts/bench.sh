@@ -0,0 +1,58 @@
+#!/usr/bin/env bash
+# Times `ssmpl sim` of the working tree against the revisions given as
+# arguments (default: the merge base of HEAD and $BASE, main by default) on loop
+# and memory heavy programs, keeping the best of RUNS runs. The revisions are
+# built once per commit in target/bench.
+set -euo pipefail
+cd "$(dirname "$0")/.."
+
+RUNS=${RUNS:-3}
+if [ $# -eq 0 ]; then
+	NAMES=("merge base" "working tree")
+	set -- "$(git merge-base HEAD "${BASE:-main}")"
+else
+	NAMES=("$@" "working tree")
+fi
+REVISIONS=()
+for revision in "$@"; do
+	REVISIONS+=("$(git rev-parse --verify "$revision^{commit}")")
+done
+
+mkdir -p target/bench
+PROGRAMS=(examples/loop_bench.ssmpl examples/sieve_bench.ssmpl)
+
+BINARIES=()
+for revision in "${REVISIONS[@]}"; do
+	dir=target/bench/$revision
+	if [ ! -x "$dir/target/release/ssmpl" ]; then
+		rm -rf "$dir" && mkdir -p "$dir"
+		git archive "$revision" | tar -x -C "$dir"
+		cargo build --release --quiet --manifest-path "$dir/Cargo.toml" \
+			--target-dir "$dir/target"
+	fi
+	BINARIES+=("$dir/target/release/ssmpl")
+done
+cargo build --release --quiet
+BINARIES+=(target/release/ssmpl)
+
+# Best wall time of `ssmpl sim $2` with the binary $1, in seconds, or `failed`
+# if a run fails or takes more than 2GB
+best() {
+	for _ in $(seq "$RUNS"); do
+		start=$(date +%s.%N)
+		(ulimit -v 2000000 && "$1" sim "$2" --log Error) &> /dev/null || echo failed
+		echo "$start $(date +%s.%N)"
+	done 2> /dev/null | awk '/failed/ { failed = 1 } { t = $2 - $1; if (NR == 1 || t < best) best = t }
+		END { if (failed) printf "failed"; else printf "%.2fs", best }'
+}
+
+printf '%-32s' "program"
+printf '%14s' "${NAMES[@]}"
+echo
+for program in "${PROGRAMS[@]}"; do
+	printf '%-32s' "$(basename "$program")"
+	for binary in "${BINARIES[@]}"; do
+		printf '%14s' "$(best "$binary" "$program")"
+	done
+	echo
+done
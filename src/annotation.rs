@@ -11,6 +11,12 @@ impl Position {
 	pub fn new(file_path: String, line: usize, col: usize) -> Self {
 		Position { file_path, line, col }
 	}
+
+	pub fn file_path(&self) -> &str { &self.file_path }
+
+	pub fn line(&self) -> usize { self.line }
+
+	pub fn col(&self) -> usize { self.col }
 }
 
 impl Display for Position {
@@ -19,6 +25,20 @@ impl Display for Position {
 	}
 }
 
+/// A macro invocation site that an op was expanded from.
+#[derive(Debug, Clone)]
+pub struct Expansion {
+	pub name: String,
+	pub pos:  Position,
+}
+
+impl Display for Expansion {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let Position { file_path, line, col } = &self.pos;
+		write!(f, "in expansion of `{}` at {file_path}:{line}:{col}", self.name)
+	}
+}
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum Type {
 	I64,
@@ -27,6 +47,17 @@ pub enum Type {
 	Ptr,
 }
 
+impl Type {
+	/// Value of a raw slot of the simulator holding this type
+	pub fn format(&self, slot: u64) -> String {
+		match self {
+			| Type::I64 | Type::Ptr => (slot as i64).to_string(),
+			| Type::Bool => (slot != 0).to_string(),
+			| Type::F64 => format!("{:?}", f64::from_bits(slot)),
+		}
+	}
+}
+
 impl Display for Type {
 	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
 		match self {
@@ -40,15 +71,31 @@ impl Display for Type {
 
 #[derive(Clone)]
 pub struct Annotation {
-	pos:     Position,
-	pub typ: Type,
+	pos:        Position,
+	pub typ:    Type,
+	/// Macro invocation sites, innermost first.
+	expansions: Vec<Expansion>,
 }
 
 impl Annotation {
-	pub fn new(pos: Position) -> Self { Annotation { pos, typ: Type::I64 } }
+	pub fn new(pos: Position) -> Self {
+		Annotation { pos, typ: Type::I64, expansions: vec![] }
+	}
 
 	pub fn get_pos(&self) -> &Position { &self.pos }
 
+	pub fn get_expansions(&self) -> &[Expansion] { &self.expansions }
+
+	pub fn push_expansion(&mut self, name: String, pos: Position) {
+		self.expansions.push(Expansion { name, pos })
+	}
+
+	/// One indented line per macro invocation site, ready to be appended to a
+	/// diagnostic.
+	pub fn expansion_trace(&self) -> String {
+		self.expansions.iter().map(|expansion| format!("\n\t{expansion}")).collect()
+	}
+
 	pub fn with_type(mut self, typ: Type) -> Self {
 		self.typ = typ;
 		self
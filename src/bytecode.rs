@@ -0,0 +1,817 @@
+//#region Imports
+use std::{collections::HashSet, fmt::Display};
+
+use crate::{
+	annotation::Type,
+	parser::{IntOp, Op, OpType, Overflow, Program},
+};
+//#endregion
+
+/// Instruction of the simulator's virtual machine.
+///
+/// Values are stored as raw `u64` slots, how a slot is interpreted is decided
+/// once while lowering from the types of the values, so that the instructions
+/// never have to look at a tag.
+#[derive(Clone, Copy)]
+pub enum Instr {
+	Push(u64),
+	DumpI,
+	DumpF,
+	DumpB,
+	/// Converts the integer `n` slots below the top of the stack to a float
+	ToF64(u8),
+	/// Truncates the float on top of the stack to an integer, `i64::MIN` if it
+	/// does not fit like `cvttsd2si`
+	ToI64,
+	RoundF,
+	FloorF,
+	CeilF,
+	SqrtF,
+	AbsF,
+	NegF,
+	ExpF,
+	LogF,
+	SinF,
+	CosF,
+	FmodF,
+	MinF,
+	MaxF,
+	PowF,
+	/// Replaces the float `n` slots below the top of the stack by whether it is
+	/// non zero
+	FToBool(u8),
+	/// Replaces the top of the stack by whether it is non zero
+	ToBool,
+	AddI,
+	SubI,
+	MulI,
+	AddSat,
+	SubSat,
+	MulSat,
+	/// Pushes the wrapped result and whether it overflowed
+	AddChecked,
+	SubChecked,
+	MulChecked,
+	/// Runtime error on overflow, for `--overflow-checks`
+	AddTrap,
+	SubTrap,
+	MulTrap,
+	/// Runtime error on a zero divisor, like `Mod` and `DivMod*`
+	DivI,
+	Mod,
+	/// Pushes the truncated quotient and remainder
+	DivModI,
+	/// Pushes the floored quotient and remainder
+	DivModFloorI,
+	AddF,
+	SubF,
+	MulF,
+	DivF,
+	IncI,
+	DecI,
+	IncF,
+	DecF,
+	Drop(u32),
+	Swap,
+	Rot,
+	Unrot,
+	Nip,
+	Tuck,
+	Swap2,
+	Roll(u32),
+	Over(u32),
+	Dup(u32),
+	SetOver(u32),
+	Jmp(u32),
+	/// Pops the top of the stack and jumps if it is zero
+	JmpZ(u32),
+	/// Pops the float on top of the stack and jumps if it is zero
+	JmpZF(u32),
+	EqI,
+	NeqI,
+	LtI,
+	GtI,
+	LteI,
+	GteI,
+	EqF,
+	NeqF,
+	LtF,
+	GtF,
+	LteF,
+	GteF,
+	/// (syscode, argc)
+	Syscall(u32, u32),
+	/// Calls the host function of the `n`th host call, see `Bytecode::hosts`
+	Host(u32),
+	/// Pops the exit status and stops
+	Exit,
+	ProgramArgs,
+	Load8,
+	Load16,
+	Load32,
+	Load64,
+	Store8,
+	Store16,
+	Store32,
+	Store64,
+	ShiftR,
+	ShiftRA,
+	ShiftL,
+	RotL,
+	RotR,
+	BitAnd,
+	BitOr,
+	Xor,
+	Popcount,
+	Clz,
+	Ctz,
+	And,
+	Or,
+	Not,
+	NotB,
+	// Fused instructions, see `fuse`
+	AddImm(u64),
+	SubImm(u64),
+	MulImm(u64),
+	BitAndImm(u64),
+	BitOrImm(u64),
+	ShiftRImm(u64),
+	ShiftLImm(u64),
+	EqImm(u64),
+	NeqImm(u64),
+	LtImm(u64),
+	GtImm(u64),
+	LteImm(u64),
+	GteImm(u64),
+	/// Pushes the value under the top of the stack
+	PushUnder(u64),
+	/// Pops the top of the stack and jumps unless it equals the value
+	JmpNotEqImm(u64, u32),
+	JmpNotNeqImm(u64, u32),
+	JmpNotLtImm(u64, u32),
+	JmpNotGtImm(u64, u32),
+	JmpNotLteImm(u64, u32),
+	JmpNotGteImm(u64, u32),
+}
+
+/// Combines an instruction with the one emitted right before it, so that common
+/// sequences such as `dup 10 < do` take fewer dispatches.
+fn fuse(prev: Instr, next: Instr) -> Option<Instr> {
+	use Instr::*;
+	Some(match (prev, next) {
+		| (Push(v), IncI) => Push(v.wrapping_add(1)),
+		| (Push(v), DecI) => Push(v.wrapping_sub(1)),
+		| (Push(v), AddI) => AddImm(v),
+		| (Push(v), SubI) => SubImm(v),
+		| (Push(v), MulI) => MulImm(v),
+		| (Push(v), BitAnd) => BitAndImm(v),
+		| (Push(v), BitOr) => BitOrImm(v),
+		| (Push(v), ShiftR) => ShiftRImm(v),
+		| (Push(v), ShiftL) => ShiftLImm(v),
+		| (Push(v), EqI) => EqImm(v),
+		| (Push(v), NeqI) => NeqImm(v),
+		| (Push(v), LtI) => LtImm(v),
+		| (Push(v), GtI) => GtImm(v),
+		| (Push(v), LteI) => LteImm(v),
+		| (Push(v), GteI) => GteImm(v),
+		| (Push(v), Swap) => PushUnder(v),
+		// Booleans are only normalized, that does not change their truthiness
+		| (ToBool, JmpZ(to)) => JmpZ(to),
+		| (EqImm(v), JmpZ(to)) => JmpNotEqImm(v, to),
+		| (NeqImm(v), JmpZ(to)) => JmpNotNeqImm(v, to),
+		| (LtImm(v), JmpZ(to)) => JmpNotLtImm(v, to),
+		| (GtImm(v), JmpZ(to)) => JmpNotGtImm(v, to),
+		| (LteImm(v), JmpZ(to)) => JmpNotLteImm(v, to),
+		| (GteImm(v), JmpZ(to)) => JmpNotGteImm(v, to),
+		| _ => return None,
+	})
+}
+
+/// Accumulates the instructions lowered from the ops
+struct Emitter {
+	code:    Vec<Instr>,
+	origins: Vec<usize>,
+	firsts:  Vec<usize>,
+	/// Op currently being lowered
+	ip:      usize,
+	/// Instructions before this index can not be fused anymore, the following
+	/// one may be the target of a jump
+	barrier: usize,
+}
+
+impl Emitter {
+	fn emit(&mut self, instr: Instr) {
+		if self.code.len() > self.barrier {
+			if let Some(fused) = fuse(*self.code.last().unwrap(), instr) {
+				*self.code.last_mut().unwrap() = fused;
+				*self.origins.last_mut().unwrap() = self.ip;
+				return;
+			}
+		}
+		self.code.push(instr);
+		self.origins.push(self.ip);
+		self.firsts.push(self.ip);
+	}
+}
+
+#[derive(Clone, PartialEq)]
+pub enum RegionKind {
+	Str(String),
+	Decla(String),
+	/// The builtin `mem`, from the end of the other regions to the end of the
+	/// memory
+	Mem,
+}
+
+/// Part of the simulator's memory reserved at lowering time
+#[derive(Clone)]
+pub struct Region {
+	pub kind:  RegionKind,
+	pub start: usize,
+	pub len:   usize,
+}
+
+impl Region {
+	/// Distance from the address to the closest byte of the region
+	pub fn distance(&self, addr: i64) -> u128 {
+		let (addr, start) = (addr as i128, self.start as i128);
+		let end = start + self.len as i128;
+		if addr < start {
+			(start - addr) as u128
+		} else if addr >= end {
+			(addr - end + 1) as u128
+		} else {
+			0
+		}
+	}
+}
+
+impl Display for Region {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let range = format!("[{}, {})", self.start, self.start + self.len);
+		match &self.kind {
+			| RegionKind::Str(lit) => write!(f, "string literal {lit:?} at {range}"),
+			| RegionKind::Decla(name) => write!(f, "decla region `{name}` at {range}"),
+			| RegionKind::Mem => write!(f, "builtin `mem` at {range}"),
+		}
+	}
+}
+
+/// Placement of the memory of a program
+#[derive(Clone, Default)]
+pub struct Layout {
+	/// Initial content of the memory, the string literals
+	pub data:    Vec<u8>,
+	/// String literals and `decla` regions, in address order
+	pub regions: Vec<Region>,
+	/// Address of the builtin `mem`
+	pub mem:     usize,
+}
+
+pub struct Bytecode {
+	pub code:       Vec<Instr>,
+	/// Index of the op each instruction was lowered from
+	pub origins:    Vec<usize>,
+	/// Index of the first op each instruction was lowered from, an instruction
+	/// fused across ops stands for every op from there to its origin
+	pub firsts:     Vec<usize>,
+	/// Initial content of the memory, the string literals
+	pub data:       Vec<u8>,
+	/// String literals and `decla` regions, in address order
+	pub regions:    Vec<Region>,
+	/// Address of the builtin `mem`
+	pub mem:        usize,
+	/// Store instructions writing a ptr typed value
+	pub ptr_stores: HashSet<usize>,
+	/// Load instructions whose value is directly cast to a ptr
+	pub ptr_loads:  HashSet<usize>,
+	/// Types of the stack after every op, only when lowered for tracing
+	pub stacks:     Vec<Vec<Type>>,
+	/// Op of every host call
+	pub hosts:      Vec<usize>,
+}
+
+/// Number of times every op was executed, from the number of times every
+/// instruction was
+pub fn executed_ops(
+	hits: &[u64],
+	origins: &[usize],
+	firsts: &[usize],
+	ops_len: usize,
+) -> Vec<u64> {
+	let mut executed = vec![0; ops_len];
+	for (ip, hits) in hits.iter().enumerate() {
+		let first = firsts[ip];
+		for (op, count) in (first..).zip(&mut executed[first..=origins[ip]]) {
+			// Only the first instruction of an op executes it
+			if ip == 0 || origins[ip - 1] != op {
+				*count = *hits;
+			}
+		}
+	}
+	executed
+}
+
+impl Op {
+	fn is_control_flow(&self) -> bool {
+		use OpType::*;
+		matches!(self.typ, If(_) | Then(..) | Else(_) | End(..) | While(_) | Do(_))
+	}
+}
+
+impl Program {
+	/// Resolves the target of every control flow op.
+	///
+	/// - `Then` jumps to its `Else` (or `End`) when the condition is false
+	/// - `Else` jumps to its `End`
+	/// - `Do` jumps to its `End` when the condition is false
+	/// - The `End` of a `While` jumps back to the `While`
+	///
+	/// The execution continues at the op following the target.
+	pub fn resolve_jumps(&self) -> Vec<usize> {
+		use OpType::*;
+		let mut jumps = vec![0; self.ops.len()];
+		// Indices of the control flow ops of the blocks currently open
+		let mut blocks: Vec<Vec<usize>> = vec![];
+		for (ip, Op { typ, .. }) in self.ops.iter().enumerate() {
+			match typ {
+				| If(_) | While(_) => blocks.push(vec![ip]),
+				| Then(..) | Else(_) | Do(_) => blocks.last_mut().unwrap().push(ip),
+				| End(..) => {
+					match blocks.pop().unwrap()[..] {
+						| [while_, do_] if matches!(self.ops[while_].typ, While(_)) => {
+							jumps[do_] = ip;
+							jumps[ip] = while_;
+						}
+						| [_, then] => jumps[then] = ip,
+						| [_, then, else_] => {
+							jumps[then] = else_;
+							jumps[else_] = ip;
+						}
+						| _ => unreachable!("Prevented by type check"),
+					}
+				}
+				| _ => (),
+			}
+		}
+		jumps
+	}
+
+	/// Places the string literals and `decla` regions in the memory
+	pub fn layout(&self) -> Layout {
+		let mut data: Vec<u8> = vec![];
+		let mut regions: Vec<Region> = vec![];
+		for lit in self.strings.iter() {
+			// Null terminated, like in the compiled program
+			let kind = RegionKind::Str(lit.clone());
+			regions.push(Region { kind, start: data.len(), len: lit.len() + 1 });
+			data.extend_from_slice(lit.as_bytes());
+			data.push(0);
+		}
+		let mut mem = data.len();
+		for name in self.memory_regions_order.iter() {
+			let len = self.memory_regions[name] as usize;
+			regions.push(Region {
+				kind: RegionKind::Decla(name.clone()),
+				start: mem,
+				len,
+			});
+			mem += len;
+		}
+		Layout { data, regions, mem }
+	}
+
+	/// Lowers the type checked program into bytecode for the simulator.
+	///
+	/// The types of the values are followed the same way the values themselves
+	/// used to be tagged at runtime, so that every op picks the exact same
+	/// integer or float behaviour. Pointers behave as integers for every op,
+	/// they are only kept apart for the sanitizer.
+	///
+	/// When lowering for `trace`, instructions are not fused across ops and the
+	/// types of the stack after every op are kept.
+	pub fn lower(&self, trace: bool) -> Bytecode {
+		self.lower_with(trace, self.layout(), vec![])
+	}
+
+	/// Lowers the program into an already placed memory, starting with a stack
+	/// of `stack`
+	pub fn lower_with(&self, trace: bool, layout: Layout, stack: Vec<Type>) -> Bytecode {
+		use Instr as I;
+		use OpType::*;
+		use Type::*;
+
+		let Layout { data, regions, mem } = layout;
+		let jumps = self.resolve_jumps();
+		let mut out = Emitter {
+			code:    vec![],
+			origins: vec![],
+			firsts:  vec![],
+			ip:      0,
+			barrier: 0,
+		};
+		// Index of the first instruction of every op
+		let mut starts: Vec<usize> = vec![];
+		let mut types = stack;
+		let mut snapshots: Vec<Vec<Type>> = vec![];
+		let mut ptr_stores = HashSet::new();
+		let mut ptr_loads = HashSet::new();
+		let mut stacks: Vec<Vec<Type>> = vec![];
+		let mut hosts = vec![];
+
+		for (ip, Op { typ, .. }) in self.ops.iter().enumerate() {
+			starts.push(out.code.len());
+			out.ip = ip;
+			// Jumps land right after control flow ops
+			if trace || ip > 0 && self.ops[ip - 1].is_control_flow() {
+				out.barrier = out.code.len();
+			}
+			match typ {
+				| PushI(i) => {
+					out.emit(I::Push(*i as u64));
+					types.push(I64);
+				}
+				| PushB(b) => {
+					out.emit(I::Push(*b as u64));
+					types.push(Bool);
+				}
+				| PushF(f) => {
+					out.emit(I::Push(f.to_bits()));
+					types.push(F64);
+				}
+				| PushStr(lit) => {
+					let kind = RegionKind::Str(lit.clone());
+					let region = regions.iter().find(|r| r.kind == kind).unwrap();
+					out.emit(I::Push(region.start as u64));
+					types.push(Ptr);
+				}
+				| Dump(_) => {
+					out.emit(match types.pop().unwrap() {
+						| I64 | Ptr => I::DumpI,
+						| F64 => I::DumpF,
+						| Bool => I::DumpB,
+					})
+				}
+				| Add(..) | Sub(..) | Mul(..) | Div(..) => {
+					let (a, b) = (types.pop().unwrap(), types.pop().unwrap());
+					let float = match (a, b) {
+						| (F64, F64) => true,
+						| (F64, I64) => {
+							out.emit(I::ToF64(1));
+							true
+						}
+						| (I64, F64) => {
+							out.emit(I::ToF64(0));
+							true
+						}
+						| _ => false,
+					};
+					out.emit(match (typ, float) {
+						| (Add(..), false) if self.overflow_checks => I::AddTrap,
+						| (Sub(..), false) if self.overflow_checks => I::SubTrap,
+						| (Mul(..), false) if self.overflow_checks => I::MulTrap,
+						| (Add(..), false) => I::AddI,
+						| (Sub(..), false) => I::SubI,
+						| (Mul(..), false) => I::MulI,
+						| (Div(..), false) => I::DivI,
+						| (Add(..), true) => I::AddF,
+						| (Sub(..), true) => I::SubF,
+						| (Mul(..), true) => I::MulF,
+						| (_, true) => I::DivF,
+						| _ => unreachable!(),
+					});
+					let offset =
+						matches!(typ, Add(..) | Sub(..)) && (a == Ptr || b == Ptr);
+					types.push(if float {
+						F64
+					} else if offset {
+						Ptr
+					} else {
+						I64
+					});
+				}
+				| Increment(_) | Decrement(_) => {
+					let arg = types.pop().unwrap();
+					let float = arg == F64;
+					out.emit(match (typ, float) {
+						| (Increment(_), false) => I::IncI,
+						| (Increment(_), true) => I::IncF,
+						| (_, false) => I::DecI,
+						| (_, true) => I::DecF,
+					});
+					types.push(if float || arg == Ptr { arg } else { I64 });
+				}
+				| Mod(..) => {
+					types.truncate(types.len() - 2);
+					out.emit(I::Mod);
+					types.push(I64);
+				}
+				| DivMod(floor) => {
+					types.truncate(types.len() - 2);
+					out.emit(if *floor { I::DivModFloorI } else { I::DivModI });
+					types.extend([I64, I64]);
+				}
+				| IntArith(op, overflow) => {
+					types.truncate(types.len() - 2);
+					out.emit(match (overflow, op) {
+						| (Overflow::Wrapping, IntOp::Add) => I::AddI,
+						| (Overflow::Wrapping, IntOp::Sub) => I::SubI,
+						| (Overflow::Wrapping, IntOp::Mul) => I::MulI,
+						| (Overflow::Saturating, IntOp::Add) => I::AddSat,
+						| (Overflow::Saturating, IntOp::Sub) => I::SubSat,
+						| (Overflow::Saturating, IntOp::Mul) => I::MulSat,
+						| (Overflow::Checked, IntOp::Add) => I::AddChecked,
+						| (Overflow::Checked, IntOp::Sub) => I::SubChecked,
+						| (Overflow::Checked, IntOp::Mul) => I::MulChecked,
+					});
+					types.push(I64);
+					if *overflow == Overflow::Checked {
+						types.push(Bool);
+					}
+				}
+				| Drop(n) => {
+					types.truncate(types.len() - *n as usize);
+					out.emit(I::Drop(*n as u32));
+				}
+				| Swap => {
+					let len = types.len();
+					types.swap(len - 1, len - 2);
+					out.emit(I::Swap);
+				}
+				| Rot => {
+					let len = types.len();
+					types[len - 3..].rotate_left(1);
+					out.emit(I::Rot);
+				}
+				| Unrot => {
+					let len = types.len();
+					types[len - 3..].rotate_right(1);
+					out.emit(I::Unrot);
+				}
+				| Nip => {
+					let typ = types.pop().unwrap();
+					*types.last_mut().unwrap() = typ;
+					out.emit(I::Nip);
+				}
+				| Tuck => {
+					types.insert(types.len() - 2, *types.last().unwrap());
+					out.emit(I::Tuck);
+				}
+				| Swap2 => {
+					let len = types.len();
+					types[len - 4..].rotate_left(2);
+					out.emit(I::Swap2);
+				}
+				| Roll(n) => {
+					let len = types.len();
+					types[len - *n as usize - 1..].rotate_left(1);
+					out.emit(I::Roll(*n as u32));
+				}
+				| Over(n) => {
+					types.push(types[types.len() - *n as usize - 1]);
+					out.emit(I::Over(*n as u32));
+				}
+				| Dup(n) => {
+					types.extend_from_within(types.len() - *n as usize..);
+					out.emit(I::Dup(*n as u32));
+				}
+				| SetOver(n) => {
+					let typ = types.pop().unwrap();
+					let idx = types.len() - *n as usize;
+					types[idx] = typ;
+					out.emit(I::SetOver(*n as u32));
+				}
+				| If(_) | While(_) => (),
+				| Then(..) | Do(_) => {
+					out.emit(match types.pop().unwrap() {
+						| F64 => I::JmpZF(0),
+						| _ => I::JmpZ(0),
+					});
+					snapshots.push(types.clone());
+				}
+				| Else(_) => {
+					out.emit(I::Jmp(0));
+					// The else branch starts from the stack the condition left
+					std::mem::swap(&mut types, snapshots.last_mut().unwrap());
+				}
+				| End(_, while_) => {
+					if *while_ {
+						out.emit(I::Jmp(0));
+						// The loop is left right after the condition was popped
+						types = snapshots.pop().unwrap();
+					} else {
+						snapshots.pop();
+					}
+				}
+				| Eq(..) | Neq(..) | Lt(..) | Gt(..) | Lte(..) | Gte(..) => {
+					let float = match (types.pop().unwrap(), types.pop().unwrap()) {
+						| (F64, F64) => true,
+						| (_, F64) => {
+							out.emit(I::ToF64(0));
+							true
+						}
+						| (F64, _) => {
+							out.emit(I::ToF64(1));
+							true
+						}
+						| _ => false,
+					};
+					out.emit(match (typ, float) {
+						| (Eq(..), false) => I::EqI,
+						| (Neq(..), false) => I::NeqI,
+						| (Lt(..), false) => I::LtI,
+						| (Gt(..), false) => I::GtI,
+						| (Lte(..), false) => I::LteI,
+						| (Gte(..), false) => I::GteI,
+						| (Eq(..), true) => I::EqF,
+						| (Neq(..), true) => I::NeqF,
+						| (Lt(..), true) => I::LtF,
+						| (Gt(..), true) => I::GtF,
+						| (Lte(..), true) => I::LteF,
+						| (_, true) => I::GteF,
+						| _ => unreachable!(),
+					});
+					types.push(Bool);
+				}
+				| Syscall(syscode, argc) => {
+					types.truncate(types.len() - argc);
+					out.emit(I::Syscall(*syscode as u32, *argc as u32));
+					types.push(I64);
+				}
+				| Host(_, ins, outs) => {
+					types.truncate(types.len() - ins.len());
+					out.emit(I::Host(hosts.len() as u32));
+					hosts.push(ip);
+					types.extend(outs);
+				}
+				| Exit => {
+					types.pop();
+					out.emit(I::Exit);
+				}
+				| ToF64 => {
+					out.emit(I::ToF64(0));
+					*types.last_mut().unwrap() = F64;
+				}
+				| ToI64 => {
+					out.emit(I::ToI64);
+					*types.last_mut().unwrap() = I64;
+				}
+				| Round => out.emit(I::RoundF),
+				| Floor => out.emit(I::FloorF),
+				| Ceil => out.emit(I::CeilF),
+				| Sqrt => out.emit(I::SqrtF),
+				| Abs => out.emit(I::AbsF),
+				| Neg => out.emit(I::NegF),
+				| Exp => out.emit(I::ExpF),
+				| Log => out.emit(I::LogF),
+				| Sin => out.emit(I::SinF),
+				| Cos => out.emit(I::CosF),
+				| Fmod | Min | Max | Pow => {
+					types.pop();
+					out.emit(match typ {
+						| Fmod => I::FmodF,
+						| Min => I::MinF,
+						| Max => I::MaxF,
+						| _ => I::PowF,
+					});
+				}
+				| Argc | Argv => {
+					out.emit(I::ProgramArgs);
+					types.push(if let Argc = typ { I64 } else { Ptr });
+				}
+				| Load8 | Load16 | Load32 | Load64 => {
+					types.pop();
+					out.emit(match typ {
+						| Load8 => I::Load8,
+						| Load16 => I::Load16,
+						| Load32 => I::Load32,
+						| _ => I::Load64,
+					});
+					types.push(I64);
+				}
+				| Store8 | Store16 | Store32 | Store64 => {
+					if types.last() == Some(&Ptr) {
+						ptr_stores.insert(out.code.len());
+					}
+					types.truncate(types.len() - 2);
+					out.emit(match typ {
+						| Store8 => I::Store8,
+						| Store16 => I::Store16,
+						| Store32 => I::Store32,
+						| _ => I::Store64,
+					});
+				}
+				| Cast(typ) => {
+					// Casts reinterpret the bits, only booleans are normalized
+					if *typ == Bool {
+						out.emit(I::ToBool);
+					}
+					if *typ == Ptr && matches!(self.ops[ip - 1].typ, Load64) {
+						ptr_loads.insert(out.code.len() - 1);
+					}
+					*types.last_mut().unwrap() = *typ;
+				}
+				| ShiftR | ShiftRA | ShiftL | RotL | RotR => {
+					types.pop();
+					out.emit(match typ {
+						| ShiftR => I::ShiftR,
+						| ShiftRA => I::ShiftRA,
+						| ShiftL => I::ShiftL,
+						| RotL => I::RotL,
+						| _ => I::RotR,
+					});
+					if *types.last().unwrap() == Bool {
+						out.emit(I::ToBool);
+					}
+				}
+				| BitAnd | BitOr | Xor => {
+					types.truncate(types.len() - 2);
+					out.emit(match typ {
+						| BitAnd => I::BitAnd,
+						| BitOr => I::BitOr,
+						| _ => I::Xor,
+					});
+					types.push(I64);
+				}
+				| And | Or => {
+					for depth in 0..2 {
+						if types.pop().unwrap() == F64 {
+							out.emit(I::FToBool(depth));
+						}
+					}
+					out.emit(if let And = typ { I::And } else { I::Or });
+					types.push(Bool);
+				}
+				| Popcount | Clz | Ctz => {
+					out.emit(match typ {
+						| Popcount => I::Popcount,
+						| Clz => I::Clz,
+						| _ => I::Ctz,
+					});
+					*types.last_mut().unwrap() = I64;
+				}
+				| Not => {
+					match types.last().unwrap() {
+						| I64 | Ptr => out.emit(I::Not),
+						| Bool => out.emit(I::NotB),
+						| F64 => (),
+					}
+				}
+				| Mem(name) => {
+					let ptr = match name {
+						| Some(name) => {
+							let kind = RegionKind::Decla(name.clone());
+							regions.iter().find(|r| r.kind == kind).unwrap().start
+						}
+						| None => mem,
+					};
+					out.emit(I::Push(ptr as u64));
+					types.push(Ptr);
+				}
+				| Asm(..) => unreachable!("Replaced by its fallback in simulation mode"),
+			}
+			if trace {
+				stacks.push(match typ {
+					// Leaving a block keeps the stack of the last op of the block
+					| Else(_) | End(..) => stacks[ip - 1].clone(),
+					| _ => types.clone(),
+				});
+			}
+		}
+		starts.push(out.code.len());
+		let Emitter { mut code, origins, firsts, .. } = out;
+
+		// Jumps are the last instruction of their op and continue at the op
+		// following their target
+		for (ip, Op { typ, .. }) in self.ops.iter().enumerate() {
+			if let Then(..) | Else(_) | Do(_) | End(_, true) = typ {
+				let dest = starts[jumps[ip] + 1] as u32;
+				match &mut code[starts[ip + 1] - 1] {
+					| I::Jmp(to)
+					| I::JmpZ(to)
+					| I::JmpZF(to)
+					| I::JmpNotEqImm(_, to)
+					| I::JmpNotNeqImm(_, to)
+					| I::JmpNotLtImm(_, to)
+					| I::JmpNotGtImm(_, to)
+					| I::JmpNotLteImm(_, to)
+					| I::JmpNotGteImm(_, to) => *to = dest,
+					| _ => unreachable!(),
+				}
+			}
+		}
+
+		Bytecode {
+			code,
+			origins,
+			firsts,
+			data,
+			regions,
+			mem,
+			ptr_stores,
+			ptr_loads,
+			stacks,
+			hosts,
+		}
+	}
+}
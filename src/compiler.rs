@@ -9,7 +9,7 @@ use std::{
 
 use crate::{
 	annotation::Type,
-	parser::{Op, OpType, Program},
+	parser::{IntOp, Op, OpType, Overflow, Program},
 	Cli,
 };
 //#endregion
@@ -19,6 +19,12 @@ const ASM_HEADER: &str = r#"extern dump_i
 extern dump_f
 extern dump_f_rounded
 extern i64tof64
+extern math_fmod
+extern math_exp
+extern math_log
+extern math_sin
+extern math_cos
+extern math_pow
 
 global write
 write:
@@ -39,6 +45,15 @@ dump_b:
 	push 	rbp
 	ret
 
+; Prints the message of length rdx at rsi and exits with 1, like a runtime error
+; of the simulation
+trap:
+	mov 	rdi, 2
+	call	write
+	mov 	rdi, 1
+	mov 	rax, 60
+	syscall
+
 test_xmm0:
 	add 	rsp, 8
 	xor 	r15, r15
@@ -183,8 +198,11 @@ impl Program {
 		);
 		buf.write_all(ASM_HEADER.as_bytes())?;
 		let mut labels = HashMap::<String, i64>::new();
+		let mut traps = vec![];
 		for op in self.ops.iter() {
-			buf.write_all(op.to_asm(cli, &mut labels, &self.strings).as_bytes())?;
+			buf.write_all(
+				op.to_asm(cli, &mut labels, &mut traps, &self.strings).as_bytes(),
+			)?;
 		}
 		buf.write_all(ASM_EXIT_DATA.as_bytes())?;
 		for (idx, lit) in self.strings.iter().enumerate() {
@@ -192,6 +210,24 @@ impl Program {
 				format!("STR_LIT_{}: db `{}`, 0\n", idx, escape_string(lit)).as_bytes(),
 			)?;
 		}
+		for (idx, msg) in traps.iter().enumerate() {
+			buf.write_all(
+				format!("TRAP_MSG_{idx}: db `{}`\n", escape_string(msg)).as_bytes(),
+			)?;
+		}
+		if !traps.is_empty() {
+			buf.write_all(b"\nsection .text\n")?;
+		}
+		for (idx, msg) in traps.iter().enumerate() {
+			buf.write_all(
+				format!(
+					"TRAP_{idx}:\n\tmov \trsi, TRAP_MSG_{idx}\n\tmov \trdx, {}\n\tjmp \
+					 \ttrap\n",
+					msg.len()
+				)
+				.as_bytes(),
+			)?;
+		}
 		buf.write_all(ASM_BSS.as_bytes())?;
 		for name in self.memory_regions_order.iter() {
 			let size = self.memory_regions.get(name).unwrap();
@@ -222,10 +258,65 @@ impl Program {
 }
 
 impl Op {
+	/// With `--overflow-checks`, jumps to a trap reporting the op if the integer
+	/// operation just done overflowed
+	fn overflow_check(
+		&self,
+		types: (Type, Type),
+		cli: &Cli<String>,
+		traps: &mut Vec<String>,
+	) -> String {
+		if !cli.overflow_checks || types.0 == Type::F64 || types.1 == Type::F64 {
+			return String::new();
+		}
+		format!("\tjo  \tTRAP_{}\n", self.trap("Integer overflow", traps))
+	}
+
+	/// Adds a trap printing `what` happened in the op, the same as the simulator,
+	/// and returns its index
+	fn trap(&self, what: &str, traps: &mut Vec<String>) -> usize {
+		traps.push(format!(
+			"ERROR: {}: {what} in {}{}\n",
+			self.annot.get_pos(),
+			self.typ,
+			self.annot.expansion_trace()
+		));
+		traps.len() - 1
+	}
+
+	/// Pops the divisor and the dividend and leaves the quotient in `rax` and the
+	/// remainder in `rdx`, truncated like `idiv` or floored.
+	///
+	/// A zero divisor traps, `-1` is done apart as `idiv` faults on `MIN / -1`
+	fn int_division(
+		&self,
+		floor: bool,
+		labels: &mut HashMap<String, i64>,
+		traps: &mut Vec<String>,
+	) -> String {
+		labels.insert("DIV_L".into(), labels.get("DIV_L").unwrap_or(&-1) + 1);
+		let l = labels.get("DIV_L").unwrap();
+		let trap = self.trap("Division by zero", traps);
+		let floor = if floor {
+			format!(
+				"\ttest\trdx, rdx\n\tjz  \tDIV_END_{l}\n\tmov \trcx, rdx\n\txor \trcx, \
+				 rdi\n\tjns \tDIV_END_{l}\n\tdec \trax\n\tadd \trdx, rdi\n"
+			)
+		} else {
+			String::new()
+		};
+		format!(
+			"\tpop \trdi\n\tpop \trax\n\ttest\trdi, rdi\n\tjz  \tTRAP_{trap}\n\tcmp \
+			 \trdi, -1\n\tje  \tDIV_NEG_{l}\n\tcqo\n\tidiv\trdi\n{floor}\tjmp \
+			 \tDIV_END_{l}\nDIV_NEG_{l}:\n\tneg \trax\n\txor \tedx, edx\nDIV_END_{l}:\n"
+		)
+	}
+
 	fn to_asm(
 		&self,
 		cli: &Cli<String>,
 		labels: &mut HashMap<String, i64>,
+		traps: &mut Vec<String>,
 		strings: &[String],
 	) -> String {
 		use OpType::*;
@@ -273,7 +364,7 @@ impl Op {
 							 [rsp]\n\tmovq\t[rsp+8], xmm0\n\tadd \trsp, 8\n"
 						}
 						| (..) => "\tpop \trdi\n\tadd \t[rsp], rdi\n",
-					}
+					} + self.overflow_check((a, b), cli, traps).as_str()
 			}
 			| Sub(a, b) => {
 				";SUB\n".to_string()
@@ -291,7 +382,7 @@ impl Op {
 							 [rsp]\n\tmovq\t[rsp+8], xmm0\n\tadd \trsp, 8\n"
 						}
 						| (..) => "\tpop \trdi\n\tsub \t[rsp], rdi\n",
-					}
+					} + self.overflow_check((a, b), cli, traps).as_str()
 			}
 			| Mul(a, b) => {
 				";MUL\n".to_string()
@@ -311,10 +402,15 @@ impl Op {
 						| (..) => {
 							"\tpop \trdi\n\tpop \trax\n\timul \trax, rdi\n\tpush\t rax\n"
 						}
-					}
+					} + self.overflow_check((a, b), cli, traps).as_str()
+			}
+			| Div(a, b) if a != Type::F64 && b != Type::F64 => {
+				";DIV\n".to_string()
+					+ &self.int_division(false, labels, traps)
+					+ "\tpush\trax\n"
 			}
 			| Div(a, b) => {
-				";ADD\n".to_string()
+				";DIV\n".to_string()
 					+ match (a, b) {
 						| (Type::I64, Type::F64) => {
 							"\tpop \trdi\n\tcall\ti64tof64\n\tmovq\txmm1, \
@@ -328,14 +424,56 @@ impl Op {
 							"\tmovq\txmm0, [rsp+8]\n\tdivsd\txmm0, \
 							 [rsp]\n\tmovq\t[rsp+8], xmm0\n\tadd \trsp, 8\n"
 						}
-						| (..) => {
-							"\tpop \trdi\n\tpop \trax\n\tcqo\n\tidiv \trdi\n\tpush\trax\n"
-						}
+						| (..) => unreachable!(),
 					}
 			}
 			| Mod(..) => {
-				";MOD\n\tpop \trdi\n\tpop \trax\n\tcqo\n\tidiv \trdi\n\tpush\trdx\n"
-					.into()
+				";MOD\n".to_string()
+					+ &self.int_division(false, labels, traps)
+					+ "\tpush\trdx\n"
+			}
+			| DivMod(floor) => {
+				format!(
+					";{}\n{}\tpush\trax\n\tpush\trdx\n",
+					self.typ,
+					self.int_division(floor, labels, traps)
+				)
+			}
+			| IntArith(op, overflow) => {
+				let load = "mov \trax, qword[rsp]\n\t";
+				let instr = match op {
+					| IntOp::Add => "add \trax, rdi",
+					| IntOp::Sub => "sub \trax, rdi",
+					| IntOp::Mul => "imul\trax, rdi",
+				};
+				match overflow {
+					| Overflow::Wrapping => {
+						format!(
+							";{}\n\tpop \trdi\n\t{load}{instr}\n\tmov \tqword[rsp], \
+							 rax\n",
+							self.typ
+						)
+					}
+					// The bound is picked from the sign the exact result would have: the
+					// one of the first operand for `+` and `-`, of both for `*`
+					| Overflow::Saturating => {
+						format!(
+							";{}\n\tpop \trdi\n\t{load}mov \trdx, rax\n\t{}shr \trdx, \
+							 63\n\tmov \trcx, 0x7FFFFFFFFFFFFFFF\n\tadd \trdx, \
+							 rcx\n\t{instr}\n\tcmovo\trax, rdx\n\tmov \tqword[rsp], \
+							 rax\n",
+							self.typ,
+							if let IntOp::Mul = op { "xor \trdx, rdi\n\t" } else { "" }
+						)
+					}
+					| Overflow::Checked => {
+						format!(
+							";{}\n\tpop \trdi\n\t{load}{instr}\n\tmov \tqword[rsp], \
+							 rax\n\tseto\tcl\n\tmovzx\tecx, cl\n\tpush\trcx\n",
+							self.typ
+						)
+					}
+				}
 			}
 			| Increment(typ) => {
 				";INC\n".to_string()
@@ -361,6 +499,55 @@ impl Op {
 			| Swap => {
 				";SWAP\n\tpop \trax\n\tpop \trbx\n\tpush\trax\n\tpush \trbx\n".to_string()
 			}
+			| Rot => {
+				";ROT\n\tmov \trax, qword[rsp+16]\n\tmov \trdi, qword[rsp+8]\n\tmov \
+				 \trcx, qword[rsp]\n\tmov \tqword[rsp+16], rdi\n\tmov \tqword[rsp+8], \
+				 rcx\n\tmov \tqword[rsp], rax\n"
+					.into()
+			}
+			| Unrot => {
+				";UNROT\n\tmov \trax, qword[rsp+16]\n\tmov \trdi, qword[rsp+8]\n\tmov \
+				 \trcx, qword[rsp]\n\tmov \tqword[rsp+16], rcx\n\tmov \tqword[rsp+8], \
+				 rax\n\tmov \tqword[rsp], rdi\n"
+					.into()
+			}
+			| Nip => ";NIP\n\tpop \trax\n\tmov \tqword[rsp], rax\n".into(),
+			| Tuck => {
+				";TUCK\n\tmov \trax, qword[rsp]\n\tmov \trdi, qword[rsp+8]\n\tmov \
+				 \tqword[rsp+8], rax\n\tmov \tqword[rsp], rdi\n\tpush\trax\n"
+					.into()
+			}
+			| Swap2 => {
+				";2SWAP\n\tmovdqu\txmm0, [rsp]\n\tmovdqu\txmm1, \
+				 [rsp+16]\n\tmovdqu\t[rsp], xmm1\n\tmovdqu\t[rsp+16], xmm0\n"
+					.into()
+			}
+			// Every value above the rolled one goes down a slot, from the deepest
+			| Roll(0) => ";ROLL0\n".into(),
+			| Roll(n) if n < 6 => {
+				(0..n).rev().fold(
+					format!(";ROLL{n}\n\tmov \trax, qword[rsp+{}]\n", 8 * n),
+					|acc, i| {
+						acc + format!(
+							"\tmov \trdi, qword[rsp+{}]\n\tmov \tqword[rsp+{}], rdi\n",
+							8 * i,
+							8 * (i + 1)
+						)
+						.as_str()
+					},
+				) + "\tmov \tqword[rsp], rax\n"
+			}
+			| Roll(n) => {
+				labels.insert("ROLL_L".into(), labels.get("ROLL_L").unwrap_or(&-1) + 1);
+				let l = labels.get("ROLL_L").unwrap();
+				format!(
+					";ROLL{n}\n\tmov \trax, qword[rsp+{}]\n\tmov \trcx, \
+					 {n}\nROLL_L{l}:\n\tmov \trdi, qword[rsp+8*rcx-8]\n\tmov \
+					 \tqword[rsp+8*rcx], rdi\n\tdec \trcx\n\tjnz \tROLL_L{l}\n\tmov \
+					 \tqword[rsp], rax\n",
+					8 * n
+				)
+			}
 			| Over(n) => format!(";OVER{n}\n\tpush\tqword[rsp+{}]\n", 8 * n),
 			| Dup(n) => {
 				if n < 6 {
@@ -543,6 +730,53 @@ impl Op {
 					format!("{acc}pop \t{}\n\t", SYSCALL_REGS[idx])
 				}) + format!("mov \trax, {syscode}\n\tsyscall\n\tpush\trax\n").as_str()
 			}
+			| ToF64 => {
+				";ToF64\n\tcvtsi2sd\txmm0, qword[rsp]\n\tmovq\tqword[rsp], xmm0\n".into()
+			}
+			| ToI64 => {
+				";ToI64\n\tcvttsd2si\trax, qword[rsp]\n\tmov \tqword[rsp], rax\n".into()
+			}
+			// Precision exceptions are suppressed (bit 3 of the rounding mode)
+			| Round => {
+				";Round\n\troundsd\txmm0, qword[rsp], 8\n\tmovq\tqword[rsp], xmm0\n"
+					.into()
+			}
+			| Floor => {
+				";Floor\n\troundsd\txmm0, qword[rsp], 9\n\tmovq\tqword[rsp], xmm0\n"
+					.into()
+			}
+			| Ceil => {
+				";Ceil\n\troundsd\txmm0, qword[rsp], 10\n\tmovq\tqword[rsp], xmm0\n"
+					.into()
+			}
+			| Sqrt => {
+				";Sqrt\n\tsqrtsd\txmm0, qword[rsp]\n\tmovq\tqword[rsp], xmm0\n".into()
+			}
+			| Abs => ";Abs\n\tbtr \tqword[rsp], 63\n".into(),
+			| Neg => ";Neg\n\tbtc \tqword[rsp], 63\n".into(),
+			| op @ (Min | Max) => {
+				format!(
+					";{op}\n\tmovq\txmm1, qword[rsp]\n\tadd \trsp, 8\n\tmovq\txmm0, \
+					 qword[rsp]\n\t{}\txmm0, xmm1\n\tmovq\tqword[rsp], xmm0\n",
+					if let Min = op { "minsd" } else { "maxsd" }
+				)
+			}
+			// No instruction for them, they are computed by the runtime
+			| op @ (Exp | Log | Sin | Cos) => {
+				format!(
+					";{op}\n\tmovq\txmm0, \
+					 qword[rsp]\n\tcall\tmath_{}\n\tmovq\tqword[rsp], xmm0\n",
+					op.to_string().to_lowercase()
+				)
+			}
+			| op @ (Fmod | Pow) => {
+				format!(
+					";{op}\n\tmovq\txmm1, qword[rsp]\n\tadd \trsp, 8\n\tmovq\txmm0, \
+					 qword[rsp]\n\tcall\tmath_{}\n\tmovq\tqword[rsp], xmm0\n",
+					op.to_string().to_lowercase()
+				)
+			}
+			| Exit => ";Exit\n\tpop \trdi\n\tmov \trax, 60\n\tsyscall\n".into(),
 			| Argc => ";Argc\n\tpush\tqword[argc]\n".into(),
 			| Argv => ";Argv\n\tpush\tqword[argv]\n".into(),
 			| Load8 => {
@@ -571,8 +805,38 @@ impl Op {
 				";Cast(Bool)\n\tcmp \tqword[rsp], 0\n\tsetne\t[rsp]\n".into()
 			}
 			| Cast(_) => "".into(),
-			| ShiftR => ";ShiftR\n\tpop \trcx\n\tshr \tqword[rsp], cl\n".into(),
-			| ShiftL => ";ShiftL\n\tpop \trcx\n\tshl \tqword[rsp], cl\n".into(),
+			// The count is masked to 6 bits, larger ones shift everything out
+			| op @ (ShiftR | ShiftL) => {
+				format!(
+					";{op}\n\tpop \trcx\n\txor \teax, eax\n\tmov \trdx, \
+					 qword[rsp]\n\t{} \trdx, cl\n\tcmp \trcx, 64\n\tcmovae\trdx, \
+					 rax\n\tmov \tqword[rsp], rdx\n",
+					if let ShiftR = op { "shr" } else { "shl" }
+				)
+			}
+			| ShiftRA => {
+				";ShiftRA\n\tpop \trcx\n\tmov \teax, 63\n\tcmp \trcx, rax\n\tcmova\trcx, \
+				 rax\n\tsar \tqword[rsp], cl\n"
+					.into()
+			}
+			| RotL => ";RotL\n\tpop \trcx\n\trol \tqword[rsp], cl\n".into(),
+			| RotR => ";RotR\n\tpop \trcx\n\tror \tqword[rsp], cl\n".into(),
+			| Xor => ";Xor\n\tpop \trax\n\txor \tqword[rsp], rax\n".into(),
+			| Popcount => {
+				";Popcount\n\tpopcnt\trax, qword[rsp]\n\tmov \tqword[rsp], rax\n".into()
+			}
+			// `bsr` and `bsf` leave the destination undefined for 0, `lzcnt` and
+			// `tzcnt` would need BMI
+			| Clz => {
+				";Clz\n\tmov \tecx, 127\n\tbsr \trax, qword[rsp]\n\tcmovz\trax, \
+				 rcx\n\txor \trax, 63\n\tmov \tqword[rsp], rax\n"
+					.into()
+			}
+			| Ctz => {
+				";Ctz\n\tmov \tecx, 64\n\tbsf \trax, qword[rsp]\n\tcmovz\trax, \
+				 rcx\n\tmov \tqword[rsp], rax\n"
+					.into()
+			}
 			| BitAnd => ";BitAnd\n\tpop \trax\n\tand \tqword[rsp], rax\n".into(),
 			| BitOr => ";BitOr\n\tpop \trax\n\tor \tqword[rsp], rax\n".into(),
 			| And => {
@@ -599,7 +863,8 @@ impl Op {
 					(size - 1) * 8
 				)
 			}
-			| Nop => unreachable!(),
+			| Asm(_, _, code) => format!(";ASM\n{code}\n"),
+			| Host(..) => unreachable!("Rejected by the parser in compilation mode"),
 		}
 	}
 }
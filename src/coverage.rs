@@ -0,0 +1,156 @@
+//#region Imports
+use std::{collections::BTreeMap, fmt::Write};
+
+use crate::{
+	bytecode::executed_ops,
+	lexer::{Token, TokenType},
+	parser::{OpType, Program},
+};
+//#endregion
+
+/// Counts the executed instructions and the taken jumps of a simulation
+pub struct Counters {
+	hits:  Vec<u64>,
+	taken: Vec<u64>,
+	prev:  usize,
+}
+
+impl Counters {
+	pub fn new(code_len: usize) -> Self {
+		Counters { hits: vec![0; code_len], taken: vec![0; code_len], prev: usize::MAX }
+	}
+
+	/// Counts the instruction `ip`, about to be executed
+	pub fn hit(&mut self, ip: usize) {
+		if ip != self.prev.wrapping_add(1) {
+			self.taken[self.prev] += 1;
+		}
+		if let Some(hits) = self.hits.get_mut(ip) {
+			*hits += 1;
+		}
+		self.prev = ip;
+	}
+}
+
+#[derive(Clone, Default)]
+struct FileCoverage {
+	/// Executions of every line
+	lines:    BTreeMap<usize, u64>,
+	/// Executions of the true and false side of every `then` and `do`, keyed by
+	/// their line and column, `None` if the condition never ran
+	branches: BTreeMap<(usize, usize), Option<[u64; 2]>>,
+}
+
+/// Line and branch coverage of one or more simulations (`--coverage`)
+#[derive(Clone, Default)]
+pub struct Coverage {
+	files: BTreeMap<String, FileCoverage>,
+}
+
+impl Coverage {
+	fn file(&mut self, path: &str) -> &mut FileCoverage {
+		self.files.entry(path.to_string()).or_default()
+	}
+
+	/// Coverage of a finished simulation of `program`.
+	///
+	/// A line counts as executed as many times as its most executed op, ops
+	/// expanded from a macro also count for the line the macro is used at. The
+	/// lines of macros that were never expanded are reported as never executed.
+	pub fn new(
+		program: &Program,
+		counters: Counters,
+		origins: &[usize],
+		firsts: &[usize],
+	) -> Self {
+		let mut coverage = Coverage::default();
+		for Token { typ, annot } in program.macros.values().flat_map(|m| m.body.iter()) {
+			let pos = annot.get_pos();
+			// Defines (`-D`, `SIM`, ...) have no source line
+			if pos.line() == 0 {
+				continue;
+			}
+			let file = coverage.file(pos.file_path());
+			file.lines.entry(pos.line()).or_insert(0);
+			if let TokenType::Then | TokenType::Do = typ {
+				file.branches.entry((pos.line(), pos.col())).or_insert(None);
+			}
+		}
+
+		let Counters { hits, taken, .. } = counters;
+		let executed = executed_ops(&hits, origins, firsts, program.ops.len());
+		// Control flow ops end with their jump
+		let mut jumps = vec![0; program.ops.len()];
+		for (ip, op) in origins.iter().enumerate() {
+			jumps[*op] = ip;
+		}
+
+		for (ip, op) in program.ops.iter().enumerate() {
+			let expansions = op.annot.get_expansions().iter().map(|e| &e.pos);
+			for pos in std::iter::once(op.annot.get_pos()).chain(expansions) {
+				let line =
+					coverage.file(pos.file_path()).lines.entry(pos.line()).or_insert(0);
+				*line = executed[ip].max(*line);
+			}
+			if let OpType::Then(..) | OpType::Do(_) = op.typ {
+				let pos = op.annot.get_pos();
+				let file = coverage.file(pos.file_path());
+				let branch = file.branches.entry((pos.line(), pos.col())).or_insert(None);
+				let (runs, taken) = (hits[jumps[ip]], taken[jumps[ip]]);
+				if runs > 0 {
+					let [true_, false_] = branch.get_or_insert([0, 0]);
+					*true_ += runs - taken;
+					*false_ += taken;
+				}
+			}
+		}
+		coverage
+	}
+
+	/// Adds the counts of `other` to this coverage
+	pub fn merge(&mut self, other: &Coverage) {
+		for (path, other) in other.files.iter() {
+			let file = self.file(path);
+			for (line, count) in other.lines.iter() {
+				*file.lines.entry(*line).or_insert(0) += count;
+			}
+			for (key, counts) in other.branches.iter() {
+				let branch = file.branches.entry(*key).or_insert(None);
+				if let Some([true_, false_]) = counts {
+					let [t, f] = branch.get_or_insert([0, 0]);
+					*t += true_;
+					*f += false_;
+				}
+			}
+		}
+	}
+
+	/// lcov tracefile, every `then` and `do` is a block of two branches (true
+	/// then false) numbered by its column
+	pub fn to_lcov(&self) -> String {
+		let mut lcov = String::new();
+		for (path, FileCoverage { lines, branches }) in self.files.iter() {
+			writeln!(lcov, "TN:\nSF:{path}").unwrap();
+			let mut hit = 0;
+			for ((line, col), counts) in branches.iter() {
+				for branch in 0..2 {
+					let taken = match counts {
+						| Some(counts) => {
+							hit += (counts[branch] > 0) as usize;
+							counts[branch].to_string()
+						}
+						| None => "-".to_string(),
+					};
+					writeln!(lcov, "BRDA:{line},{col},{branch},{taken}").unwrap();
+				}
+			}
+			writeln!(lcov, "BRF:{}\nBRH:{hit}", branches.len() * 2).unwrap();
+			for (line, count) in lines.iter() {
+				writeln!(lcov, "DA:{line},{count}").unwrap();
+			}
+			let hit = lines.values().filter(|count| **count > 0).count();
+			writeln!(lcov, "LF:{}\nLH:{hit}\nend_of_record", lines.len()).unwrap();
+		}
+		lcov
+	}
+}
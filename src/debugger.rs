@@ -0,0 +1,586 @@
+//#region Imports
+use std::{
+	cell::Cell,
+	io::{stdin, stdout, BufWriter, Write},
+	iter::from_fn,
+};
+
+use crate::{
+	bytecode::RegionKind,
+	parser::Program,
+	replay::Replay,
+	simulator::{output_failed, Machine, RuntimeError, Simulator, Undo},
+	syscalls::Io,
+	tracer::TraceFilter,
+	Cli,
+};
+//#endregion
+
+/// Steps between two checkpoints, going back to a step re-executes at most this
+/// many instructions
+const CHECKPOINT_INTERVAL: u64 = 1 << 12;
+
+/// Checkpoints kept, the oldest ones are forgotten so that the debugger goes back
+/// at most about a million steps
+const MAX_CHECKPOINTS: usize = 1 << 8;
+
+/// Writes kept in the undo log, the steps before the oldest checkpoint whose
+/// writes fit are forgotten
+const MAX_WRITES: usize = 1 << 20;
+
+const HELP: &str = "\
+step, s [n]                      Execute the next n ops (default: 1)
+reverse-step, rs [n]             Go back n ops (default: 1)
+continue, c                      Run until a breakpoint or the end of the program
+reverse-continue, rc             Go back to the previous breakpoint hit
+break, b [[file:]first[-last]]   Break on these lines, or list the breakpoints
+delete, d [n]                    Delete the breakpoint n, or every breakpoint
+reverse-watch, rw <addr|decla>   Go back to the last write to this address
+stack                            Print the stack, typed between two ops
+mem <addr|decla> [len]           Print len bytes of the memory (default: 8)
+where, w                         Print the next op
+quit, q                          Stop debugging";
+
+/// Standard stream of the debugged program, silenced while steps the user
+/// already saw are executed again
+struct Muted<'a, W> {
+	out:   W,
+	muted: &'a Cell<bool>,
+}
+
+impl<W: Write> Write for Muted<'_, W> {
+	fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+		if self.muted.get() {
+			return Ok(buf.len());
+		}
+		self.out.write(buf)
+	}
+
+	fn flush(&mut self) -> std::io::Result<()> { self.out.flush() }
+}
+
+/// Failure to print what a command shows
+fn shown(e: std::io::Error) -> RuntimeError {
+	RuntimeError::Io(format!("Failed to write the output of the debugger: {e}"))
+}
+
+/// State of the machine at a step, the memory is restored with the undo log
+struct Checkpoint {
+	step:     u64,
+	ip:       usize,
+	prev:     usize,
+	stack:    Vec<u64>,
+	/// Syscalls done before the step
+	syscalls: usize,
+}
+
+enum State {
+	Running,
+	Exited(i32),
+	/// Stopped right before the instruction raising the error
+	Faulted(RuntimeError),
+}
+
+/// Simulation going forwards one instruction at a time, and backwards by
+/// restoring the last checkpoint before the step and executing again from there.
+/// The syscalls done are replayed, so that the program sees the same inputs.
+struct Debugger<'a> {
+	program:     &'a Program,
+	cli:         &'a Cli<String>,
+	m:           Machine<'a>,
+	muted:       &'a Cell<bool>,
+	checkpoints: Vec<Checkpoint>,
+	breakpoints: Vec<TraceFilter>,
+	/// Where the commands print
+	out:         &'a mut dyn Write,
+	/// Last executed instruction, `usize::MAX` at the start
+	prev:        usize,
+	state:       State,
+}
+
+impl Debugger<'_> {
+	fn running(&self) -> bool { matches!(self.state, State::Running) }
+
+	/// Whether the next instruction is the first of its op
+	fn boundary(&self) -> bool {
+		let (ip, origins) = (self.m.ip, &self.m.origins);
+		ip == 0 || ip >= origins.len() || origins[ip - 1] != origins[ip]
+	}
+
+	/// Whether the next op enters the lines of a breakpoint
+	fn hit(&self) -> bool {
+		let (ip, prev) = (self.m.ip, self.prev);
+		if ip >= self.m.code.len() || !self.boundary() {
+			return false;
+		}
+		let op = |ip: usize| &self.program.ops[self.m.origins[ip]];
+		self.breakpoints.iter().any(|breakpoint| {
+			breakpoint.keeps(op(ip))
+				&& !(prev < self.m.code.len()
+					&& ip == prev + 1
+					&& breakpoint.keeps(op(prev)))
+		})
+	}
+
+	fn checkpoint(&mut self) {
+		let step = self.m.steps;
+		if !step.is_multiple_of(CHECKPOINT_INTERVAL)
+			|| self.checkpoints.last().is_some_and(|checkpoint| checkpoint.step >= step)
+		{
+			return;
+		}
+		let syscalls = &self.m.syscalls;
+		let history = syscalls.history.as_ref().unwrap();
+		self.checkpoints.push(Checkpoint {
+			step,
+			ip: self.m.ip,
+			prev: self.prev,
+			stack: self.m.stack.slots[..self.m.stack.sp].to_vec(),
+			syscalls: history.len() - syscalls.replay.as_ref().map_or(0, Replay::left),
+		});
+		self.forget();
+	}
+
+	/// Drops the oldest checkpoints and their writes past the limits, the last
+	/// checkpoint is always kept
+	fn forget(&mut self) {
+		let undo = self.m.undo.as_mut().unwrap();
+		let written = |step: u64| undo.partition_point(|write| write.step < step);
+		let mut drop = self.checkpoints.len().saturating_sub(MAX_CHECKPOINTS);
+		while drop + 1 < self.checkpoints.len()
+			&& undo.len() - written(self.checkpoints[drop].step) > MAX_WRITES
+		{
+			drop += 1;
+		}
+		if drop > 0 {
+			self.checkpoints.drain(..drop);
+			undo.drain(..written(self.checkpoints[0].step));
+		}
+	}
+
+	/// Oldest step the debugger can go back to
+	fn oldest(&self) -> u64 { self.checkpoints[0].step }
+
+	/// Syscalls are done again once the ones already done are replayed
+	fn live(&mut self) {
+		let syscalls = &mut self.m.syscalls;
+		if syscalls.replay.as_ref().is_some_and(|replay| replay.left() == 0) {
+			syscalls.replay = None;
+		}
+	}
+
+	fn mute(&mut self, muted: bool) -> Result<(), RuntimeError> {
+		self.m.io.stdout.flush().map_err(output_failed)?;
+		self.muted.set(muted);
+		Ok(())
+	}
+
+	/// Executes the next instruction
+	fn step(&mut self) -> Result<(), RuntimeError> {
+		if !self.running() {
+			return Ok(());
+		}
+		self.checkpoint();
+		let (ip, step) = (self.m.ip, self.m.steps);
+		self.m.pause_at = step + 1;
+		match self.program.run::<true>(&mut self.m, self.cli) {
+			| Ok(None) => self.prev = ip,
+			| Ok(Some(code)) => {
+				self.prev = ip;
+				self.state = State::Exited(code);
+			}
+			| Err(e) => {
+				// The instruction may have started to change the stack
+				self.goto(step)?;
+				self.state = State::Faulted(e);
+			}
+		}
+		self.live();
+		Ok(())
+	}
+
+	/// Executes the next op
+	fn step_op(&mut self) -> Result<(), RuntimeError> {
+		self.step()?;
+		while self.running() && !self.boundary() {
+			self.step()?
+		}
+		Ok(())
+	}
+
+	/// Goes back to the step, at most the current one and at least the oldest
+	fn goto(&mut self, step: u64) -> Result<(), RuntimeError> {
+		let step = step.max(self.oldest());
+		let idx = self.checkpoints.partition_point(|checkpoint| checkpoint.step <= step);
+		let Checkpoint { step: from, ip, prev, stack, syscalls } =
+			&self.checkpoints[idx - 1];
+		let undo = self.m.undo.as_mut().unwrap();
+		while undo.last().is_some_and(|write| write.step >= *from) {
+			let Undo { addr, old, .. } = undo.pop().unwrap();
+			self.m.memory[addr..addr + old.len()].copy_from_slice(&old);
+		}
+		self.m.stack.slots[..stack.len()].copy_from_slice(stack);
+		self.m.stack.sp = stack.len();
+		self.m.ip = *ip;
+		self.m.steps = *from;
+		self.prev = *prev;
+		let history = self.m.syscalls.history.clone().unwrap();
+		self.m.syscalls.replay = Some(Replay::new(history, *syscalls));
+		self.state = State::Running;
+		self.live();
+
+		self.mute(true)?;
+		while self.m.steps < step {
+			self.step()?
+		}
+		self.mute(false)
+	}
+
+	fn reverse_step(&mut self) -> Result<(), RuntimeError> {
+		while self.m.steps > self.oldest() {
+			self.goto(self.m.steps - 1)?;
+			if self.boundary() {
+				break;
+			}
+		}
+		Ok(())
+	}
+
+	/// Goes back to the last breakpoint hit, returns false if there is none
+	fn reverse_continue(&mut self) -> Result<bool, RuntimeError> {
+		let mut until = self.m.steps;
+		while until > self.oldest() {
+			let idx =
+				self.checkpoints.partition_point(|checkpoint| checkpoint.step < until);
+			let from = self.checkpoints[idx - 1].step;
+			self.goto(from)?;
+			let mut last = None;
+			self.mute(true)?;
+			while self.m.steps < until {
+				if self.hit() {
+					last = Some(self.m.steps);
+				}
+				self.step()?;
+			}
+			self.mute(false)?;
+			if let Some(step) = last {
+				self.goto(step)?;
+				return Ok(true);
+			}
+			until = from;
+		}
+		self.goto(self.oldest())?;
+		Ok(false)
+	}
+
+	/// Goes back to the last write to the address, returns false if there is none
+	fn reverse_watch(&mut self, addr: usize) -> Result<bool, RuntimeError> {
+		let undo = self.m.undo.as_ref().unwrap();
+		let write = undo
+			.iter()
+			.rev()
+			.find(|write| write.addr <= addr && addr < write.addr + write.old.len());
+		match write.map(|write| write.step) {
+			| Some(step) => {
+				self.goto(step)?;
+				Ok(true)
+			}
+			| None => Ok(false),
+		}
+	}
+
+	/// Address of `addr`, a number or the name of a `decla` region
+	fn address(&self, addr: &str) -> Option<usize> {
+		let addr = match addr.parse() {
+			| Ok(addr) => addr,
+			| Err(_) => {
+				let kind = RegionKind::Decla(addr.to_string());
+				self.m.regions.iter().find(|region| region.kind == kind)?.start
+			}
+		};
+		(addr < self.m.memory.len()).then_some(addr)
+	}
+
+	fn show(&mut self) -> Result<(), RuntimeError> {
+		self.m.io.stdout.flush().map_err(output_failed)?;
+		let steps = self.m.steps;
+		match &self.state {
+			| State::Running => {
+				let op = &self.program.ops[self.m.origins[self.m.ip]];
+				writeln!(self.out, "step {steps}: {} {}", op.annot.get_pos(), op.typ)
+			}
+			| State::Exited(code) => {
+				writeln!(self.out, "step {steps}: The program exited with code {code}")
+			}
+			| State::Faulted(e) => writeln!(self.out, "step {steps}: {e}"),
+		}
+		.map_err(shown)
+	}
+
+	fn print_stack(&mut self) -> Result<(), RuntimeError> {
+		let Machine { stack, origins, stacks, ip, .. } = &self.m;
+		let values = &stack.slots[..stack.sp];
+		let op = origins.get(*ip).copied().unwrap_or(self.program.ops.len());
+		let types = match op {
+			| 0 => &[][..],
+			| op => &stacks[op - 1][..],
+		};
+		if values.is_empty() {
+			writeln!(self.out, "The stack is empty")
+		} else if self.boundary() && types.len() == values.len() {
+			let values = values.iter().zip(types);
+			let values = values.map(|(slot, typ)| format!("{typ} {}", typ.format(*slot)));
+			writeln!(self.out, "{}", values.collect::<Vec<_>>().join(", "))
+		} else {
+			let values = values.iter().map(|slot| (*slot as i64).to_string());
+			writeln!(self.out, "{}", values.collect::<Vec<_>>().join(", "))
+		}
+		.map_err(shown)
+	}
+
+	fn print_memory(&mut self, addr: usize, len: usize) -> Result<(), RuntimeError> {
+		let bytes = &self.m.memory[addr..(addr + len).min(self.m.memory.len())];
+		for (line, bytes) in bytes.chunks(16).enumerate() {
+			let bytes = bytes.iter().map(|byte| format!("{byte:02x}"));
+			writeln!(
+				self.out,
+				"{}: {}",
+				addr + line * 16,
+				bytes.collect::<Vec<_>>().join(" ")
+			)
+			.map_err(shown)?
+		}
+		Ok(())
+	}
+
+	/// Breakpoint on `[file:]first[-last]`, in the debugged file by default
+	fn breakpoint(&self, lines: &str) -> Option<TraceFilter> {
+		if lines.contains(':') {
+			TraceFilter::parse(lines)
+		} else {
+			TraceFilter::parse(&format!("{}:{lines}", self.cli.input_path))
+		}
+	}
+
+	/// Runs the command, returns false to stop debugging
+	fn command(&mut self, line: &str) -> Result<bool, RuntimeError> {
+		let words: Vec<&str> = line.split_whitespace().collect();
+		let count = |arg: Option<&&str>| arg.map_or(Some(1), |n| n.parse::<u64>().ok());
+		match words[..] {
+			| [] => (),
+			| ["step" | "s", ref n @ ..] if n.len() <= 1 => {
+				let Some(n) = count(n.first()) else {
+					writeln!(self.out, "Expected a number of ops but got `{}`", n[0])
+						.map_err(shown)?;
+					return Ok(true);
+				};
+				for _ in 0..n {
+					self.step_op()?;
+				}
+				self.show()?
+			}
+			| ["reverse-step" | "rs", ref n @ ..] if n.len() <= 1 => {
+				let Some(n) = count(n.first()) else {
+					writeln!(self.out, "Expected a number of ops but got `{}`", n[0])
+						.map_err(shown)?;
+					return Ok(true);
+				};
+				for _ in 0..n {
+					self.reverse_step()?;
+				}
+				self.show()?
+			}
+			| ["continue" | "c"] => {
+				self.step_op()?;
+				while self.running() && !self.hit() {
+					self.step_op()?
+				}
+				self.show()?
+			}
+			| ["reverse-continue" | "rc"] => {
+				if !self.reverse_continue()? {
+					match self.oldest() {
+						| 0 => {
+							writeln!(
+								self.out,
+								"No breakpoint was hit before, back to the start"
+							)
+						}
+						| oldest => {
+							writeln!(
+								self.out,
+								"No breakpoint was hit since step {oldest}, the oldest \
+								 kept"
+							)
+						}
+					}
+					.map_err(shown)?
+				}
+				self.show()?
+			}
+			| ["break" | "b"] => {
+				for (idx, TraceFilter { file, lines }) in
+					self.breakpoints.iter().enumerate()
+				{
+					writeln!(self.out, "{idx}: {file}:{}-{}", lines.start(), lines.end())
+						.map_err(shown)?
+				}
+			}
+			| ["break" | "b", lines] => {
+				match self.breakpoint(lines) {
+					| Some(breakpoint) => {
+						writeln!(self.out, "Breakpoint {}", self.breakpoints.len())
+							.map_err(shown)?;
+						self.breakpoints.push(breakpoint)
+					}
+					| None => {
+						writeln!(
+							self.out,
+							"Expected `[file:]first[-last]` but got `{lines}`"
+						)
+						.map_err(shown)?
+					}
+				}
+			}
+			| ["delete" | "d"] => self.breakpoints.clear(),
+			| ["delete" | "d", idx] => {
+				match idx.parse().ok().filter(|idx| *idx < self.breakpoints.len()) {
+					| Some(idx) => {
+						self.breakpoints.remove(idx);
+					}
+					| None => {
+						writeln!(self.out, "No breakpoint `{idx}`").map_err(shown)?
+					}
+				}
+			}
+			| ["reverse-watch" | "rw", addr] => {
+				match self.address(addr) {
+					| Some(addr) if self.reverse_watch(addr)? => self.show()?,
+					| Some(addr) => {
+						match self.oldest() {
+							| 0 => {
+								writeln!(
+									self.out,
+									"Address {addr} was not written before"
+								)
+							}
+							| oldest => {
+								writeln!(
+									self.out,
+									"Address {addr} was not written since step \
+									 {oldest}, the oldest kept"
+								)
+							}
+						}
+						.map_err(shown)?
+					}
+					| None => {
+						writeln!(self.out, "`{addr}` is not an address of the memory")
+							.map_err(shown)?
+					}
+				}
+			}
+			| ["stack"] => self.print_stack()?,
+			| ["mem", addr, ref len @ ..] if len.len() <= 1 => {
+				let len = len.first().map_or(Some(8), |len| len.parse().ok());
+				match (self.address(addr), len) {
+					| (Some(addr), Some(len)) => self.print_memory(addr, len)?,
+					| (None, _) => {
+						writeln!(self.out, "`{addr}` is not an address of the memory")
+							.map_err(shown)?
+					}
+					| (_, None) => {
+						writeln!(self.out, "Expected a number of bytes").map_err(shown)?
+					}
+				}
+			}
+			| ["where" | "w"] => self.show()?,
+			| ["help" | "h"] => writeln!(self.out, "{HELP}").map_err(shown)?,
+			| ["quit" | "q"] => return Ok(false),
+			| _ => {
+				writeln!(self.out, "Unknown command `{}`, see `help`", line.trim())
+					.map_err(shown)?
+			}
+		}
+		Ok(true)
+	}
+}
+
+impl Program {
+	/// Debugs the program on the standard streams of the process, the commands
+	/// are read from stdin (`debug` mode)
+	pub fn debug(&mut self, cli: &Cli<String>) {
+		let commands = from_fn(|| {
+			let mut line = String::new();
+			(stdin().read_line(&mut line).unwrap_or(0) > 0).then_some(line)
+		});
+		if let Err(e) = Simulator::new(cli).debug(self, commands, &mut stdout()) {
+			self.add_error(e.to_string()).exit(e.exit_code())
+		}
+	}
+}
+
+impl Simulator<'_> {
+	/// Debugs the program on the streams of the simulator, running the commands
+	/// one after the other until `quit` and printing what they show to `out`
+	pub fn debug(
+		&mut self,
+		program: &Program,
+		mut commands: impl Iterator<Item = String>,
+		out: &mut dyn Write,
+	) -> Result<(), RuntimeError> {
+		// Instrumenting would count the steps executed again
+		let cli = Cli {
+			sanitize: false,
+			trace: None,
+			profile: false,
+			profile_folded: None,
+			coverage: None,
+			record: None,
+			replay: None,
+			..self.cli.clone()
+		};
+		let muted = Cell::new(false);
+		let mut output = Muted { out: &mut *self.stdout, muted: &muted };
+		let mut errors = Muted { out: &mut *self.stderr, muted: &muted };
+		let io = Io {
+			stdin:  &mut *self.stdin,
+			stdout: BufWriter::new(&mut output),
+			stderr: &mut errors,
+		};
+		let mut m =
+			Simulator::machine(program, program.lower(true), &cli, io, &mut self.hosts)?;
+		m.undo = Some(vec![]);
+		m.syscalls.history = Some(vec![]);
+		let state = if m.code.is_empty() { State::Exited(0) } else { State::Running };
+		let mut debugger = Debugger {
+			program,
+			cli: &cli,
+			m,
+			muted: &muted,
+			checkpoints: vec![],
+			breakpoints: vec![],
+			out,
+			prev: usize::MAX,
+			state,
+		};
+		debugger.checkpoint();
+
+		writeln!(
+			debugger.out,
+			"Debugging {}, see `help` for the commands",
+			cli.input_path
+		)
+		.map_err(shown)?;
+		debugger.show()?;
+		loop {
+			write!(debugger.out, "(debug) ").map_err(shown)?;
+			debugger.out.flush().map_err(shown)?;
+			match commands.next() {
+				| Some(line) if debugger.command(&line)? => (),
+				| _ => break,
+			}
+		}
+		debugger.m.io.stdout.flush().map_err(output_failed)
+	}
+}
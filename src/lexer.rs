@@ -3,8 +3,9 @@ use std::fmt::Display;
 
 use crate::{
 	annotation::{Annotation, Position},
-	parser::{Parser, Program},
-	report::Reporter,
+	parser::{IntOp, Overflow, Parser, Program},
+	report::{Abort, Reporter},
+	Cli,
 };
 //#endregion
 
@@ -17,6 +18,8 @@ pub struct Lexer {
 	line:         usize,
 	line_start:   usize,
 	pub strings:  Vec<String>,
+	/// Set after an `asm` keyword: the next `{ ... }` is kept verbatim.
+	raw_block:    bool,
 }
 
 #[derive(Clone)]
@@ -40,12 +43,20 @@ pub enum TokenType {
 	Plus,
 	Minus,
 	Star,
+	IntArith(IntOp, Overflow),
 	Slash,
 	Modulo,
+	DivMod(bool),
 	DoubleMinus,
 	DoublePlus,
 	Drop,
 	Swap,
+	Rot,
+	Unrot,
+	Nip,
+	Tuck,
+	Swap2,
+	Roll,
 	Over,
 	Dup,
 	If,
@@ -61,6 +72,23 @@ pub enum TokenType {
 	Lte,
 	Gte,
 	Syscall,
+	Exit,
+	ToF64,
+	ToI64,
+	Round,
+	Floor,
+	Ceil,
+	Fmod,
+	Sqrt,
+	Abs,
+	Neg,
+	Min,
+	Max,
+	Pow,
+	Exp,
+	Log,
+	Sin,
+	Cos,
 	StringLit(String),
 	Argc,
 	Argv,
@@ -78,6 +106,7 @@ pub enum TokenType {
 	CCurly,
 	OParen,
 	CParen,
+	Comma,
 	Include,
 	Cast,
 	TypeI64,
@@ -85,7 +114,14 @@ pub enum TokenType {
 	TypeBool,
 	TypePtr,
 	ShiftR,
+	ShiftRA,
 	ShiftL,
+	RotL,
+	RotR,
+	Xor,
+	Popcount,
+	Clz,
+	Ctz,
 	Or,
 	BitOr,
 	And,
@@ -94,6 +130,12 @@ pub enum TokenType {
 	Mem,
 	Decla,
 	SetOver,
+	PreIf,
+	PreElse,
+	PreEnd,
+	Asm,
+	Host,
+	RawBlock(String),
 }
 
 impl Display for TokenType {
@@ -106,11 +148,32 @@ impl Display for TokenType {
 			| Plus => write!(f, "+"),
 			| Minus => write!(f, "-"),
 			| Star => write!(f, "*"),
+			| IntArith(op, overflow) => {
+				let op = match op {
+					| IntOp::Add => "+",
+					| IntOp::Sub => "-",
+					| IntOp::Mul => "*",
+				};
+				let overflow = match overflow {
+					| Overflow::Wrapping => "%",
+					| Overflow::Saturating => "|",
+					| Overflow::Checked => "?",
+				};
+				write!(f, "{op}{overflow}")
+			}
 			| Slash => write!(f, "/"),
 			| DoubleMinus => write!(f, "--"),
 			| DoublePlus => write!(f, "++"),
 			| Modulo => write!(f, "%"),
+			| DivMod(false) => write!(f, "divmod"),
+			| DivMod(true) => write!(f, "divmod_floor"),
 			| Swap => write!(f, "swap"),
+			| Rot => write!(f, "rot"),
+			| Unrot => write!(f, "-rot"),
+			| Nip => write!(f, "nip"),
+			| Tuck => write!(f, "tuck"),
+			| Swap2 => write!(f, "2swap"),
+			| Roll => write!(f, "roll"),
 			| Drop => write!(f, "drop"),
 			| Over => write!(f, "over"),
 			| Dup => write!(f, "dup"),
@@ -128,6 +191,23 @@ impl Display for TokenType {
 			| Lte => write!(f, "lte"),
 			| Gte => write!(f, "gte"),
 			| Syscall => write!(f, "syscall"),
+			| Exit => write!(f, "exit"),
+			| ToF64 => write!(f, "to_f64"),
+			| ToI64 => write!(f, "to_i64"),
+			| Round => write!(f, "round"),
+			| Floor => write!(f, "floor"),
+			| Ceil => write!(f, "ceil"),
+			| Fmod => write!(f, "fmod"),
+			| Sqrt => write!(f, "sqrt"),
+			| Abs => write!(f, "abs"),
+			| Neg => write!(f, "neg"),
+			| Min => write!(f, "min"),
+			| Max => write!(f, "max"),
+			| Pow => write!(f, "pow"),
+			| Exp => write!(f, "exp"),
+			| Log => write!(f, "log"),
+			| Sin => write!(f, "sin"),
+			| Cos => write!(f, "cos"),
 			| StringLit(str_lit) => write!(f, "\"{}\"", str_lit),
 			| Argc => write!(f, "argc"),
 			| Argv => write!(f, "argv"),
@@ -145,6 +225,7 @@ impl Display for TokenType {
 			| CCurly => write!(f, "}}"),
 			| OParen => write!(f, "("),
 			| CParen => write!(f, ")"),
+			| Comma => write!(f, ","),
 			| Include => write!(f, "include"),
 			| Cast => write!(f, "cast"),
 			| TypeI64 => write!(f, "I64"),
@@ -152,7 +233,14 @@ impl Display for TokenType {
 			| TypeBool => write!(f, "Bool"),
 			| TypePtr => write!(f, "Ptr"),
 			| ShiftR => write!(f, ">>"),
+			| ShiftRA => write!(f, ">>>"),
 			| ShiftL => write!(f, "<<"),
+			| RotL => write!(f, "rotl"),
+			| RotR => write!(f, "rotr"),
+			| Xor => write!(f, "^"),
+			| Popcount => write!(f, "popcount"),
+			| Clz => write!(f, "clz"),
+			| Ctz => write!(f, "ctz"),
 			| BitOr => write!(f, "||"),
 			| Or => write!(f, "|"),
 			| BitAnd => write!(f, "&&"),
@@ -161,12 +249,18 @@ impl Display for TokenType {
 			| Mem => write!(f, "mem"),
 			| Decla => write!(f, "decla"),
 			| SetOver => write!(f, "setOver"),
+			| PreIf => write!(f, "#if"),
+			| PreElse => write!(f, "#else"),
+			| PreEnd => write!(f, "#end"),
+			| Asm => write!(f, "asm"),
+			| Host => write!(f, "host"),
+			| RawBlock(_) => write!(f, "{{ ... }}"),
 		}
 	}
 }
 
 impl Lexer {
-	const WORD_STOP: [char; 5] = ['/', '(', ')', '{', '}'];
+	const WORD_STOP: [char; 6] = ['/', '(', ')', '{', '}', ','];
 
 	pub fn new(input: Vec<char>, input_path: String, reporter: Reporter) -> Self {
 		Lexer {
@@ -177,9 +271,17 @@ impl Lexer {
 			line: 1,
 			line_start: 0,
 			strings: vec![],
+			raw_block: false,
 		}
 	}
 
+	/// Lexer of an input starting at `line` of its file, like an entry of the
+	/// `repl`
+	pub fn at_line(mut self, line: usize) -> Self {
+		self.line = line;
+		self
+	}
+
 	fn trim(&mut self) -> &mut Self { self.skip_while(|c| c.is_whitespace()) }
 
 	fn is_end(&self) -> bool { self.pos >= self.input.len() }
@@ -210,15 +312,19 @@ impl Lexer {
 	pub fn get_annot(&self) -> Annotation { Annotation::new(self.get_pos()) }
 
 	pub fn get_pos(&self) -> Position {
-		Position::new(self.input_path.clone(), self.line, self.pos - self.line_start)
+		Position::new(self.input_path.clone(), self.line, self.pos - self.line_start + 1)
 	}
 
 	pub fn add_error(&mut self, msg: String) -> &mut Self {
-		self.reporter.add_error(format!("{}: {}", self.get_pos(), msg));
+		self.reporter.add_error(msg);
 		self
 	}
 
-	pub fn exit(&mut self, code: i32) -> ! { self.reporter.exit(code) }
+	/// Ends the tokens at an error, the parser aborts on the errors of the lexer
+	fn stop(&mut self) -> Option<Token> {
+		self.pos = self.input.len();
+		None
+	}
 
 	fn skip_n(&mut self, n: usize) -> &mut Self {
 		for _ in 0..n {
@@ -227,7 +333,7 @@ impl Lexer {
 			}
 			if self.at() == '\n' {
 				self.line += 1;
-				self.line_start = self.pos;
+				self.line_start = self.pos + 1;
 			}
 			self.pos += 1;
 		}
@@ -259,9 +365,15 @@ impl Lexer {
 		lit
 	}
 
-	pub fn parse(self) -> Program { Program::new(Parser::new(self)) }
+	pub fn parse(self, cli: &Cli<String>) -> Result<Program, Abort> {
+		Ok(Program::new(Parser::new(self, cli)?))
+	}
 
-	fn lex_number(&mut self, lit: &str) -> Result<Option<Token>, String> {
+	fn lex_number(
+		&mut self,
+		lit: &str,
+		annot: Annotation,
+	) -> Result<Option<Token>, String> {
 		let (lit, neg) = if lit.starts_with('-') {
 			(lit.strip_prefix('-').unwrap(), true)
 		} else {
@@ -271,8 +383,8 @@ impl Lexer {
 		Ok(if lit.contains('.') {
 			let f_lit = lit.parse::<f64>().map_err(|e| e.to_string())?;
 			Some(Token {
-				typ:   TokenType::FloatLit(if neg { -f_lit } else { f_lit }),
-				annot: self.get_annot(),
+				typ: TokenType::FloatLit(if neg { -f_lit } else { f_lit }),
+				annot,
 			})
 		} else {
 			let int_lit = if lit.starts_with("0b") {
@@ -294,8 +406,8 @@ impl Lexer {
 				};
 			};
 			Some(Token {
-				typ:   TokenType::IntLit(if neg { -int_lit } else { int_lit }),
-				annot: self.get_annot(),
+				typ: TokenType::IntLit(if neg { -int_lit } else { int_lit }),
+				annot,
 			})
 		})
 	}
@@ -316,14 +428,41 @@ impl Iterator for Lexer {
 		if self.start_with("/*") {
 			return self.skip_until_str("*/").next();
 		}
+		let annot = self.get_annot();
+		if self.raw_block && self.at() == '{' {
+			self.raw_block = false;
+			self.skip_n(1);
+			let start = self.pos;
+			let mut depth = 0;
+			while depth > 0 || self.at() != '}' {
+				if self.is_end() {
+					return self
+						.add_error(format!("{}: Unterminated asm block", annot.get_pos()))
+						.stop();
+				}
+				match self.at() {
+					| '{' => depth += 1,
+					| '}' => depth -= 1,
+					| _ => (),
+				}
+				self.skip_n(1);
+			}
+			let code: String = self.input[start..self.pos].iter().collect();
+			self.skip_n(1);
+			return Some(Token {
+				typ: RawBlock(code.trim_end().trim_start_matches(['\n', '\r']).into()),
+				annot,
+			});
+		}
 		if self.at() == '"' {
 			self.skip_n(1);
 			let start_pos = self.get_pos();
 			let start = self.pos;
 			while self.at() != '"' {
 				if self.is_end() || self.at() == '\n' {
-					self.add_error(format!("{start_pos}: Unterminated string literal"))
-						.exit(1);
+					return self
+						.add_error(format!("{start_pos}: Unterminated string literal"))
+						.stop();
 				}
 				if self.at() == '\\' {
 					self.skip_n(1);
@@ -335,7 +474,7 @@ impl Iterator for Lexer {
 				self.strings.push(lit.clone());
 			}
 			self.skip_n(1);
-			return Some(Token { typ: StringLit(lit), annot: self.get_annot() });
+			return Some(Token { typ: StringLit(lit), annot });
 		}
 		if self.at() == '\'' {
 			self.skip_n(1);
@@ -346,20 +485,22 @@ impl Iterator for Lexer {
 				false
 			};
 			if ['\n', '\r', '\0'].contains(&self.at()) {
-				self.add_error(format!(
-					"{}: Unterminated character literal",
-					self.get_pos()
-				))
-				.exit(1)
+				return self
+					.add_error(format!(
+						"{}: Unterminated character literal",
+						self.get_pos()
+					))
+					.stop();
 			}
 			let mut lit = self.at();
 			self.skip_n(1);
 			if self.at() != '\'' {
-				self.add_error(format!(
-					"{}: Unterminated or too long character literal",
-					self.get_pos()
-				))
-				.exit(1)
+				return self
+					.add_error(format!(
+						"{}: Unterminated or too long character literal",
+						self.get_pos()
+					))
+					.stop();
 			}
 			self.skip_n(1);
 			if escaped {
@@ -369,29 +510,47 @@ impl Iterator for Lexer {
 					| 't' => '\t',
 					| '0' => '\0',
 					| c => {
-						self.add_error(format!(
-							"{}: Invalid escape sequence in character literal: \\{c}",
-							self.get_pos()
-						))
-						.exit(1)
+						return self
+							.add_error(format!(
+								"{}: Invalid escape sequence in character literal: \\{c}",
+								self.get_pos()
+							))
+							.stop()
 					}
 				};
 			}
-			return Some(Token { typ: IntLit(lit as i64), annot: self.get_annot() });
+			return Some(Token { typ: IntLit(lit as i64), annot });
 		}
 
 		let lit = self.take_word();
 		Some(Token {
-			typ:   match lit.as_str() {
+			typ: match lit.as_str() {
 				| "dump" => Dump,
 				| "+" => Plus,
 				| "-" => Minus,
 				| "*" => Star,
+				| "+%" => IntArith(IntOp::Add, Overflow::Wrapping),
+				| "-%" => IntArith(IntOp::Sub, Overflow::Wrapping),
+				| "*%" => IntArith(IntOp::Mul, Overflow::Wrapping),
+				| "+|" => IntArith(IntOp::Add, Overflow::Saturating),
+				| "-|" => IntArith(IntOp::Sub, Overflow::Saturating),
+				| "*|" => IntArith(IntOp::Mul, Overflow::Saturating),
+				| "+?" => IntArith(IntOp::Add, Overflow::Checked),
+				| "-?" => IntArith(IntOp::Sub, Overflow::Checked),
+				| "*?" => IntArith(IntOp::Mul, Overflow::Checked),
 				| "/" => Slash,
 				| "++" => DoublePlus,
 				| "--" => DoubleMinus,
 				| "%" => Modulo,
+				| "divmod" => DivMod(false),
+				| "divmod_floor" => DivMod(true),
 				| "swap" => Swap,
+				| "rot" => Rot,
+				| "-rot" => Unrot,
+				| "nip" => Nip,
+				| "tuck" => Tuck,
+				| "2swap" => Swap2,
+				| "roll" => Roll,
 				| "if" => If,
 				| "then" => Then,
 				| "else" => Else,
@@ -421,10 +580,28 @@ impl Iterator for Lexer {
 				| "dup" => Dup,
 				| "over" => Over,
 				| "syscall" => Syscall,
+				| "exit" => Exit,
+				| "to_f64" => ToF64,
+				| "to_i64" => ToI64,
+				| "round" => Round,
+				| "floor" => Floor,
+				| "ceil" => Ceil,
+				| "fmod" => Fmod,
+				| "sqrt" => Sqrt,
+				| "abs" => Abs,
+				| "neg" => Neg,
+				| "min" => Min,
+				| "max" => Max,
+				| "pow" => Pow,
+				| "exp" => Exp,
+				| "log" => Log,
+				| "sin" => Sin,
+				| "cos" => Cos,
 				| "{" => OCurly,
 				| "}" => CCurly,
 				| "(" => OParen,
 				| ")" => CParen,
+				| "," => Comma,
 				| "include" => Include,
 				| "cast" => Cast,
 				| "I64" => TypeI64,
@@ -432,7 +609,14 @@ impl Iterator for Lexer {
 				| "Bool" => TypeBool,
 				| "Ptr" => TypePtr,
 				| ">>" => ShiftR,
+				| ">>>" => ShiftRA,
 				| "<<" => ShiftL,
+				| "rotl" => RotL,
+				| "rotr" => RotR,
+				| "^" => Xor,
+				| "popcount" => Popcount,
+				| "clz" => Clz,
+				| "ctz" => Ctz,
 				| "||" => Or,
 				| "|" => BitOr,
 				| "&&" => And,
@@ -441,8 +625,16 @@ impl Iterator for Lexer {
 				| "mem" => Mem,
 				| "decla" => Decla,
 				| "setOver" => SetOver,
+				| "#if" => PreIf,
+				| "#else" => PreElse,
+				| "#end" => PreEnd,
+				| "host" => Host,
+				| "asm" => {
+					self.raw_block = true;
+					Asm
+				}
 				| lit => {
-					match self.lex_number(lit) {
+					match self.lex_number(lit, annot.clone()) {
 						| Ok(Some(typ)) => return Some(typ.clone()),
 						| Ok(None) => Id(lit.into()),
 						| Err(e) => {
@@ -452,12 +644,12 @@ impl Iterator for Lexer {
 							} else {
 								format!("{pos}: Unable to parse number {lit}")
 							};
-							self.add_error(msg).exit(1)
+							return self.add_error(msg).stop();
 						}
 					}
 				}
 			},
-			annot: self.get_annot(),
+			annot,
 		})
 	}
 }
@@ -0,0 +1,137 @@
+pub mod annotation;
+pub mod bytecode;
+pub mod compiler;
+pub mod coverage;
+pub mod debugger;
+pub mod lexer;
+pub mod math;
+pub mod parser;
+pub mod policy;
+pub mod profiler;
+pub mod repl;
+pub mod replay;
+pub mod report;
+pub mod sanitizer;
+pub mod simulator;
+pub mod syscalls;
+pub mod tester;
+pub mod tracer;
+pub mod type_checker;
+
+use std::time::Duration;
+
+use lexer::Lexer;
+use parser::Program;
+use policy::Policy;
+use report::{Abort, Level, Reporter};
+use tracer::TraceFilter;
+
+#[derive(Clone)]
+pub enum Mode {
+	Com,
+	Sim,
+	Test,
+	Debug,
+	Repl,
+}
+
+impl TryFrom<Option<String>> for Mode {
+	type Error = String;
+
+	fn try_from(value: Option<String>) -> Result<Self, Self::Error> {
+		if value.is_none() {
+			return Err("No mode where specified".to_string());
+		}
+		match value.unwrap().as_str() {
+			| "com" => Ok(Mode::Com),
+			| "sim" => Ok(Mode::Sim),
+			| "test" => Ok(Mode::Test),
+			| "debug" => Ok(Mode::Debug),
+			| "repl" => Ok(Mode::Repl),
+			| _ => Err("Unknown mode".to_string()),
+		}
+	}
+}
+
+#[derive(Clone)]
+pub struct Cli<S: Into<String>> {
+	pub program_path:    S,
+	pub input_path:      S,
+	pub output_path:     S,
+	pub mode:            Mode,
+	pub debug:           bool,
+	pub help:            bool,
+	pub rounding:        bool,
+	pub run:             bool,
+	pub level:           Level,
+	pub max_macro_depth: usize,
+	pub defines:         Vec<(String, i64)>,
+	pub sim_mem:         usize,
+	pub sanitize:        bool,
+	pub trace:           Option<String>,
+	pub trace_filter:    Option<TraceFilter>,
+	pub profile:         bool,
+	pub profile_folded:  Option<String>,
+	pub coverage:        Option<String>,
+	pub max_steps:       Option<u64>,
+	pub max_stack:       usize,
+	pub timeout:         Option<Duration>,
+	pub policy:          Policy,
+	pub record:          Option<String>,
+	pub replay:          Option<String>,
+	pub exit_with_stack: bool,
+	pub overflow_checks: bool,
+}
+
+impl Cli<String> {
+	/// Command line without any option
+	pub fn new(program_path: String, mode: Mode, input_path: String) -> Self {
+		Cli {
+			program_path,
+			input_path,
+			mode,
+			output_path: "a.out".to_string(),
+			debug: false,
+			help: false,
+			rounding: false,
+			run: false,
+			level: Level::Info,
+			max_macro_depth: 256,
+			defines: vec![],
+			sim_mem: 256 * 1024,
+			sanitize: false,
+			trace: None,
+			trace_filter: None,
+			profile: false,
+			profile_folded: None,
+			coverage: None,
+			max_steps: None,
+			max_stack: 1024 * 1024,
+			timeout: None,
+			policy: Policy::default(),
+			record: None,
+			replay: None,
+			exit_with_stack: false,
+			overflow_checks: false,
+		}
+	}
+}
+
+/// Parses and type checks the program `input`, read from `cli.input_path`.
+///
+/// Errors in the program are reported and returned rather than exiting, the
+/// warnings are left in the reporter of the program.
+pub fn load(
+	input: &str,
+	cli: &Cli<String>,
+	reporter: Reporter,
+) -> Result<Program, Vec<String>> {
+	let lexer = Lexer::new(input.chars().collect(), cli.input_path.clone(), reporter);
+	match lexer.parse(cli).and_then(Program::type_check) {
+		| Ok(mut program) if program.reporter.has(Level::Error) => {
+			Err(program.reporter.flush().errors())
+		}
+		| Ok(program) => Ok(program),
+		| Err(Abort(errors)) => Err(errors),
+	}
+}
@@ -1,53 +1,22 @@
-mod annotation;
-mod compiler;
-mod lexer;
-mod parser;
-mod report;
-mod simulator;
-mod type_checker;
-
 use std::{
 	env::args,
+	io::{stderr, stdin, stdout},
+	os::unix::process::ExitStatusExt,
 	path::Path,
 	process::{exit, Command},
+	time::Duration,
 };
 
-use lexer::Lexer;
-use report::{Level, Reporter};
-
-#[derive(Clone)]
-enum Mode {
-	Com,
-	Sim,
-}
-
-impl TryFrom<Option<String>> for Mode {
-	type Error = String;
-
-	fn try_from(value: Option<String>) -> Result<Self, Self::Error> {
-		if value.is_none() {
-			return Err("No mode where specified".to_string());
-		}
-		match value.unwrap().as_str() {
-			| "com" => Ok(Mode::Com),
-			| "sim" => Ok(Mode::Sim),
-			| _ => Err("Unknown mode".to_string()),
-		}
-	}
-}
-
-#[derive(Clone)]
-struct Cli<S: Into<String>> {
-	program_path: S,
-	input_path:   S,
-	output_path:  S,
-	mode:         Mode,
-	debug:        bool,
-	help:         bool,
-	rounding:     bool,
-	run:          bool,
-	level:        Level,
-}
+use ssmpl::{
+	load,
+	policy::Policy,
+	repl,
+	report::{self, Level, Reporter},
+	tester,
+	tracer::TraceFilter,
+	Cli,
+	Mode,
+};
 
 fn usage(program_path: &String) -> String {
 	format!("Usage: {} <mode> <input> [options]\n", program_path)
@@ -55,20 +24,62 @@ fn usage(program_path: &String) -> String {
 		+ "    com: Compile the program to elf64 asm.\n"
 		+ "         If output is not specified, it will be a.out.\n"
 		+ "    sim: Simulate the program.\n"
-		+ "         Output is ignored if specified\n."
+		+ "         Output is ignored if specified.\n"
+		+ "    debug: Simulate the program step by step, forwards and backwards.\n"
+		+ "           The commands are read from stdin, see `help`.\n"
+		+ "    repl: Read, type check and simulate lines of Ssmpl one after the other.\n"
+		+ "          The stack and the definitions are kept between lines, no input is \
+		   given.\n"
+		+ "    test: Simulate the test program, or every `.ssmpl` file of the \
+		   directory.\n"
+		+ "          A test passes if it succeeds and prints the content of its \
+		   `.expected` file, if any.\n"
 		+ "Options:\n"
 		+ "    -o <path>: Specify the output path for the compilation (`com`) mode.\n"
 		+ "               As no effect in simulation (`sim`) mode.\n"
 		+ "    -d, --debug: Enable debug mode.\n"
 		+ "                 Don't remove temporary files (.o and .asm)\n"
-		+ "                 As no effect in simulation (`sim`) mode.\n"
+		+ "                 Defines `DEBUG` for `#if`.\n"
 		+ "    -h --help: Show this help message.\n"
 		+ "    -r --run: Run the program after compilation.\n"
 		+ "		   	     As no effect in simulation (`sim`) mode.\n"
 		+ "    --rounding: Rounds double values for dumping.\n"
 		+ "		   			   As no effect in simulation (`sim`) mode.\n"
+		+ "    --exit-with-stack: Exit with the i64 on top of the stack at the end of \
+		   the program.\n"
+		+ "    --overflow-checks: Integer `+`, `-` and `*` overflowing is a runtime \
+		   error, in both modes.\n"
 		+ "    --log <level>: Set the log level.\n"
 		+ "		   			  Possible values are: Info(as no effect), Warning, Error\n"
+		+ "    --macro-depth <n>: Set the maximum macro expansion depth (default: 256).\n"
+		+ "    --sim-mem <bytes>: Set the memory size of the simulation (`sim`) mode \
+		   (default: 262144).\n"
+		+ "    --sanitize: Report misuses of the memory in simulation (`sim`) mode.\n"
+		+ "    --profile: Report the ops and cycles per line, macro and loop in \
+		   simulation (`sim`) mode.\n"
+		+ "    --profile-folded <path>: Write the folded stacks of the simulation \
+		   (`sim`) for flamegraphs.\n"
+		+ "    --coverage <path>: Write the line and branch coverage of the simulation \
+		   (`sim`, `test`) as an lcov tracefile.\n"
+		+ "    --trace <path>: Write one json record per op executed in simulation \
+		   (`sim`) mode.\n"
+		+ "    --trace-filter <file>:<first>[-<last>]: Only trace the ops from these \
+		   lines.\n"
+		+ "    --max-steps <n>: Stop the simulation after n steps.\n"
+		+ "    --max-stack <n>: Stop the simulation when the stack holds more than n \
+		   values (default: 1048576).\n"
+		+ "    --timeout <seconds>: Stop the simulation after this many seconds.\n"
+		+ "    --policy <path>: Restrict the syscalls of the simulation (see README).\n"
+		+ "                     The three limits and the policy stop the simulation with \
+		   the exit code 125.\n"
+		+ "    --record <path>: Write every syscall of the simulation and its result to \
+		   a log.\n"
+		+ "    --replay <path>: Replay the syscalls of a log written by --record instead \
+		   of doing them.\n"
+		+ "                     The simulation stops at the first syscall differing from \
+		   the log.\n"
+		+ "    -D <name>[=<value>]: Define an integer macro usable in `#if` (default: \
+		   1).\n"
 }
 
 fn retrieve_cli(reporter: &mut report::Reporter) -> Cli<String> {
@@ -83,27 +94,29 @@ fn retrieve_cli(reporter: &mut report::Reporter) -> Cli<String> {
 		reporter.add_error(e);
 		Mode::Sim
 	});
-	let input_path = args.pop().map(|str| str.to_string()).unwrap_or("".to_string());
+	// The repl reads its program from stdin
+	let input_path = match mode {
+		| Mode::Repl => "".to_string(),
+		| _ => args.pop().map(|str| str.to_string()).unwrap_or("".to_string()),
+	};
 
-	let (output_path, debug, help, rounding, run, level) =
-		retrieve_options(&mut args, reporter);
-	Cli { program_path, input_path, mode, output_path, debug, help, rounding, run, level }
+	let mut cli = Cli::new(program_path, mode, input_path);
+	retrieve_options(&mut args, &mut cli, reporter);
+	if cli.record.is_some() && cli.replay.is_some() {
+		reporter.add_error("--record and --replay cannot be used together".to_string());
+	}
+	cli
 }
 
 fn retrieve_options(
 	args: &mut Vec<String>,
+	cli: &mut Cli<String>,
 	reporter: &mut report::Reporter,
-) -> (String, bool, bool, bool, bool, Level) {
-	let mut output_path = "a.out".to_string();
-	let mut debug = false;
-	let mut help = false;
-	let mut rounding = false;
-	let mut run = false;
-	let mut level = Level::Info;
+) {
 	while !args.is_empty() {
 		match args.pop().unwrap().as_str() {
 			| "-o" => {
-				output_path = args.pop().unwrap_or_else(|| {
+				cli.output_path = args.pop().unwrap_or_else(|| {
 					reporter
 						.add_error(
 							"-o option requires a path to be specified".to_string(),
@@ -111,10 +124,10 @@ fn retrieve_options(
 						.exit(1)
 				})
 			}
-			| "-d" | "--debug" => debug = true,
-			| "-h" | "--help" => help = true,
-			| "--rounding" => rounding = true,
-			| "-r" | "--run" => run = true,
+			| "-d" | "--debug" => cli.debug = true,
+			| "-h" | "--help" => cli.help = true,
+			| "--rounding" => cli.rounding = true,
+			| "-r" | "--run" => cli.run = true,
 			| "--log" => {
 				let level_str = args.pop().unwrap_or_else(|| {
 					reporter.add_error(
@@ -122,7 +135,7 @@ fn retrieve_options(
 					);
 					"Info".to_string()
 				});
-				level = match level_str.as_str() {
+				cli.level = match level_str.as_str() {
 					| "Info" => Level::Info,
 					| "Warning" => Level::Warning,
 					| "Error" => Level::Error,
@@ -138,13 +151,169 @@ fn retrieve_options(
 					}
 				}
 			}
+			| "--macro-depth" => {
+				let depth = args.pop().unwrap_or_default();
+				cli.max_macro_depth =
+					depth.parse().ok().filter(|depth| *depth > 0).unwrap_or_else(|| {
+						reporter.add_error(format!(
+							"--macro-depth option requires a positive integer but got: \
+							 `{depth}`"
+						));
+						cli.max_macro_depth
+					})
+			}
+			| "--exit-with-stack" => cli.exit_with_stack = true,
+			| "--overflow-checks" => cli.overflow_checks = true,
+			| "--sanitize" => cli.sanitize = true,
+			| "--profile" => cli.profile = true,
+			| "--profile-folded" => {
+				cli.profile_folded = Some(args.pop().unwrap_or_else(|| {
+					reporter
+						.add_error(
+							"--profile-folded option requires a path to be specified"
+								.to_string(),
+						)
+						.exit(1)
+				}))
+			}
+			| "--coverage" => {
+				cli.coverage = Some(args.pop().unwrap_or_else(|| {
+					reporter
+						.add_error(
+							"--coverage option requires a path to be specified"
+								.to_string(),
+						)
+						.exit(1)
+				}))
+			}
+			| "--trace" => {
+				cli.trace = Some(args.pop().unwrap_or_else(|| {
+					reporter
+						.add_error(
+							"--trace option requires a path to be specified".to_string(),
+						)
+						.exit(1)
+				}))
+			}
+			| "--trace-filter" => {
+				let filter = args.pop().unwrap_or_default();
+				cli.trace_filter = TraceFilter::parse(&filter).or_else(|| {
+					reporter.add_error(format!(
+						"--trace-filter option requires `file:line` or \
+						 `file:first-last` but got: `{filter}`"
+					));
+					None
+				})
+			}
+			| "--sim-mem" => {
+				let size = args.pop().unwrap_or_default();
+				cli.sim_mem = size.parse().unwrap_or_else(|_| {
+					reporter.add_error(format!(
+						"--sim-mem option requires a positive integer but got: `{size}`"
+					));
+					cli.sim_mem
+				})
+			}
+			| "--max-steps" => {
+				let steps = args.pop().unwrap_or_default();
+				cli.max_steps = Some(steps.parse().unwrap_or_else(|_| {
+					reporter.add_error(format!(
+						"--max-steps option requires a positive integer but got: \
+						 `{steps}`"
+					));
+					u64::MAX
+				}))
+			}
+			| "--max-stack" => {
+				let size = args.pop().unwrap_or_default();
+				cli.max_stack = size.parse().unwrap_or_else(|_| {
+					reporter.add_error(format!(
+						"--max-stack option requires a positive integer but got: \
+						 `{size}`"
+					));
+					cli.max_stack
+				})
+			}
+			| "--timeout" => {
+				let seconds = args.pop().unwrap_or_default();
+				cli.timeout = Some(
+					seconds
+						.parse()
+						.ok()
+						.and_then(|s| Duration::try_from_secs_f64(s).ok())
+						.unwrap_or_else(|| {
+							reporter.add_error(format!(
+								"--timeout option requires a positive number of seconds \
+								 but got: `{seconds}`"
+							));
+							Duration::MAX
+						}),
+				)
+			}
+			| "--record" => {
+				cli.record = Some(args.pop().unwrap_or_else(|| {
+					reporter
+						.add_error(
+							"--record option requires a path to be specified".to_string(),
+						)
+						.exit(1)
+				}))
+			}
+			| "--replay" => {
+				cli.replay = Some(args.pop().unwrap_or_else(|| {
+					reporter
+						.add_error(
+							"--replay option requires a path to be specified".to_string(),
+						)
+						.exit(1)
+				}))
+			}
+			| "--policy" => {
+				let path = args.pop().unwrap_or_default();
+				let policy = std::fs::read_to_string(&path)
+					.map_err(|e| e.to_string())
+					.and_then(|policy| Policy::parse(&policy));
+				cli.policy = policy.unwrap_or_else(|e| {
+					reporter.add_error(format!("Invalid policy file `{path}`: {e}"));
+					cli.policy.clone()
+				})
+			}
+			| "-D" => {
+				let define = args.pop().unwrap_or_else(|| {
+					reporter
+						.add_error(
+							"-D option requires a name to be specified".to_string(),
+						)
+						.exit(1)
+				});
+				cli.defines.push(parse_define(&define, reporter));
+			}
+			| other if other.starts_with("-D") => {
+				cli.defines
+					.push(parse_define(other.strip_prefix("-D").unwrap(), reporter))
+			}
 			| other => {
 				reporter.add_error(format!("Unknown option: {}", other));
 			}
 		}
 	}
-	(output_path, debug, help, rounding, run, level)
 }
+fn parse_define(define: &str, reporter: &mut report::Reporter) -> (String, i64) {
+	let (name, value) = define.split_once('=').unwrap_or((define, "1"));
+	let mut chars = name.chars();
+	if !chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+		|| !chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+	{
+		reporter.add_error(format!("Name of define must be an identifier: `{name}`"));
+	}
+	let value = value.parse().unwrap_or_else(|_| {
+		reporter
+			.add_error(format!("Value of define {name} must be an integer: `{value}`"));
+		0
+	});
+	(name.to_string(), value)
+}
+
 fn main() {
 	let mut reporter = Reporter::new(Level::Info);
 	let cli = retrieve_cli(&mut reporter);
@@ -157,25 +326,34 @@ fn main() {
 		exit(0)
 	}
 
+	if let Mode::Test = cli.mode {
+		tester::run_tests(&cli, &mut reporter)
+	}
+
+	if let Mode::Repl = cli.mode {
+		reporter.flush().exit_if(Level::Error, 1);
+		let (stdin, mut stdout, mut stderr) = (stdin(), stdout(), stderr());
+		match repl::run(&cli, &reporter, &mut stdin.lock(), &mut stdout, &mut stderr) {
+			| Ok(code) => exit(code),
+			| Err(e) => reporter.add_error(e.to_string()).exit(e.exit_code()),
+		}
+	}
+
 	if Path::new(&cli.output_path).file_name().is_none() {
 		reporter.add_error(format!("Invalid output path: {}", cli.output_path));
 	}
 
-	let input = std::fs::read_to_string(&cli.input_path)
-		.unwrap_or_else(|e| {
-			reporter.add_error(format!("Failed to read input file: {}", e));
-			"".to_string()
-		})
-		.chars()
-		.collect::<Vec<_>>();
+	let input = std::fs::read_to_string(&cli.input_path).unwrap_or_else(|e| {
+		reporter.add_error(format!("Failed to read input file: {}", e));
+		"".to_string()
+	});
 
 	if reporter.has(Level::Error) {
 		eprintln!("{}", usage(&cli.program_path))
 	}
 	reporter.exit_if(Level::Error, 1);
 
-	let lexer = Lexer::new(input.to_vec(), cli.clone().input_path, reporter.clone());
-	let mut program = lexer.parse().type_check();
+	let mut program = load(&input, &cli, reporter.clone()).unwrap_or_else(|_| exit(1));
 	match cli.mode {
 		| Mode::Com => {
 			program.compile(&cli).unwrap_or_else(|e| {
@@ -183,16 +361,25 @@ fn main() {
 			});
 			program.reporter.flush();
 			if cli.run {
-				Command::new(format!("./{}", &cli.output_path))
+				// `./` keeps a relative path from being searched in the PATH
+				let status = Command::new(Path::new(".").join(&cli.output_path))
 					.spawn()
 					.unwrap()
 					.wait()
 					.unwrap();
+				// A program killed by a signal exits like in a shell
+				exit(status.code().unwrap_or_else(|| 128 + status.signal().unwrap_or(0)))
 			}
 		}
+		| Mode::Test => unreachable!("Tests are run in simulations of their own"),
+		| Mode::Repl => unreachable!("The repl reads its program from stdin"),
 		| Mode::Sim => {
 			program.reporter.flush().exit_if(Level::Error, 1);
-			program.simulate()
+			program.simulate(&cli)
+		}
+		| Mode::Debug => {
+			program.reporter.flush().exit_if(Level::Error, 1);
+			program.debug(&cli)
 		}
 	}
 }
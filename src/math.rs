@@ -0,0 +1,702 @@
+// Math functions of the simulator. They are the ones of the runtime of the
+// compiled programs (`resources/aux.c`), written with the same sequence of IEEE
+// operations so that both give the same bits. Ported from fdlibm and musl.
+//
+// NaN arguments are returned quieted (the first one if both are), invalid
+// operations give the default NaN of x86.
+
+// The constants are the ones of fdlibm, and `x - x` is how NaNs are made
+#![allow(clippy::excessive_precision, clippy::approx_constant, clippy::eq_op)]
+
+/// Bits of 2/π after a zero word for the bits before the binary point, for the
+/// reduction of large arguments of `sin` and `cos`
+const TWO_OVER_PI: [u64; 20] = [
+	0,
+	0xA2F9836E4E441529,
+	0xFC2757D1F534DDC0,
+	0xDB6295993C439041,
+	0xFE5163ABDEBBC561,
+	0xB7246E3A424DD2E0,
+	0x06492EEA09D1921C,
+	0xFE1DEB1CB129A73E,
+	0xE88235F52EBB4484,
+	0xE99C7026B45F7E41,
+	0x3991D639835339F4,
+	0x9C845F8BBDF9283B,
+	0x1FF897FFDE05980F,
+	0xEF2F118B5A0A6D1F,
+	0x6D367ECF27CB09B7,
+	0x4F463F669E5FEA2D,
+	0x7527BAC7EBE5F17B,
+	0x3D0739F78A5292EA,
+	0x6BFB5FB11F8D5D08,
+	0x56033046FC7B6BAB,
+];
+
+fn hi(x: f64) -> i32 { (x.to_bits() >> 32) as i32 }
+
+fn lo(x: f64) -> u32 { x.to_bits() as u32 }
+
+fn from_words(hi: i32, lo: u32) -> f64 {
+	f64::from_bits((hi as u32 as u64) << 32 | lo as u64)
+}
+
+fn with_hi(x: f64, hi: i32) -> f64 { from_words(hi, lo(x)) }
+
+/// `x` without the low word of its bits
+fn trunc_lo(x: f64) -> f64 { from_words(hi(x), 0) }
+
+/// 2^n, for -1022 <= n <= 1023
+fn pow2(n: i32) -> f64 { f64::from_bits(((0x3FF + n) as u64) << 52) }
+
+/// `x * 2^n`
+fn scalbn(x: f64, mut n: i32) -> f64 {
+	let mut y = x;
+	if n > 1023 {
+		y *= pow2(1023);
+		n -= 1023;
+		if n > 1023 {
+			y *= pow2(1023);
+			n -= 1023;
+			if n > 1023 {
+				n = 1023;
+			}
+		}
+	} else if n < -1022 {
+		// Down to 2^-969 first, to round only once in the subnormal range
+		y *= pow2(-969);
+		n += 969;
+		if n < -1022 {
+			y *= pow2(-969);
+			n += 969;
+			if n < -1022 {
+				n = -1022;
+			}
+		}
+	}
+	y * pow2(n)
+}
+
+/// Remainder of `x / y` with the sign of `x`, exact
+pub fn fmod(x: f64, y: f64) -> f64 {
+	if x.is_nan() {
+		return x + x;
+	}
+	if y.is_nan() {
+		return y + y;
+	}
+	let (mut ux, mut uy) = (x.to_bits(), y.to_bits());
+	let mut ex = (ux >> 52 & 0x7FF) as i32;
+	let mut ey = (uy >> 52 & 0x7FF) as i32;
+	let sx = ux >> 63;
+	if uy << 1 == 0 || ex == 0x7FF {
+		let z = x - x;
+		return z / z;
+	}
+	if ux << 1 <= uy << 1 {
+		if ux << 1 == uy << 1 {
+			return 0.0 * x;
+		}
+		return x;
+	}
+
+	// Integer significands
+	if ex == 0 {
+		let mut i = ux << 12;
+		while i >> 63 == 0 {
+			ex -= 1;
+			i <<= 1;
+		}
+		ux <<= -ex + 1;
+	} else {
+		ux &= u64::MAX >> 12;
+		ux |= 1 << 52;
+	}
+	if ey == 0 {
+		let mut i = uy << 12;
+		while i >> 63 == 0 {
+			ey -= 1;
+			i <<= 1;
+		}
+		uy <<= -ey + 1;
+	} else {
+		uy &= u64::MAX >> 12;
+		uy |= 1 << 52;
+	}
+
+	while ex > ey {
+		let i = ux.wrapping_sub(uy);
+		if i >> 63 == 0 {
+			if i == 0 {
+				return 0.0 * x;
+			}
+			ux = i;
+		}
+		ux <<= 1;
+		ex -= 1;
+	}
+	let i = ux.wrapping_sub(uy);
+	if i >> 63 == 0 {
+		if i == 0 {
+			return 0.0 * x;
+		}
+		ux = i;
+	}
+	while ux >> 52 == 0 {
+		ux <<= 1;
+		ex -= 1;
+	}
+
+	if ex > 0 {
+		ux -= 1 << 52;
+		ux |= (ex as u64) << 52;
+	} else {
+		ux >>= -ex + 1;
+	}
+	f64::from_bits(ux | sx << 63)
+}
+
+pub fn exp(x: f64) -> f64 {
+	const HALF: [f64; 2] = [0.5, -0.5];
+	const LN2_HI: [f64; 2] = [6.93147180369123816490e-01, -6.93147180369123816490e-01];
+	const LN2_LO: [f64; 2] = [1.90821492927058770002e-10, -1.90821492927058770002e-10];
+	const INV_LN2: f64 = 1.44269504088896338700e+00;
+	const P1: f64 = 1.66666666666666019037e-01;
+	const P2: f64 = -2.77777777770155933842e-03;
+	const P3: f64 = 6.61375632143793436117e-05;
+	const P4: f64 = -1.65339022054652515390e-06;
+	const P5: f64 = 4.13813679705723846039e-08;
+
+	let mut x = x;
+	let mut hx = hi(x);
+	let sign = (hx >> 31 & 1) as usize;
+	hx &= 0x7FFFFFFF;
+
+	if hx >= 0x40862E42 {
+		// |x| >= 709.78...
+		if x.is_nan() {
+			return x + x;
+		}
+		if hx >= 0x7FF00000 {
+			return if sign == 0 { x } else { 0.0 };
+		}
+		if x > 7.09782712893383973096e+02 {
+			return f64::INFINITY;
+		}
+		if x < -7.45133219101941108420e+02 {
+			return 0.0;
+		}
+	}
+
+	// x = k ln2 + r, |r| <= ln2 / 2
+	let (mut hi_, mut lo_, mut k) = (0.0, 0.0, 0);
+	if hx > 0x3FD62E42 {
+		if hx < 0x3FF0A2B2 {
+			hi_ = x - LN2_HI[sign];
+			lo_ = LN2_LO[sign];
+			k = 1 - sign as i32 - sign as i32;
+		} else {
+			k = (INV_LN2 * x + HALF[sign]) as i32;
+			let t = k as f64;
+			hi_ = x - t * LN2_HI[0];
+			lo_ = t * LN2_LO[0];
+		}
+		x = hi_ - lo_;
+	} else if hx < 0x3E300000 {
+		return 1.0 + x;
+	}
+
+	let t = x * x;
+	let c = x - t * (P1 + t * (P2 + t * (P3 + t * (P4 + t * P5))));
+	if k == 0 {
+		return 1.0 - ((x * c) / (c - 2.0) - x);
+	}
+	let y = 1.0 - ((lo_ - (x * c) / (2.0 - c)) - hi_);
+	if k >= -1021 {
+		with_hi(y, hi(y).wrapping_add(k << 20))
+	} else {
+		with_hi(y, hi(y).wrapping_add((k + 1000) << 20)) * pow2(-1000)
+	}
+}
+
+pub fn log(x: f64) -> f64 {
+	const LN2_HI: f64 = 6.93147180369123816490e-01;
+	const LN2_LO: f64 = 1.90821492927058770002e-10;
+	const TWO54: f64 = 1.80143985094819840000e+16;
+	const LG1: f64 = 6.666666666666735130e-01;
+	const LG2: f64 = 3.999999999940941908e-01;
+	const LG3: f64 = 2.857142874366239149e-01;
+	const LG4: f64 = 2.222219843214978396e-01;
+	const LG5: f64 = 1.818357216161805012e-01;
+	const LG6: f64 = 1.531383769920937332e-01;
+	const LG7: f64 = 1.479819860511658591e-01;
+
+	let mut x = x;
+	let mut hx = hi(x);
+	let mut k = 0;
+	if hx < 0x00100000 {
+		// Negative, zero or subnormal
+		if x.is_nan() {
+			return x + x;
+		}
+		if x == 0.0 {
+			return f64::NEG_INFINITY;
+		}
+		if hx < 0 {
+			return (x - x) / 0.0;
+		}
+		k -= 54;
+		x *= TWO54;
+		hx = hi(x);
+	}
+	if hx >= 0x7FF00000 {
+		return x + x;
+	}
+	k += (hx >> 20) - 1023;
+	hx &= 0x000FFFFF;
+	let i = (hx + 0x95F64) & 0x100000;
+	// x or x / 2 in [sqrt(2) / 2, sqrt(2)]
+	x = with_hi(x, hx | (i ^ 0x3FF00000));
+	k += i >> 20;
+	let f = x - 1.0;
+	let dk = k as f64;
+	if (0x000FFFFF & (2 + hx)) < 3 {
+		// -2^-20 <= f < 2^-20
+		if f == 0.0 {
+			if k == 0 {
+				return 0.0;
+			}
+			return dk * LN2_HI + dk * LN2_LO;
+		}
+		let r = f * f * (0.5 - 0.33333333333333333 * f);
+		if k == 0 {
+			return f - r;
+		}
+		return dk * LN2_HI - ((r - dk * LN2_LO) - f);
+	}
+	let s = f / (2.0 + f);
+	let z = s * s;
+	let w = z * z;
+	let t1 = w * (LG2 + w * (LG4 + w * LG6));
+	let t2 = z * (LG1 + w * (LG3 + w * (LG5 + w * LG7)));
+	let r = t2 + t1;
+	if (hx - 0x6147A) | (0x6B851 - hx) > 0 {
+		let hfsq = 0.5 * f * f;
+		if k == 0 {
+			return f - (hfsq - s * (hfsq + r));
+		}
+		dk * LN2_HI - ((hfsq - (s * (hfsq + r) + dk * LN2_LO)) - f)
+	} else {
+		if k == 0 {
+			return f - s * (f - r);
+		}
+		dk * LN2_HI - ((s * (f - r) - dk * LN2_LO) - f)
+	}
+}
+
+/// Exact product of `a` and `b` as a sum of two doubles
+fn two_prod(a: f64, b: f64) -> (f64, f64) {
+	const SPLIT: f64 = 134217729.0;
+	let p = a * b;
+	let c = SPLIT * a;
+	let (ah, al) = (c - (c - a), a - (c - (c - a)));
+	let c = SPLIT * b;
+	let (bh, bl) = (c - (c - b), b - (c - (c - b)));
+	(p, ((ah * bh - p) + ah * bl + al * bh) + al * bl)
+}
+
+/// `x - n π/2` as the sum of two doubles, for `|x| >= 2^20 π/2`: `x` is
+/// multiplied by as many bits of 2/π as needed with integers
+fn rem_pio2_large(x: f64) -> (i32, f64, f64) {
+	const PIO2_HI: f64 = 1.57079632679489655800e+00;
+	const PIO2_LO: f64 = 6.12323399573676603587e-17;
+
+	let bits = x.to_bits();
+	let m = bits & (u64::MAX >> 12) | 1 << 52;
+	// x = m 2^e, the bits of 2/π before s only add multiples of 4 to x 2/π
+	let e = (bits >> 52 & 0x7FF) as i32 - 1075;
+	let start = (e - 2 + 64) as usize;
+	let (q, r) = (start / 64, start % 64);
+	let word = |k: usize| {
+		if r == 0 {
+			TWO_OVER_PI[q + k]
+		} else {
+			TWO_OVER_PI[q + k] << r | TWO_OVER_PI[q + k + 1] >> (64 - r)
+		}
+	};
+	// m times the 192 bits of 2/π from s, the binary point is at bit 190
+	let t = m as u128 * word(2) as u128;
+	let p0 = t as u64;
+	let t = m as u128 * word(1) as u128 + (t >> 64);
+	let p1 = t as u64;
+	let t = m as u128 * word(0) as u128 + (t >> 64);
+	let p2 = t as u64;
+	let frac = (p2 as u128) << 66 | (p1 as u128) << 2 | (p0 >> 62) as u128;
+	// Rounded to the nearest quadrant, the fraction in [-1/2, 1/2)
+	let n = ((p2 >> 62) as i32 + (frac >> 127) as i32) & 3;
+	let frac = frac as i128;
+	let mag = frac.unsigned_abs();
+	if mag == 0 {
+		return if bits >> 63 == 0 { (n, 0.0, 0.0) } else { (-n, -0.0, -0.0) };
+	}
+	let lz = mag.leading_zeros() as i32;
+	let top = mag << lz;
+	let a = (top >> 75) as i64 as f64 * pow2(-53 - lz);
+	let b = ((top >> 22) as u64 & (u64::MAX >> 11)) as i64 as f64 * pow2(-106 - lz);
+	let (a, b) = if frac < 0 { (-a, -b) } else { (a, b) };
+	let (p, e) = two_prod(a, PIO2_HI);
+	let tail = e + (a * PIO2_LO + b * PIO2_HI);
+	let y0 = p + tail;
+	let y1 = tail - (y0 - p);
+	if bits >> 63 == 0 {
+		(n, y0, y1)
+	} else {
+		(-n, -y0, -y1)
+	}
+}
+
+/// `x - n π/2` as the sum of two doubles, with `n`
+fn rem_pio2(x: f64) -> (i32, f64, f64) {
+	const TO_INT: f64 = 6755399441055744.0;
+	const INV_PIO2: f64 = 6.36619772367581382433e-01;
+	const PIO2_1: f64 = 1.57079632673412561417e+00;
+	const PIO2_1T: f64 = 6.07710050650619224932e-11;
+	const PIO2_2: f64 = 6.07710050630396597660e-11;
+	const PIO2_2T: f64 = 2.02226624879595063154e-21;
+	const PIO2_3: f64 = 2.02226624871116645580e-21;
+	const PIO2_3T: f64 = 8.47842766036889956997e-32;
+
+	let ix = hi(x) & 0x7FFFFFFF;
+	if ix >= 0x413921FB {
+		return rem_pio2_large(x);
+	}
+	let fn_ = x * INV_PIO2 + TO_INT - TO_INT;
+	let n = fn_ as i32;
+	let mut r = x - fn_ * PIO2_1;
+	// Good to 85 bits, then to 118 and 151 bits if r is too small
+	let mut w = fn_ * PIO2_1T;
+	let mut y0 = r - w;
+	let ex = ix >> 20;
+	if ex - (hi(y0) >> 20 & 0x7FF) > 16 {
+		let t = r;
+		w = fn_ * PIO2_2;
+		r = t - w;
+		w = fn_ * PIO2_2T - ((t - r) - w);
+		y0 = r - w;
+		if ex - (hi(y0) >> 20 & 0x7FF) > 49 {
+			let t = r;
+			w = fn_ * PIO2_3;
+			r = t - w;
+			w = fn_ * PIO2_3T - ((t - r) - w);
+			y0 = r - w;
+		}
+	}
+	(n, y0, (r - y0) - w)
+}
+
+/// sin(x + y) for |x| <= π/4, `y` being the tail of `x`
+fn kernel_sin(x: f64, y: f64) -> f64 {
+	const S1: f64 = -1.66666666666666324348e-01;
+	const S2: f64 = 8.33333333332248946124e-03;
+	const S3: f64 = -1.98412698298579493134e-04;
+	const S4: f64 = 2.75573137070700676789e-06;
+	const S5: f64 = -2.50507602534068634195e-08;
+	const S6: f64 = 1.58969099521155010221e-10;
+
+	let z = x * x;
+	let v = z * x;
+	let r = S2 + z * (S3 + z * (S4 + z * (S5 + z * S6)));
+	x - ((z * (0.5 * y - v * r) - y) - v * S1)
+}
+
+/// cos(x + y) for |x| <= π/4, `y` being the tail of `x`
+fn kernel_cos(x: f64, y: f64) -> f64 {
+	const C1: f64 = 4.16666666666666019037e-02;
+	const C2: f64 = -1.38888888888741095749e-03;
+	const C3: f64 = 2.48015872894767294178e-05;
+	const C4: f64 = -2.75573143513906633035e-07;
+	const C5: f64 = 2.08757232129817482790e-09;
+	const C6: f64 = -1.13596475577881948265e-11;
+
+	let z = x * x;
+	let w = z * z;
+	let r = z * (C1 + z * (C2 + z * C3)) + w * w * (C4 + z * (C5 + z * C6));
+	let hz = 0.5 * z;
+	let w = 1.0 - hz;
+	w + (((1.0 - w) - hz) + (z * r - x * y))
+}
+
+pub fn sin(x: f64) -> f64 {
+	let ix = hi(x) & 0x7FFFFFFF;
+	if ix <= 0x3FE921FB {
+		if ix < 0x3E500000 {
+			return x;
+		}
+		return kernel_sin(x, 0.0);
+	}
+	if ix >= 0x7FF00000 {
+		return x - x;
+	}
+	let (n, y0, y1) = rem_pio2(x);
+	match n & 3 {
+		| 0 => kernel_sin(y0, y1),
+		| 1 => kernel_cos(y0, y1),
+		| 2 => -kernel_sin(y0, y1),
+		| _ => -kernel_cos(y0, y1),
+	}
+}
+
+pub fn cos(x: f64) -> f64 {
+	let ix = hi(x) & 0x7FFFFFFF;
+	if ix <= 0x3FE921FB {
+		if ix < 0x3E46A09E {
+			return 1.0;
+		}
+		return kernel_cos(x, 0.0);
+	}
+	if ix >= 0x7FF00000 {
+		return x - x;
+	}
+	let (n, y0, y1) = rem_pio2(x);
+	match n & 3 {
+		| 0 => kernel_cos(y0, y1),
+		| 1 => -kernel_sin(y0, y1),
+		| 2 => -kernel_cos(y0, y1),
+		| _ => kernel_sin(y0, y1),
+	}
+}
+
+pub fn pow(x: f64, y: f64) -> f64 {
+	const BP: [f64; 2] = [1.0, 1.5];
+	const DP_H: [f64; 2] = [0.0, 5.84962487220764160156e-01];
+	const DP_L: [f64; 2] = [0.0, 1.35003920212974897128e-08];
+	const TWO53: f64 = 9007199254740992.0;
+	const HUGE: f64 = 1.0e300;
+	const TINY: f64 = 1.0e-300;
+	const L1: f64 = 5.99999999999994648725e-01;
+	const L2: f64 = 4.28571428578550184252e-01;
+	const L3: f64 = 3.33333329818377432918e-01;
+	const L4: f64 = 2.72728123808534006489e-01;
+	const L5: f64 = 2.30660745775561754067e-01;
+	const L6: f64 = 2.06975017800338417784e-01;
+	const P1: f64 = 1.66666666666666019037e-01;
+	const P2: f64 = -2.77777777770155933842e-03;
+	const P3: f64 = 6.61375632143793436117e-05;
+	const P4: f64 = -1.65339022054652515390e-06;
+	const P5: f64 = 4.13813679705723846039e-08;
+	const LG2: f64 = 6.93147180559945286227e-01;
+	const LG2_H: f64 = 6.93147182464599609375e-01;
+	const LG2_L: f64 = -1.90465429995776804525e-09;
+	const OVT: f64 = 8.0085662595372944372e-17;
+	const CP: f64 = 9.61796693925975554329e-01;
+	const CP_H: f64 = 9.61796700954437255859e-01;
+	const CP_L: f64 = -7.02846165095275826516e-09;
+	const IVLN2: f64 = 1.44269504088896338700e+00;
+	const IVLN2_H: f64 = 1.44269502162933349609e+00;
+	const IVLN2_L: f64 = 1.92596299112661746887e-08;
+
+	let (hx, lx) = (hi(x), lo(x));
+	let (hy, ly) = (hi(y), lo(y));
+	let (mut ix, iy) = (hx & 0x7FFFFFFF, hy & 0x7FFFFFFF);
+
+	// x^0 = 1 and 1^y = 1, even for NaN
+	if iy as u32 | ly == 0 || hx == 0x3FF00000 && lx == 0 {
+		return 1.0;
+	}
+	if x.is_nan() {
+		return x + x;
+	}
+	if y.is_nan() {
+		return y + y;
+	}
+
+	// 0 if y is not an integer, 1 if it is odd and 2 if it is even, only for a
+	// negative x
+	let mut yisint = 0;
+	if hx < 0 {
+		if iy >= 0x43400000 {
+			yisint = 2;
+		} else if iy >= 0x3FF00000 {
+			let k = (iy >> 20) - 0x3FF;
+			if k > 20 {
+				let j = ly >> (52 - k);
+				if j << (52 - k) == ly {
+					yisint = 2 - (j & 1) as i32;
+				}
+			} else if ly == 0 {
+				let j = iy >> (20 - k);
+				if j << (20 - k) == iy {
+					yisint = 2 - (j & 1);
+				}
+			}
+		}
+	}
+
+	if ly == 0 {
+		if iy == 0x7FF00000 {
+			// y is +-inf
+			if (ix - 0x3FF00000) as u32 | lx == 0 {
+				return 1.0;
+			}
+			if ix >= 0x3FF00000 {
+				return if hy >= 0 { y } else { 0.0 };
+			}
+			return if hy < 0 { -y } else { 0.0 };
+		}
+		if iy == 0x3FF00000 {
+			return if hy < 0 { 1.0 / x } else { x };
+		}
+		if hy == 0x40000000 {
+			return x * x;
+		}
+		if hy == 0x3FE00000 && hx >= 0 {
+			return x.sqrt();
+		}
+	}
+
+	let mut ax = x.abs();
+	if lx == 0 && (ix == 0x7FF00000 || ix == 0 || ix == 0x3FF00000) {
+		// x is +-0, +-inf or +-1
+		let mut z = ax;
+		if hy < 0 {
+			z = 1.0 / z;
+		}
+		if hx < 0 {
+			if (ix - 0x3FF00000) | yisint == 0 {
+				// (-1)^non integer
+				z = (z - z) / (z - z);
+			} else if yisint == 1 {
+				z = -z;
+			}
+		}
+		return z;
+	}
+
+	// x < 0 if n is 0
+	let mut n = (hx >> 31) + 1;
+	if n | yisint == 0 {
+		return (x - x) / (x - x);
+	}
+	let s = if n | (yisint - 1) == 0 { -1.0 } else { 1.0 };
+
+	// log2(x) = t1 + t2
+	let (t1, t2);
+	if iy > 0x41E00000 {
+		// |y| > 2^31
+		if iy > 0x43F00000 {
+			if ix <= 0x3FEFFFFF {
+				return if hy < 0 { HUGE * HUGE } else { TINY * TINY };
+			}
+			if ix >= 0x3FF00000 {
+				return if hy > 0 { HUGE * HUGE } else { TINY * TINY };
+			}
+		}
+		if ix < 0x3FEFFFFF {
+			return if hy < 0 { s * HUGE * HUGE } else { s * TINY * TINY };
+		}
+		if ix > 0x3FF00000 {
+			return if hy > 0 { s * HUGE * HUGE } else { s * TINY * TINY };
+		}
+		// |1 - x| <= 2^-20, log(x) is x - x^2/2 + x^3/3 - x^4/4
+		let t = ax - 1.0;
+		let w = (t * t) * (0.5 - t * (0.3333333333333333333333 - t * 0.25));
+		let u = IVLN2_H * t;
+		let v = t * IVLN2_L - w * IVLN2;
+		t1 = trunc_lo(u + v);
+		t2 = v - (t1 - u);
+	} else {
+		n = 0;
+		if ix < 0x00100000 {
+			ax *= TWO53;
+			n -= 53;
+			ix = hi(ax);
+		}
+		n += (ix >> 20) - 0x3FF;
+		let j = ix & 0x000FFFFF;
+		ix = j | 0x3FF00000;
+		let k;
+		if j <= 0x3988E {
+			k = 0;
+		} else if j < 0xBB67A {
+			k = 1;
+		} else {
+			k = 0;
+			n += 1;
+			ix -= 0x00100000;
+		}
+		ax = with_hi(ax, ix);
+
+		// ss = s_h + s_l = (x - 1) / (x + 1) or (x - 1.5) / (x + 1.5)
+		let u = ax - BP[k];
+		let v = 1.0 / (ax + BP[k]);
+		let ss = u * v;
+		let s_h = trunc_lo(ss);
+		let t_h =
+			from_words(((ix >> 1) | 0x20000000) + 0x00080000 + ((k as i32) << 18), 0);
+		let t_l = ax - (t_h - BP[k]);
+		let s_l = v * ((u - s_h * t_h) - s_h * t_l);
+		let s2 = ss * ss;
+		let mut r =
+			s2 * s2 * (L1 + s2 * (L2 + s2 * (L3 + s2 * (L4 + s2 * (L5 + s2 * L6)))));
+		r += s_l * (s_h + ss);
+		let s2 = s_h * s_h;
+		let t_h = trunc_lo(3.0 + s2 + r);
+		let t_l = r - ((t_h - 3.0) - s2);
+		let u = s_h * t_h;
+		let v = s_l * t_h + t_l * ss;
+		let p_h = trunc_lo(u + v);
+		let p_l = v - (p_h - u);
+		let z_h = CP_H * p_h;
+		let z_l = CP_L * p_h + p_l * CP + DP_L[k];
+		let t = n as f64;
+		t1 = trunc_lo(((z_h + z_l) + DP_H[k]) + t);
+		t2 = z_l - (((t1 - t) - DP_H[k]) - z_h);
+	}
+
+	// y log2(x) = p_h + p_l
+	let y1 = trunc_lo(y);
+	let p_l = (y - y1) * t1 + y * t2;
+	let mut p_h = y1 * t1;
+	let z = p_l + p_h;
+	let j = hi(z);
+	let i = lo(z);
+	if j >= 0x40900000 {
+		// z >= 1024
+		if (j - 0x40900000) as u32 | i != 0 || p_l + OVT > z - p_h {
+			return s * HUGE * HUGE;
+		}
+	} else if j & 0x7FFFFFFF >= 0x4090CC00 {
+		// z <= -1075
+		if (j as u32).wrapping_sub(0xC090CC00) | i != 0 || p_l <= z - p_h {
+			return s * TINY * TINY;
+		}
+	}
+
+	// 2^(p_h + p_l)
+	let i = j & 0x7FFFFFFF;
+	let mut k = (i >> 20) - 0x3FF;
+	n = 0;
+	if i > 0x3FE00000 {
+		// |z| > 0.5, n = [z + 0.5]
+		n = j + (0x00100000 >> (k + 1));
+		k = ((n & 0x7FFFFFFF) >> 20) - 0x3FF;
+		let t = from_words(n & !(0x000FFFFF >> k), 0);
+		n = ((n & 0x000FFFFF) | 0x00100000) >> (20 - k);
+		if j < 0 {
+			n = -n;
+		}
+		p_h -= t;
+	}
+	let t = trunc_lo(p_l + p_h);
+	let u = t * LG2_H;
+	let v = (p_l - (t - p_h)) * LG2 + t * LG2_L;
+	let z = u + v;
+	let w = v - (z - u);
+	let t = z * z;
+	let t1 = z - t * (P1 + t * (P2 + t * (P3 + t * (P4 + t * P5))));
+	let r = (z * t1) / (t1 - 2.0) - (w + z * w);
+	let z = 1.0 - (r - z);
+	let j = hi(z).wrapping_add(n << 20);
+	let z = if (j >> 20) <= 0 { scalbn(z, n) } else { with_hi(z, j) };
+	s * z
+}
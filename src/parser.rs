@@ -2,12 +2,53 @@
 use std::{collections::HashMap, fmt::Display};
 
 use crate::{
-	annotation::{Annotation, Type},
+	annotation::{Annotation, Expansion, Position, Type},
 	lexer::{Lexer, Token, TokenType},
-	report::Reporter,
+	report::{Abort, Level, Reporter},
+	Cli,
+	Mode,
 };
 //#endregion
 
+/// Integer operation of the `+%`, `+|` and `+?` families
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum IntOp {
+	Add,
+	Sub,
+	Mul,
+}
+
+impl Display for IntOp {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			| IntOp::Add => write!(f, "Add"),
+			| IntOp::Sub => write!(f, "Sub"),
+			| IntOp::Mul => write!(f, "Mul"),
+		}
+	}
+}
+
+/// What an integer operation does when its result does not fit in an i64
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+	/// `+%`, the result modulo 2^64
+	Wrapping,
+	/// `+|`, the bound of i64 it went past
+	Saturating,
+	/// `+?`, the wrapped result and whether it overflowed
+	Checked,
+}
+
+impl Display for Overflow {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			| Overflow::Wrapping => write!(f, "Wrapping"),
+			| Overflow::Saturating => write!(f, "Saturating"),
+			| Overflow::Checked => write!(f, "Checked"),
+		}
+	}
+}
+
 #[derive(Clone)]
 pub enum OpType {
 	PushI(i64),
@@ -17,11 +58,25 @@ pub enum OpType {
 	Add(Type, Type),
 	Sub(Type, Type),
 	Mul(Type, Type),
+	IntArith(IntOp, Overflow),
 	Div(Type, Type),
 	Mod(Type, Type),
+	DivMod(bool),
 	Increment(Type),
 	Decrement(Type),
 	Swap,
+	/// `a b c -- b c a`
+	Rot,
+	/// `a b c -- c a b`
+	Unrot,
+	/// `a b -- b`
+	Nip,
+	/// `a b -- b a b`
+	Tuck,
+	/// `a b c d -- c d a b`
+	Swap2,
+	/// Moves the value under the n others to the top
+	Roll(i64),
 	Drop(i64),
 	Over(i64),
 	Dup(i64),
@@ -38,6 +93,30 @@ pub enum OpType {
 	Lte(Type, Type),
 	Gte(Type, Type),
 	Syscall(usize, usize),
+	/// Exits with the status on top of the stack
+	Exit,
+	/// Converts an i64 to the nearest f64
+	ToF64,
+	/// Converts an f64 to an i64, truncating it
+	ToI64,
+	/// Rounds an f64 to the nearest integer, ties to even
+	Round,
+	Floor,
+	Ceil,
+	/// Remainder of the division of two f64, with the sign of the dividend
+	Fmod,
+	Sqrt,
+	Abs,
+	Neg,
+	/// Smallest of two f64, the second one if they are unordered like `minsd`
+	Min,
+	/// Largest of two f64, the second one if they are unordered like `maxsd`
+	Max,
+	Pow,
+	Exp,
+	Log,
+	Sin,
+	Cos,
 	PushStr(String),
 	Argc,
 	Argv,
@@ -50,9 +129,20 @@ pub enum OpType {
 	Store32,
 	Store64,
 	Cast(Type),
-	Nop,
+	/// Logical shift, by 64 or more gives 0
 	ShiftR,
+	/// Arithmetic shift, by 64 or more gives the sign
+	ShiftRA,
 	ShiftL,
+	/// Rotates by the count modulo 64
+	RotL,
+	RotR,
+	Xor,
+	Popcount,
+	/// Leading zeros, 64 for 0
+	Clz,
+	/// Trailing zeros, 64 for 0
+	Ctz,
 	BitAnd,
 	And,
 	BitOr,
@@ -60,6 +150,11 @@ pub enum OpType {
 	Not,
 	Mem(Option<String>),
 	SetOver(i64),
+	/// Inline assembly: (consumed types, produced types, code)
+	Asm(Vec<Type>, Vec<Type>, String),
+	/// Call to a function of the program embedding the simulator: (name,
+	/// consumed types, produced types)
+	Host(String, Vec<Type>, Vec<Type>),
 }
 
 #[derive(Clone)]
@@ -79,12 +174,21 @@ impl Display for OpType {
 			| Add(..) => write!(f, "Add"),
 			| Sub(..) => write!(f, "Sub"),
 			| Mul(..) => write!(f, "Mul"),
+			| IntArith(op, overflow) => write!(f, "{op}{overflow}"),
 			| Div(..) => write!(f, "Div"),
 			| Mod(..) => write!(f, "Mod"),
+			| DivMod(false) => write!(f, "DivMod"),
+			| DivMod(true) => write!(f, "DivModFloor"),
 			| Increment(_) => write!(f, "Increment"),
 			| Decrement(_) => write!(f, "Decrement"),
 			| Drop(n) => write!(f, "Drop{}", n),
 			| Swap => write!(f, "Swap"),
+			| Rot => write!(f, "Rot"),
+			| Unrot => write!(f, "Unrot"),
+			| Nip => write!(f, "Nip"),
+			| Tuck => write!(f, "Tuck"),
+			| Swap2 => write!(f, "Swap2"),
+			| Roll(n) => write!(f, "Roll{}", n),
 			| Over(n) => write!(f, "Over{}", n),
 			| Dup(n) => write!(f, "Dup{}", n),
 			| If(..) => write!(f, "If"),
@@ -93,7 +197,6 @@ impl Display for OpType {
 			| End(..) => write!(f, "End"),
 			| While(..) => write!(f, "While"),
 			| Do(..) => write!(f, "Do"),
-			| Nop => unreachable!(),
 			| Eq(..) => write!(f, "Eq"),
 			| Neq(..) => write!(f, "Neq"),
 			| Lt(..) => write!(f, "Lt"),
@@ -101,20 +204,44 @@ impl Display for OpType {
 			| Lte(..) => write!(f, "Lte"),
 			| Gte(..) => write!(f, "Gte"),
 			| Syscall(syscode, ..) => write!(f, "Syscall({})", syscode),
+			| Exit => write!(f, "Exit"),
+			| ToF64 => write!(f, "ToF64"),
+			| ToI64 => write!(f, "ToI64"),
+			| Round => write!(f, "Round"),
+			| Floor => write!(f, "Floor"),
+			| Ceil => write!(f, "Ceil"),
+			| Fmod => write!(f, "Fmod"),
+			| Sqrt => write!(f, "Sqrt"),
+			| Abs => write!(f, "Abs"),
+			| Neg => write!(f, "Neg"),
+			| Min => write!(f, "Min"),
+			| Max => write!(f, "Max"),
+			| Pow => write!(f, "Pow"),
+			| Exp => write!(f, "Exp"),
+			| Log => write!(f, "Log"),
+			| Sin => write!(f, "Sin"),
+			| Cos => write!(f, "Cos"),
 			| PushStr(lit) => write!(f, "PushStr({})", lit),
 			| Argc => write!(f, "Argc"),
 			| Argv => write!(f, "Argv"),
 			| Load8 => write!(f, "Load8"),
-			| Load16 => write!(f, "Load8"),
-			| Load32 => write!(f, "Load8"),
-			| Load64 => write!(f, "Load8"),
+			| Load16 => write!(f, "Load16"),
+			| Load32 => write!(f, "Load32"),
+			| Load64 => write!(f, "Load64"),
 			| Store8 => write!(f, "Store8"),
-			| Store16 => write!(f, "Store8"),
-			| Store32 => write!(f, "Store8"),
-			| Store64 => write!(f, "Store8"),
+			| Store16 => write!(f, "Store16"),
+			| Store32 => write!(f, "Store32"),
+			| Store64 => write!(f, "Store64"),
 			| Cast(typ) => write!(f, "Cast({typ})"),
 			| ShiftR => write!(f, "ShiftR"),
+			| ShiftRA => write!(f, "ShiftRA"),
 			| ShiftL => write!(f, "ShiftL"),
+			| RotL => write!(f, "RotL"),
+			| RotR => write!(f, "RotR"),
+			| Xor => write!(f, "Xor"),
+			| Popcount => write!(f, "Popcount"),
+			| Clz => write!(f, "Clz"),
+			| Ctz => write!(f, "Ctz"),
 			| And => write!(f, "And"),
 			| Or => write!(f, "Or"),
 			| Not => write!(f, "Not"),
@@ -122,6 +249,8 @@ impl Display for OpType {
 			| BitOr => write!(f, "BitOr"),
 			| Mem(_) => write!(f, "Mem"),
 			| SetOver(size) => write!(f, "SetOver({size})"),
+			| Asm(..) => write!(f, "Asm"),
+			| Host(name, ..) => write!(f, "Host({name})"),
 		}
 	}
 }
@@ -139,16 +268,27 @@ pub struct Program {
 	pub strings:              Vec<String>,
 	pub memory_regions:       HashMap<String, i64>,
 	pub memory_regions_order: Vec<String>,
+	/// Every macro defined, expanded or not
+	pub macros:               HashMap<String, Macro>,
+	/// Plain integer `+`, `-` and `*` are runtime errors when they overflow
+	/// (`--overflow-checks`)
+	pub overflow_checks:      bool,
 }
 
 impl Program {
-	pub fn new(parser: Parser) -> Self {
+	pub fn new(mut parser: Parser) -> Self {
+		if parser.cli.exit_with_stack {
+			// The program exits with the top of its stack once its last op ran
+			parser.ops.push(Op { typ: OpType::Exit, annot: parser.last_annot.clone() });
+		}
 		Self {
 			ops:                  parser.ops,
 			reporter:             parser.reporter,
 			strings:              parser.strings,
 			memory_regions:       parser.memory_regions,
 			memory_regions_order: parser.memory_regions_order,
+			macros:               parser.macros,
+			overflow_checks:      parser.cli.overflow_checks,
 		}
 	}
 
@@ -163,47 +303,86 @@ impl Program {
 	}
 
 	pub fn exit(&mut self, code: i32) -> ! { self.reporter.exit(code) }
+
+	pub fn abort(&mut self) -> Abort { self.reporter.abort() }
+}
+
+#[derive(Clone)]
+pub struct Macro {
+	pub params: Vec<String>,
+	pub body:   Vec<Token>,
 }
 
 #[derive(Clone)]
 pub struct Parser {
 	pub reporter:             Reporter,
 	pub ops:                  Vec<Op>,
-	pub macros:               HashMap<String, Vec<Op>>,
+	/// Macro bodies are kept as tokens and only parsed when the macro is used.
+	pub macros:               HashMap<String, Macro>,
 	pub strings:              Vec<String>,
 	pub memory_regions:       HashMap<String, i64>,
 	pub memory_regions_order: Vec<String>,
 	included:                 Vec<String>,
+	/// Names usable in `#if`, built-in ones (`SIM`, `COM`, `DEBUG`) and the ones
+	/// given with `-D NAME=VALUE`.
+	pub defines:              HashMap<String, i64>,
+	/// Macros currently being expanded, outermost first.
+	expansion_stack:          Vec<Expansion>,
+	/// Position of the last token parsed, where the program ends
+	last_annot:               Annotation,
+	pub cli:                  Cli<String>,
 }
 
 impl Parser {
-	pub fn new(lexer: Lexer) -> Self {
-		let mut ops = lexer.clone().collect::<Vec<_>>();
+	pub fn new(lexer: Lexer, cli: &Cli<String>) -> Result<Self, Abort> {
+		let mut tokens = lexer.clone();
+		let ops = tokens.by_ref().collect::<Vec<_>>();
+		if tokens.reporter.has(Level::Error) {
+			return Err(tokens.reporter.abort());
+		}
+		let last_annot = lexer.get_annot();
 		let mut itself = Self {
-			reporter:             lexer.reporter,
-			strings:              lexer.strings,
-			ops:                  vec![],
-			macros:               HashMap::new(),
-			memory_regions:       HashMap::new(),
+			reporter: lexer.reporter,
+			strings: lexer.strings,
+			ops: vec![],
+			macros: HashMap::new(),
+			memory_regions: HashMap::new(),
 			memory_regions_order: vec![],
-			included:             vec![],
+			included: vec![],
+			defines: HashMap::new(),
+			expansion_stack: vec![],
+			last_annot,
+			cli: cli.clone(),
 		};
+		itself.collect_defines();
+		itself.push_tokens(ops)?;
+		Ok(itself)
+	}
+
+	/// Parses tokens following the ones already parsed, they can use the macros
+	/// and `decla` regions defined before
+	pub fn push_tokens(&mut self, mut ops: Vec<Token>) -> Result<(), Abort> {
+		if let Some(last) = ops.last() {
+			self.last_annot = last.annot.clone();
+		}
+		self.preprocess(&mut ops)?;
+		self.collect_macros(&mut ops)?;
 		while !ops.is_empty() {
-			for op in itself.ops_from_first_token(&mut ops) {
-				itself.ops.push(op);
+			for op in self.ops_from_first_token(&mut ops)? {
+				self.ops.push(op);
 			}
 		}
-		itself
+		Ok(())
 	}
 
-	fn ops_from_first_token(&mut self, ops: &mut Vec<Token>) -> Vec<Op> {
+	fn ops_from_first_token(&mut self, ops: &mut Vec<Token>) -> Result<Vec<Op>, Abort> {
 		use OpType as O;
 		use TokenType as T;
 		if ops.is_empty() {
-			return vec![];
+			return Ok(vec![]);
 		}
 		let Token { typ, annot } = ops.remove(0);
-		match typ {
+		Ok(match typ {
 			| T::IntLit(v) => vec![Op { typ: O::PushI(v), annot }],
 			| T::FloatLit(v) => vec![Op { typ: O::PushF(v), annot }],
 			| T::BoolLit(v) => vec![Op { typ: O::PushB(v), annot }],
@@ -217,8 +396,12 @@ impl Parser {
 			| T::Plus => vec![Op { typ: O::Add(Type::I64, Type::I64), annot }],
 			| T::Minus => vec![Op { typ: O::Sub(Type::I64, Type::I64), annot }],
 			| T::Star => vec![Op { typ: O::Mul(Type::I64, Type::I64), annot }],
+			| T::IntArith(op, overflow) => {
+				vec![Op { typ: O::IntArith(op, overflow), annot }]
+			}
 			| T::Slash => vec![Op { typ: O::Div(Type::I64, Type::I64), annot }],
 			| T::Modulo => vec![Op { typ: O::Mod(Type::I64, Type::I64), annot }],
+			| T::DivMod(floor) => vec![Op { typ: O::DivMod(floor), annot }],
 			| T::DoubleMinus => vec![Op { typ: O::Decrement(Type::I64), annot }],
 			| T::DoublePlus => vec![Op { typ: O::Increment(Type::I64), annot }],
 			| T::If => vec![Op { typ: O::If(0), annot }],
@@ -233,6 +416,23 @@ impl Parser {
 			| T::Gt => vec![Op { typ: O::Gt(Type::I64, Type::I64), annot }],
 			| T::Lte => vec![Op { typ: O::Lte(Type::I64, Type::I64), annot }],
 			| T::Gte => vec![Op { typ: O::Gte(Type::I64, Type::I64), annot }],
+			| T::Exit => vec![Op { typ: O::Exit, annot }],
+			| T::ToF64 => vec![Op { typ: O::ToF64, annot }],
+			| T::ToI64 => vec![Op { typ: O::ToI64, annot }],
+			| T::Round => vec![Op { typ: O::Round, annot }],
+			| T::Floor => vec![Op { typ: O::Floor, annot }],
+			| T::Ceil => vec![Op { typ: O::Ceil, annot }],
+			| T::Fmod => vec![Op { typ: O::Fmod, annot }],
+			| T::Sqrt => vec![Op { typ: O::Sqrt, annot }],
+			| T::Abs => vec![Op { typ: O::Abs, annot }],
+			| T::Neg => vec![Op { typ: O::Neg, annot }],
+			| T::Min => vec![Op { typ: O::Min, annot }],
+			| T::Max => vec![Op { typ: O::Max, annot }],
+			| T::Pow => vec![Op { typ: O::Pow, annot }],
+			| T::Exp => vec![Op { typ: O::Exp, annot }],
+			| T::Log => vec![Op { typ: O::Log, annot }],
+			| T::Sin => vec![Op { typ: O::Sin, annot }],
+			| T::Cos => vec![Op { typ: O::Cos, annot }],
 			| T::Argc => vec![Op { typ: O::Argc, annot }],
 			| T::Argv => vec![Op { typ: O::Argv, annot }],
 			| T::Load8 => vec![Op { typ: O::Load8, annot }],
@@ -244,65 +444,65 @@ impl Parser {
 			| T::Store32 => vec![Op { typ: O::Store32, annot }],
 			| T::Store64 => vec![Op { typ: O::Store64, annot }],
 			| T::Swap => vec![Op { typ: O::Swap, annot }],
+			| T::Rot => vec![Op { typ: O::Rot, annot }],
+			| T::Unrot => vec![Op { typ: O::Unrot, annot }],
+			| T::Nip => vec![Op { typ: O::Nip, annot }],
+			| T::Tuck => vec![Op { typ: O::Tuck, annot }],
+			| T::Swap2 => vec![Op { typ: O::Swap2, annot }],
+			| T::Roll => vec![Op { typ: O::Roll(self.expect_size_arg(ops)?), annot }],
 			| T::Drop => {
-				vec![Op { typ: O::Drop(self.expect_optional_size_arg(ops)), annot }]
+				vec![Op { typ: O::Drop(self.expect_optional_size_arg(ops)?), annot }]
 			}
 			| T::Over => {
-				vec![Op { typ: O::Over(self.expect_optional_size_arg(ops)), annot }]
+				vec![Op { typ: O::Over(self.expect_optional_size_arg(ops)?), annot }]
 			}
 			| T::Dup => {
-				vec![Op { typ: O::Dup(self.expect_optional_size_arg(ops)), annot }]
+				vec![Op { typ: O::Dup(self.expect_optional_size_arg(ops)?), annot }]
 			}
 			| T::Syscall => {
-				let arg = self.expect_size_arg(ops) as usize;
+				let arg = self.expect_size_arg(ops)? as usize;
 				vec![Op { typ: O::Syscall(arg, get_arg_count_from_syscode(&arg)), annot }]
 			}
 			| T::Macro => {
-				let name = self.expect_id(ops);
-				self.expect(ops, T::OCurly);
-				let macro_ops = self.collect_until(ops, T::CCurly);
-				self.macros.insert(name, macro_ops);
-				self.expect(ops, T::CCurly);
-				self.ops_from_first_token(ops)
-			}
-			| T::Id(name) => {
-				if let Some(macro_ops) = self.macros.get(&name) {
-					macro_ops.clone()
-				} else {
-					self.add_error(format!(
-						"{}: Undefined macro: {name}",
-						annot.get_pos()
-					))
-					.exit(1);
-				}
+				self.define_macro(ops)?;
+				self.ops_from_first_token(ops)?
 			}
+			| T::Id(name) => self.expand_macro(name, annot, ops)?,
 			| T::Include => {
-				let path = self.expect_string_lit(ops);
+				let path = self.expect_string_lit(ops)?;
 				if self.included.contains(&path) {
-					return vec![];
+					return Ok(vec![]);
 				}
-				let included_program_content = std::fs::read_to_string(path.clone())
-					.unwrap_or_else(|e| {
-						self.add_error(format!(
-							"{}: Unable to read file {path} for include: {e}",
-							annot.get_pos()
-						))
-						.exit(1)
-					});
-				let parsed_include = Parser::new(Lexer::new(
-					included_program_content.chars().collect(),
-					path.clone(),
-					self.reporter.clone(),
-				));
+				let included_program_content = match std::fs::read_to_string(&path) {
+					| Ok(content) => content,
+					| Err(e) => {
+						return Err(self
+							.add_error(format!(
+								"{}: Unable to read file {path} for include: {e}",
+								annot.get_pos()
+							))
+							.abort())
+					}
+				};
+				let parsed_include = Parser::new(
+					Lexer::new(
+						included_program_content.chars().collect(),
+						path.clone(),
+						self.reporter.clone(),
+					),
+					&self.cli,
+				)?;
 				self.macros.extend(parsed_include.macros);
 				self.strings.extend(parsed_include.strings);
 
 				for (name, size) in parsed_include.memory_regions.iter() {
 					if self.memory_regions.contains_key(name) {
-						self.add_error(format!(
-							"Duplicate memory region: {name} from included file: {path}",
-						))
-						.exit(1);
+						return Err(self
+							.add_error(format!(
+								"Duplicate memory region: {name} from included file: \
+								 {path}",
+							))
+							.abort());
 					} else {
 						self.memory_regions.insert(name.clone(), *size);
 					}
@@ -312,9 +512,16 @@ impl Parser {
 				self.memory_regions_order.extend(parsed_include.memory_regions_order);
 				parsed_include.ops
 			}
-			| T::Cast => vec![Op { typ: O::Cast(self.expect_type_arg(ops)), annot }],
+			| T::Cast => vec![Op { typ: O::Cast(self.expect_type_arg(ops)?), annot }],
 			| T::ShiftR => vec![Op { typ: O::ShiftR, annot }],
+			| T::ShiftRA => vec![Op { typ: O::ShiftRA, annot }],
 			| T::ShiftL => vec![Op { typ: O::ShiftL, annot }],
+			| T::RotL => vec![Op { typ: O::RotL, annot }],
+			| T::RotR => vec![Op { typ: O::RotR, annot }],
+			| T::Xor => vec![Op { typ: O::Xor, annot }],
+			| T::Popcount => vec![Op { typ: O::Popcount, annot }],
+			| T::Clz => vec![Op { typ: O::Clz, annot }],
+			| T::Ctz => vec![Op { typ: O::Ctz, annot }],
 			| T::Or => vec![Op { typ: O::Or, annot }],
 			| T::BitOr => vec![Op { typ: O::BitOr, annot }],
 			| T::And => vec![Op { typ: O::And, annot }],
@@ -327,29 +534,99 @@ impl Parser {
 			| T::OCurly
 			| T::CCurly
 			| T::OParen
-			| T::CParen => {
-				self.add_error(format!("{}: Unexpected token: {typ}", annot.get_pos()))
-					.exit(1)
+			| T::CParen
+			| T::Comma
+			| T::PreIf
+			| T::PreElse
+			| T::PreEnd
+			| T::RawBlock(_) => {
+				return Err(self
+					.add_error(format!("{}: Unexpected token: {typ}", annot.get_pos()))
+					.abort())
 			}
-			| T::Mem => vec![Op { typ: O::Mem(self.get_optional_id_arg(ops)), annot }],
+			| T::Mem => vec![Op { typ: O::Mem(self.get_optional_id_arg(ops)?), annot }],
 			| T::Decla => {
-				let name = self.expect_id(ops);
-				let size = self.expect_int_lit(ops);
+				let name = self.expect_id(ops)?;
+				let size = self.expect_int_lit(ops)?;
 				if self.memory_regions.contains_key(&name) {
-					self.add_error(format!(
-						"{}: Memory region {name} already defined",
-						annot.get_pos()
-					))
-					.exit(1);
+					return Err(self
+						.add_error(format!(
+							"{}: Memory region {name} already defined",
+							annot.get_pos()
+						))
+						.abort());
 				}
 				self.memory_regions_order.push(name.clone());
 				self.memory_regions.insert(name, size);
-				self.ops_from_first_token(ops)
+				self.ops_from_first_token(ops)?
 			}
 			| T::SetOver => {
-				vec![Op { typ: O::SetOver(self.expect_optional_size_arg(ops)), annot }]
+				vec![Op { typ: O::SetOver(self.expect_optional_size_arg(ops)?), annot }]
 			}
-		}
+			| T::Host => {
+				let name = self.expect_string_lit(ops)?;
+				let (ins, outs) = self.expect_signature(ops)?;
+				if let Mode::Com = self.cli.mode {
+					return Err(self
+						.add_error(format!(
+							"{}: Host function `{name}` cannot be compiled, host \
+							 functions only exist in a simulator embedding Ssmpl{}",
+							annot.get_pos(),
+							self.expansion_trace()
+						))
+						.abort());
+				}
+				vec![Op { typ: O::Host(name, ins, outs), annot }]
+			}
+			| T::Asm => {
+				let (ins, outs) = self.expect_signature(ops)?;
+				let code = match (!ops.is_empty()).then(|| ops.remove(0)) {
+					| Some(Token { typ: T::RawBlock(code), .. }) => code,
+					| Some(Token { typ, annot }) => {
+						return Err(self
+							.add_error(format!(
+								"{}: Expected an asm block but got: {typ}",
+								annot.get_pos()
+							))
+							.abort())
+					}
+					| None => {
+						return Err(self
+							.add_error("Expected an asm block but got nothing".into())
+							.abort())
+					}
+				};
+				let fallback = match ops.as_slice() {
+					| [Token { typ: T::Else, .. }, Token { typ: T::OCurly, .. }, ..] => {
+						ops.drain(..2);
+						Some(self.collect_block(ops)?)
+					}
+					| _ => None,
+				};
+				match (&self.cli.mode, fallback) {
+					| (Mode::Com, _) => vec![Op { typ: O::Asm(ins, outs, code), annot }],
+					| (_, Some(mut fallback)) => {
+						let mut fallback_ops = vec![];
+						while !fallback.is_empty() {
+							fallback_ops
+								.extend(self.ops_from_first_token(&mut fallback)?);
+						}
+						fallback_ops
+					}
+					| (_, None) => {
+						return Err(self
+							.add_error(format!(
+								"{}: Inline assembly cannot be simulated, give it a \
+								 fallback written in Ssmpl with `asm (...) {{ ... }} \
+								 else {{ ... }}`{}",
+								annot.get_pos(),
+								self.expansion_trace()
+							))
+							.abort())
+					}
+				}
+			}
+		})
 	}
 
 	pub fn add_error(&mut self, msg: String) -> &mut Self {
@@ -357,126 +634,125 @@ impl Parser {
 		self
 	}
 
-	pub fn exit(&mut self, code: i32) -> ! { self.reporter.exit(code) }
+	pub fn abort(&mut self) -> Abort { self.reporter.abort() }
 
-	pub fn expect(&mut self, ops: &mut Vec<Token>, expected: TokenType) {
+	pub fn expect(
+		&mut self,
+		ops: &mut Vec<Token>,
+		expected: TokenType,
+	) -> Result<(), Abort> {
 		if ops.is_empty() {
-			self.add_error(format!("Expected {expected} but got nothing")).exit(1)
+			return Err(self
+				.add_error(format!("Expected {expected} but got nothing"))
+				.abort());
 		}
 		let Token { typ, annot } = ops.remove(0);
 		if typ != expected {
-			self.add_error(format!(
-				"{}: Expected {expected} but got {typ}",
-				annot.get_pos()
-			))
-			.exit(1)
+			return Err(self
+				.add_error(format!(
+					"{}: Expected {expected} but got {typ}",
+					annot.get_pos()
+				))
+				.abort());
 		}
+		Ok(())
 	}
 
-	pub fn expect_size_arg(&mut self, ops: &mut Vec<Token>) -> i64 {
-		self.expect(ops, TokenType::OParen);
+	pub fn expect_size_arg(&mut self, ops: &mut Vec<Token>) -> Result<i64, Abort> {
+		self.expect(ops, TokenType::OParen)?;
 		if ops.is_empty() {
-			self.add_error("Expected size argument but got nothing".into()).exit(1)
+			return Err(self
+				.add_error("Expected size argument but got nothing".into())
+				.abort());
 		}
 		let Token { typ, annot } = ops.remove(0);
 		let arg = match typ {
 			| TokenType::IntLit(arg) => arg,
-			| TokenType::Id(id) => {
-				match self.macros.get(&id) {
-					| Some(ops) => {
-						match ops.as_slice() {
-							| [Op { typ: OpType::PushI(arg), .. }] => *arg,
-							| _ => {
-								self.add_error(format!(
-									"{}: Size argument used an invalid macro: \
-									 {id}\nSize argument can only use macros consisting \
-									 of a single integer literal",
-									annot.get_pos()
-								))
-								.exit(1)
-							}
-						}
-					}
-					| None => {
-						self.add_error(format!(
-							"{}: Size argument used an undefined macro: {id}",
-							annot.get_pos()
-						))
-						.exit(1)
-					}
-				}
-			}
+			| TokenType::Id(id) => self.size_from_macro(id, annot, ops)?,
 			| _ => {
-				self.add_error(format!(
-					"{}: Expected size argument but got: {typ}",
-					annot.get_pos()
-				))
-				.exit(1)
+				return Err(self
+					.add_error(format!(
+						"{}: Expected size argument but got: {typ}",
+						annot.get_pos()
+					))
+					.abort())
 			}
 		};
-		self.expect(ops, TokenType::CParen);
-		arg
+		self.expect(ops, TokenType::CParen)?;
+		Ok(arg)
 	}
 
-	pub fn expect_optional_size_arg(&mut self, ops: &mut Vec<Token>) -> i64 {
+	pub fn expect_optional_size_arg(
+		&mut self,
+		ops: &mut Vec<Token>,
+	) -> Result<i64, Abort> {
 		match ops.first() {
 			| Some(Token { typ, .. }) if *typ == TokenType::OParen => {
 				self.expect_size_arg(ops)
 			}
-			| _ => 1,
+			| _ => Ok(1),
 		}
 	}
 
-	pub fn expect_id(&mut self, ops: &mut Vec<Token>) -> String {
+	pub fn expect_id(&mut self, ops: &mut Vec<Token>) -> Result<String, Abort> {
 		if ops.is_empty() {
-			self.add_error("Expected identifier but got nothing".into()).exit(1)
+			return Err(self
+				.add_error("Expected identifier but got nothing".into())
+				.abort());
 		}
 		let Token { typ, annot } = ops.remove(0);
 		match typ {
-			| TokenType::Id(id) => id,
+			| TokenType::Id(id) => Ok(id),
 			| _ => {
-				self.add_error(format!(
-					"{}: Expected identifier but got: {typ}",
-					annot.get_pos()
-				))
-				.exit(1)
+				Err(self
+					.add_error(format!(
+						"{}: Expected identifier but got: {typ}",
+						annot.get_pos()
+					))
+					.abort())
 			}
 		}
 	}
 
-	pub fn expect_string_lit(&mut self, ops: &mut Vec<Token>) -> String {
+	pub fn expect_string_lit(&mut self, ops: &mut Vec<Token>) -> Result<String, Abort> {
 		if ops.is_empty() {
-			self.add_error("Expected string literal but got nothing".into()).exit(1)
+			return Err(self
+				.add_error("Expected string literal but got nothing".into())
+				.abort());
 		}
 		let Token { typ, annot } = ops.remove(0);
 		match typ {
-			| TokenType::StringLit(id) => id,
+			| TokenType::StringLit(id) => Ok(id),
 			| _ => {
-				self.add_error(format!(
-					"{}: Expected string literal but got: {typ}",
-					annot.get_pos()
-				))
-				.exit(1)
+				Err(self
+					.add_error(format!(
+						"{}: Expected string literal but got: {typ}",
+						annot.get_pos()
+					))
+					.abort())
 			}
 		}
 	}
 
-	pub fn get_optional_id_arg(&mut self, ops: &mut Vec<Token>) -> Option<String> {
+	pub fn get_optional_id_arg(
+		&mut self,
+		ops: &mut Vec<Token>,
+	) -> Result<Option<String>, Abort> {
 		match ops.first() {
 			| Some(Token { typ, .. }) if *typ == TokenType::OParen => {
-				self.expect(ops, TokenType::OParen);
-				let id = self.expect_id(ops);
-				self.expect(ops, TokenType::CParen);
-				Some(id)
+				self.expect(ops, TokenType::OParen)?;
+				let id = self.expect_id(ops)?;
+				self.expect(ops, TokenType::CParen)?;
+				Ok(Some(id))
 			}
-			| _ => None,
+			| _ => Ok(None),
 		}
 	}
 
-	pub fn expect_type_arg(&mut self, ops: &mut Vec<Token>) -> Type {
-		self.expect(ops, TokenType::OParen);
+	pub fn expect_type_arg(&mut self, ops: &mut Vec<Token>) -> Result<Type, Abort> {
+		self.expect(ops, TokenType::OParen)?;
 		if ops.is_empty() {
-			self.add_error("Expected type but got nothing".into()).exit(1)
+			return Err(self.add_error("Expected type but got nothing".into()).abort());
 		}
 		let Token { typ, annot } = ops.remove(0);
 		let typ = match typ {
@@ -485,74 +761,435 @@ impl Parser {
 			| TokenType::TypeBool => Type::Bool,
 			| TokenType::TypePtr => Type::Ptr,
 			| _ => {
-				self.add_error(format!(
-					"{}: Expected type but got: {typ}",
-					annot.get_pos()
-				))
-				.exit(1)
+				return Err(self
+					.add_error(format!(
+						"{}: Expected type but got: {typ}",
+						annot.get_pos()
+					))
+					.abort())
 			}
 		};
-		self.expect(ops, TokenType::CParen);
-		typ
+		self.expect(ops, TokenType::CParen)?;
+		Ok(typ)
+	}
+
+	/// Parses a stack effect such as `(ptr i64 -- i64)`, types can be written in
+	/// lower or upper case.
+	pub fn expect_signature(
+		&mut self,
+		ops: &mut Vec<Token>,
+	) -> Result<(Vec<Type>, Vec<Type>), Abort> {
+		self.expect(ops, TokenType::OParen)?;
+		let mut ins = vec![];
+		let mut outs = vec![];
+		let mut after_separator = false;
+		loop {
+			if ops.is_empty() {
+				return Err(self
+					.add_error("Expected a stack effect but got nothing".into())
+					.abort());
+			}
+			let Token { typ, annot } = ops.remove(0);
+			let typ = match typ {
+				| TokenType::CParen => break,
+				| TokenType::DoubleMinus if !after_separator => {
+					after_separator = true;
+					continue;
+				}
+				| TokenType::TypeI64 => Type::I64,
+				| TokenType::TypeF64 => Type::F64,
+				| TokenType::TypeBool => Type::Bool,
+				| TokenType::TypePtr => Type::Ptr,
+				| TokenType::Id(id) if id == "i64" => Type::I64,
+				| TokenType::Id(id) if id == "f64" => Type::F64,
+				| TokenType::Id(id) if id == "bool" => Type::Bool,
+				| TokenType::Id(id) if id == "ptr" => Type::Ptr,
+				| _ => {
+					return Err(self
+						.add_error(format!(
+							"{}: Expected a type or `--` in stack effect but got: {typ}",
+							annot.get_pos()
+						))
+						.abort())
+				}
+			};
+			if after_separator {
+				outs.push(typ)
+			} else {
+				ins.push(typ)
+			}
+		}
+		if !after_separator {
+			return Err(self.add_error("Expected `--` in stack effect".into()).abort());
+		}
+		Ok((ins, outs))
 	}
 
-	pub fn expect_int_lit(&mut self, ops: &mut Vec<Token>) -> i64 {
+	pub fn expect_int_lit(&mut self, ops: &mut Vec<Token>) -> Result<i64, Abort> {
 		if ops.is_empty() {
-			self.add_error("Expected identifier but got nothing".into()).exit(1)
+			return Err(self
+				.add_error("Expected identifier but got nothing".into())
+				.abort());
 		}
 		let Token { typ, annot } = ops.remove(0);
 		match typ {
-			| TokenType::IntLit(arg) => arg,
-			| TokenType::Id(id) => {
-				match self.macros.get(&id) {
-					| Some(ops) => {
-						match ops.as_slice() {
-							| [Op { typ: OpType::PushI(arg), .. }] => *arg,
-							| _ => {
-								self.add_error(format!(
-									"{}: Size argument used an invalid macro: \
-									 {id}\nSize argument can only use macros consisting \
-									 of a single integer literal",
+			| TokenType::IntLit(arg) => Ok(arg),
+			| TokenType::Id(id) => self.size_from_macro(id, annot, ops),
+			| _ => {
+				Err(self
+					.add_error(format!(
+						"{}: Expected size argument but got: {typ}",
+						annot.get_pos()
+					))
+					.abort())
+			}
+		}
+	}
+
+	/// Fills the defines from the mode and options the compiler was called with.
+	/// Every define is also usable as an integer macro.
+	fn collect_defines(&mut self) {
+		let mode = match self.cli.mode {
+			| Mode::Com => "COM",
+			| Mode::Sim | Mode::Test | Mode::Debug | Mode::Repl => "SIM",
+		};
+		self.defines.insert(mode.into(), 1);
+		if self.cli.debug {
+			self.defines.insert("DEBUG".into(), 1);
+		}
+		for (name, value) in self.cli.defines.iter() {
+			self.defines.insert(name.clone(), *value);
+		}
+		for (name, value) in self.defines.iter() {
+			let annot = Annotation::new(Position::new("<command-line>".into(), 0, 0));
+			let body = vec![Token { typ: TokenType::IntLit(*value), annot }];
+			self.macros.insert(name.clone(), Macro { params: vec![], body });
+		}
+	}
+
+	/// Evaluates `#if NAME ... #else ... #end` blocks, keeping only the tokens of
+	/// the taken branches. A branch is taken when `NAME` is defined to a non zero
+	/// value.
+	fn preprocess(&mut self, ops: &mut Vec<Token>) -> Result<(), Abort> {
+		// One entry per open `#if`: (its annotation, is the current branch taken,
+		// the annotation of its `#else` if seen)
+		let mut conditions: Vec<(Annotation, bool, Option<Annotation>)> = vec![];
+		let mut kept = Vec::with_capacity(ops.len());
+		let mut tokens = std::mem::take(ops).into_iter();
+		while let Some(token) = tokens.next() {
+			match token.typ {
+				| TokenType::PreIf => {
+					let name = match tokens.next() {
+						| Some(Token { typ: TokenType::Id(name), .. }) => name,
+						| Some(Token { typ, annot }) => {
+							return Err(self
+								.add_error(format!(
+									"{}: Expected a name after #if but got: {typ}",
 									annot.get_pos()
 								))
-								.exit(1)
-							}
+								.abort())
+						}
+						| None => {
+							return Err(self
+								.add_error(format!(
+									"{}: Expected a name after #if but got nothing",
+									token.annot.get_pos()
+								))
+								.abort())
+						}
+					};
+					let taken = self.defines.get(&name).is_some_and(|value| *value != 0);
+					conditions.push((token.annot, taken, None));
+				}
+				| TokenType::PreElse => {
+					match conditions.last_mut() {
+						| Some((_, taken, else_ @ None)) => {
+							*taken = !*taken;
+							*else_ = Some(token.annot);
+						}
+						| Some((_, _, Some(first))) => {
+							return Err(self
+								.add_error(format!(
+									"{}: Duplicate #else, the first one is at {}",
+									token.annot.get_pos(),
+									first.get_pos()
+								))
+								.abort())
+						}
+						| None => {
+							return Err(self
+								.add_error(format!(
+									"{}: Unexpected #else without a matching #if",
+									token.annot.get_pos()
+								))
+								.abort())
 						}
 					}
-					| None => {
-						self.add_error(format!(
-							"{}: Size argument used an undefined macro: {id}",
-							annot.get_pos()
-						))
-						.exit(1)
+				}
+				| TokenType::PreEnd => {
+					if conditions.pop().is_none() {
+						return Err(self
+							.add_error(format!(
+								"{}: Unexpected #end without a matching #if",
+								token.annot.get_pos()
+							))
+							.abort());
 					}
 				}
+				| _ if conditions.iter().all(|(_, taken, _)| *taken) => kept.push(token),
+				| _ => (),
 			}
-			| _ => {
-				self.add_error(format!(
-					"{}: Expected size argument but got: {typ}",
-					annot.get_pos()
-				))
-				.exit(1)
+		}
+		if let Some((annot, ..)) = conditions.last() {
+			return Err(self
+				.add_error(format!("{}: Unterminated #if", annot.get_pos()))
+				.abort());
+		}
+		*ops = kept;
+		Ok(())
+	}
+
+	/// Hoists every top-level macro definition out of `ops` so that macros can be
+	/// used before the place they are defined at.
+	fn collect_macros(&mut self, ops: &mut Vec<Token>) -> Result<(), Abort> {
+		let mut rest = Vec::with_capacity(ops.len());
+		while !ops.is_empty() {
+			if ops[0].typ == TokenType::Macro {
+				ops.remove(0);
+				self.define_macro(ops)?;
+			} else {
+				rest.push(ops.remove(0));
 			}
 		}
+		*ops = rest;
+		Ok(())
 	}
 
-	pub fn collect_until(&mut self, ops: &mut Vec<Token>, until: TokenType) -> Vec<Op> {
-		let mut collected_ops = Vec::new();
+	/// Parses `NAME { ... }` or `NAME(a, b) { ... }` (the `macro` keyword already
+	/// consumed) and stores the body tokens without expanding them.
+	fn define_macro(&mut self, ops: &mut Vec<Token>) -> Result<(), Abort> {
+		let name = self.expect_id(ops)?;
+		let mut params: Vec<String> = vec![];
+		if matches!(ops.first(), Some(Token { typ: TokenType::OParen, .. })) {
+			self.expect(ops, TokenType::OParen)?;
+			while !matches!(ops.first(), Some(Token { typ: TokenType::CParen, .. })) {
+				if !params.is_empty() {
+					self.expect(ops, TokenType::Comma)?;
+				}
+				let annot = ops.first().map(|token| token.annot.clone());
+				let param = self.expect_id(ops)?;
+				if params.contains(&param) {
+					return Err(self
+						.add_error(format!(
+							"{}: Duplicate parameter `{param}` in macro {name}",
+							annot.unwrap().get_pos()
+						))
+						.abort());
+				}
+				params.push(param);
+			}
+			self.expect(ops, TokenType::CParen)?;
+		}
+		self.expect(ops, TokenType::OCurly)?;
+		let body = self.collect_block(ops)?;
+		self.macros.insert(name, Macro { params, body });
+		Ok(())
+	}
+
+	/// Collects the comma separated arguments of a macro call, each argument being
+	/// the list of tokens it is made of.
+	fn collect_macro_args(
+		&mut self,
+		name: &str,
+		ops: &mut Vec<Token>,
+	) -> Result<Vec<Vec<Token>>, Abort> {
+		if !matches!(ops.first(), Some(Token { typ: TokenType::OParen, .. })) {
+			return Ok(vec![]);
+		}
+		ops.remove(0);
+		let mut args = vec![vec![]];
+		let mut depth = 0;
 		loop {
-			let Some(Token { typ, .. }) = ops.first() else {
-				self.add_error(format!("Expected {until} but got nothing",)).exit(1)
-			};
-			if *typ == until {
-				break;
-			} else {
-				for op in self.ops_from_first_token(ops) {
-					collected_ops.push(op);
+			if ops.is_empty() {
+				return Err(self
+					.add_error(format!(
+						"Expected {} to close the arguments of macro {name} but got \
+						 nothing",
+						TokenType::CParen
+					))
+					.abort());
+			}
+			let token = ops.remove(0);
+			match token.typ {
+				| TokenType::CParen if depth == 0 => break,
+				| TokenType::Comma if depth == 0 => {
+					args.push(vec![]);
+					continue;
+				}
+				| TokenType::OParen => depth += 1,
+				| TokenType::CParen => depth -= 1,
+				| _ => (),
+			}
+			args.last_mut().unwrap().push(token);
+		}
+		if args.len() == 1 && args[0].is_empty() {
+			args.clear();
+		}
+		Ok(args)
+	}
+
+	/// Replaces the parameters of a macro in its body with the tokens given at the
+	/// call site.
+	/// The substitution is done in a single pass so the arguments are never
+	/// rescanned for parameter names, which keeps them from capturing (or being
+	/// captured by) the macro's own parameters.
+	fn substitute_params(
+		params: &[String],
+		args: &[Vec<Token>],
+		body: Vec<Token>,
+	) -> Vec<Token> {
+		body.into_iter()
+			.flat_map(|token| {
+				match &token.typ {
+					| TokenType::Id(id) => {
+						match params.iter().position(|param| param == id) {
+							| Some(idx) => args[idx].clone(),
+							| None => vec![token],
+						}
+					}
+					| _ => vec![token],
 				}
+			})
+			.collect()
+	}
+
+	/// Collects the tokens up to the `}` matching an already consumed `{`, and
+	/// consumes that `}`.
+	fn collect_block(&mut self, ops: &mut Vec<Token>) -> Result<Vec<Token>, Abort> {
+		let mut depth = 0;
+		let mut collected = Vec::new();
+		loop {
+			if ops.is_empty() {
+				return Err(self
+					.add_error(format!("Expected {} but got nothing", TokenType::CCurly))
+					.abort());
+			}
+			let token = ops.remove(0);
+			match token.typ {
+				| TokenType::OCurly => depth += 1,
+				| TokenType::CCurly if depth == 0 => return Ok(collected),
+				| TokenType::CCurly => depth -= 1,
+				| _ => (),
+			}
+			collected.push(token);
+		}
+	}
+
+	fn expand_macro(
+		&mut self,
+		name: String,
+		annot: Annotation,
+		ops: &mut Vec<Token>,
+	) -> Result<Vec<Op>, Abort> {
+		let Some(Macro { params, body }) = self.macros.get(&name).cloned() else {
+			return Err(self
+				.add_error(format!(
+					"{}: Undefined macro: {name}{}",
+					annot.get_pos(),
+					self.expansion_trace()
+				))
+				.abort());
+		};
+		if let Some(start) = self.expansion_stack.iter().position(|e| e.name == name) {
+			let cycle = self.expansion_stack[start..]
+				.iter()
+				.map(|e| format!("`{}` -> ", e.name))
+				.collect::<String>();
+			return Err(self
+				.add_error(format!(
+					"{}: Macro expansion cycle: {cycle}`{name}`{}",
+					annot.get_pos(),
+					self.expansion_trace()
+				))
+				.abort());
+		}
+		if self.expansion_stack.len() >= self.cli.max_macro_depth {
+			return Err(self
+				.add_error(format!(
+					"{}: Maximum macro expansion depth ({}) exceeded while expanding \
+					 `{name}`{}",
+					annot.get_pos(),
+					self.cli.max_macro_depth,
+					self.expansion_trace()
+				))
+				.abort());
+		}
+
+		let args = self.collect_macro_args(&name, ops)?;
+		if args.len() != params.len() {
+			return Err(self
+				.add_error(format!(
+					"{}: Macro {name} takes {} argument{} but {} {} given{}",
+					annot.get_pos(),
+					params.len(),
+					if params.len() == 1 { "" } else { "s" },
+					args.len(),
+					if args.len() == 1 { "was" } else { "were" },
+					self.expansion_trace()
+				))
+				.abort());
+		}
+
+		self.expansion_stack
+			.push(Expansion { name: name.clone(), pos: annot.get_pos().clone() });
+		let mut body = Self::substitute_params(&params, &args, body);
+		let mut expanded = vec![];
+		while !body.is_empty() {
+			expanded.extend(self.ops_from_first_token(&mut body)?);
+		}
+		self.expansion_stack.pop();
+
+		for op in expanded.iter_mut() {
+			op.annot.push_expansion(name.clone(), annot.get_pos().clone());
+		}
+		Ok(expanded)
+	}
+
+	/// Resolves a macro used as a size argument, it must expand to a single
+	/// integer literal.
+	fn size_from_macro(
+		&mut self,
+		id: String,
+		annot: Annotation,
+		ops: &mut Vec<Token>,
+	) -> Result<i64, Abort> {
+		if !self.macros.contains_key(&id) {
+			return Err(self
+				.add_error(format!(
+					"{}: Size argument used an undefined macro: {id}{}",
+					annot.get_pos(),
+					self.expansion_trace()
+				))
+				.abort());
+		}
+		match self.expand_macro(id.clone(), annot.clone(), ops)?.as_slice() {
+			| [Op { typ: OpType::PushI(arg), .. }] => Ok(*arg),
+			| _ => {
+				Err(self
+					.add_error(format!(
+						"{}: Size argument used an invalid macro: {id}\nSize argument \
+						 can only use macros consisting of a single integer literal{}",
+						annot.get_pos(),
+						self.expansion_trace()
+					))
+					.abort())
 			}
 		}
-		collected_ops
+	}
+
+	/// Trace of the macros currently being expanded, innermost first.
+	fn expansion_trace(&self) -> String {
+		self.expansion_stack.iter().rev().map(|e| format!("\n\t{e}")).collect()
 	}
 }
 
@@ -0,0 +1,103 @@
+//#region Imports
+use std::path::{Path, PathBuf};
+//#endregion
+
+/// Exit code of a simulation stopped for exceeding a limit or violating the
+/// syscall policy
+pub const SANDBOX_EXIT_CODE: i32 = 125;
+
+/// What the simulated program is allowed to do on the host (`--policy`).
+///
+/// ```text
+/// # Syscalls the program may do, any if missing
+/// syscalls = 0 1 2 3 60
+/// # Paths `open` may access, any if missing
+/// open = ./data/ /tmp/sandbox/
+/// # Whether the program may write to other fds than stdout and stderr, or open
+/// # files with O_CREAT, O_TRUNC or O_APPEND
+/// write = false
+/// ```
+#[derive(Clone)]
+pub struct Policy {
+	pub syscalls: Option<Vec<u64>>,
+	pub open:     Option<Vec<PathBuf>>,
+	pub write:    bool,
+}
+
+/// Resolves `.`, `..` and symbolic links, the file itself may not exist
+pub fn resolve(path: &Path) -> PathBuf {
+	if let Ok(path) = path.canonicalize() {
+		return path;
+	}
+	match (path.parent(), path.file_name()) {
+		| (Some(parent), Some(name)) => {
+			let parent =
+				if parent.as_os_str().is_empty() { Path::new(".") } else { parent };
+			resolve(parent).join(name)
+		}
+		| _ => path.to_path_buf(),
+	}
+}
+
+impl Default for Policy {
+	/// Allows everything
+	fn default() -> Self { Policy { syscalls: None, open: None, write: true } }
+}
+
+impl Policy {
+	pub fn parse(policy: &str) -> Result<Self, String> {
+		let mut parsed = Policy::default();
+		for (idx, line) in policy.lines().enumerate() {
+			let line = line.split('#').next().unwrap().trim();
+			if line.is_empty() {
+				continue;
+			}
+			let Some((key, values)) = line.split_once('=') else {
+				return Err(format!(
+					"line {}: Expected `key = value` but got `{line}`",
+					idx + 1
+				));
+			};
+			let values = values.split_whitespace();
+			match key.trim() {
+				| "syscalls" => {
+					let syscalls = values
+						.map(|syscode| syscode.parse())
+						.collect::<Result<_, _>>()
+						.map_err(|e| {
+							format!("line {}: Invalid syscall number: {e}", idx + 1)
+						})?;
+					parsed.syscalls = Some(syscalls);
+				}
+				| "open" => {
+					parsed.open = Some(values.map(|p| resolve(Path::new(p))).collect())
+				}
+				| "write" => {
+					parsed.write = match values.collect::<Vec<_>>()[..] {
+						| ["true"] => true,
+						| ["false"] => false,
+						| _ => {
+							return Err(format!(
+								"line {}: `write` must be true or false",
+								idx + 1
+							))
+						}
+					}
+				}
+				| key => return Err(format!("line {}: Unknown key `{key}`", idx + 1)),
+			}
+		}
+		Ok(parsed)
+	}
+
+	pub fn allows_syscall(&self, syscode: u64) -> bool {
+		self.syscalls.as_ref().is_none_or(|syscalls| syscalls.contains(&syscode))
+	}
+
+	pub fn allows_open(&self, path: &Path) -> bool {
+		let path = resolve(path);
+		self.open
+			.as_ref()
+			.is_none_or(|prefixes| prefixes.iter().any(|p| path.starts_with(p)))
+	}
+}
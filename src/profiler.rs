@@ -0,0 +1,143 @@
+//#region Imports
+use std::{collections::HashMap, fmt::Write};
+
+use crate::{
+	bytecode::executed_ops,
+	parser::{Op, OpType},
+};
+//#endregion
+
+/// Number of rows of every table of the report
+const TOP: usize = 20;
+
+/// Counts how many times every instruction is executed (`--profile`).
+///
+/// Cycles are dispatched instructions of the virtual machine, ops fused into a
+/// single instruction share its cycles.
+pub struct Profiler {
+	pub hits: Vec<u64>,
+}
+
+/// Executed ops and cycles attributed to a line or a macro
+#[derive(Default, Clone, Copy)]
+struct Cost {
+	ops:    u64,
+	cycles: u64,
+}
+
+fn table<K: Ord>(
+	title: &str,
+	key: &str,
+	costs: HashMap<K, Cost>,
+	name: impl Fn(&K) -> String,
+) -> String {
+	let mut rows: Vec<_> = costs.into_iter().collect();
+	rows.sort_by(|(a, a_cost), (b, b_cost)| {
+		b_cost.cycles.cmp(&a_cost.cycles).then(a.cmp(b))
+	});
+	let mut table =
+		format!("{title} ({} shown of {}):\n", rows.len().min(TOP), rows.len());
+	writeln!(table, "{:>12} {:>12}  {key}", "cycles", "ops").unwrap();
+	for (k, Cost { ops, cycles }) in rows.iter().take(TOP) {
+		writeln!(table, "{cycles:>12} {ops:>12}  {}", name(k)).unwrap();
+	}
+	table
+}
+
+impl Profiler {
+	pub fn new(code_len: usize) -> Self { Profiler { hits: vec![0; code_len] } }
+
+	/// Executed ops and cycles of every op, the cycles of a fused instruction
+	/// going to the last op it stands for
+	fn costs(&self, ops: &[Op], origins: &[usize], firsts: &[usize]) -> Vec<Cost> {
+		let executed = executed_ops(&self.hits, origins, firsts, ops.len());
+		let mut costs: Vec<_> =
+			executed.into_iter().map(|ops| Cost { ops, cycles: 0 }).collect();
+		for (hits, op) in self.hits.iter().zip(origins) {
+			costs[*op].cycles += hits;
+		}
+		costs
+	}
+
+	/// Builds the text report and the folded stacks, `jumps` being the
+	/// resolved control flow of the program
+	pub fn report(
+		&self,
+		ops: &[Op],
+		origins: &[usize],
+		firsts: &[usize],
+		jumps: &[usize],
+	) -> (String, String) {
+		let costs = self.costs(ops, origins, firsts);
+		let mut lines: HashMap<(String, usize), Cost> = HashMap::new();
+		let mut macros: HashMap<String, Cost> = HashMap::new();
+		let mut stacks: HashMap<String, u64> = HashMap::new();
+		let mut loops = vec![];
+		let mut total = Cost::default();
+
+		for (ip, (op, cost)) in ops.iter().zip(&costs).enumerate() {
+			let pos = op.annot.get_pos();
+			let line =
+				lines.entry((pos.file_path().to_string(), pos.line())).or_default();
+			line.ops += cost.ops;
+			line.cycles += cost.cycles;
+			total.ops += cost.ops;
+			total.cycles += cost.cycles;
+
+			// Recursive expansions are only counted once per macro
+			let expansions = op.annot.get_expansions();
+			for (depth, expansion) in expansions.iter().enumerate() {
+				if expansions[..depth].iter().any(|e| e.name == expansion.name) {
+					continue;
+				}
+				let cost_of_macro = macros.entry(expansion.name.clone()).or_default();
+				cost_of_macro.ops += cost.ops;
+				cost_of_macro.cycles += cost.cycles;
+			}
+
+			if cost.cycles > 0 {
+				let mut frames: Vec<_> = expansions
+					.iter()
+					.rev()
+					.map(|e| format!("{}@{}:{}", e.name, e.pos.file_path(), e.pos.line()))
+					.collect();
+				frames.push(format!("{}:{}", pos.file_path(), pos.line()));
+				*stacks.entry(frames.join(";")).or_default() += cost.cycles;
+			}
+
+			// The end of a loop is executed once per iteration
+			if let OpType::End(_, true) = op.typ {
+				loops.push((jumps[ip], cost.ops));
+			}
+		}
+
+		let mut report =
+			format!("Profile: {} ops, {} cycles\n\n", total.ops, total.cycles);
+		report += &table("Hottest lines", "line", lines, |(file, line)| {
+			format!("{file}:{line}")
+		});
+		report += "\n";
+		report += &table("Macros", "macro", macros, |name| name.clone());
+		report += "\n";
+		loops.sort_by(|(a, a_iter), (b, b_iter)| b_iter.cmp(a_iter).then(a.cmp(b)));
+		writeln!(report, "Loops ({} shown of {}):", loops.len().min(TOP), loops.len())
+			.unwrap();
+		writeln!(report, "{:>12}  while", "iterations").unwrap();
+		for (while_, iterations) in loops.iter().take(TOP) {
+			let annot = &ops[*while_].annot;
+			writeln!(
+				report,
+				"{iterations:>12}  {}{}",
+				annot.get_pos(),
+				annot.expansion_trace()
+			)
+			.unwrap();
+		}
+
+		let mut stacks: Vec<_> = stacks.into_iter().collect();
+		stacks.sort();
+		let folded =
+			stacks.iter().map(|(stack, cycles)| format!("{stack} {cycles}\n")).collect();
+		(report, folded)
+	}
+}
@@ -0,0 +1,336 @@
+//#region Imports
+use std::io::{BufRead, BufWriter, Write};
+
+use crate::{
+	annotation::Annotation,
+	bytecode::{Layout, Region, RegionKind},
+	lexer::{Lexer, Token, TokenType},
+	parser::{Parser, Program},
+	report::{Level, Reporter},
+	simulator::{output_failed, RuntimeError, Simulator},
+	syscalls::{Io, Syscalls},
+	Cli,
+};
+//#endregion
+
+const INPUT_PATH: &str = "<repl>";
+
+/// Failure to print the prompt or the stack
+fn shown(e: std::io::Error) -> RuntimeError {
+	RuntimeError::Io(format!("Failed to write the output of the repl: {e}"))
+}
+
+/// Session of the `repl`: what the entries defined so far and the state of the
+/// simulation they ran in
+struct Repl<'a> {
+	cli:      Cli<String>,
+	/// Whether the errors are only kept, never printed
+	quiet:    bool,
+	/// Entries, and the input of the programs they run
+	stdin:    &'a mut dyn BufRead,
+	stdout:   &'a mut dyn Write,
+	stderr:   &'a mut dyn Write,
+	/// Macros, strings and `decla` regions of the entries so far
+	parser:   Parser,
+	/// Types of the values on the stack
+	types:    Vec<Annotation>,
+	/// Values on the stack
+	values:   Vec<u64>,
+	memory:   Vec<u8>,
+	/// Files opened by the entries so far
+	syscalls: Syscalls,
+	/// Strings and `decla` regions, placed from the end of the memory down so
+	/// that `mem` keeps its address
+	layout:   Layout,
+	/// Start of the lowest region, the end of `mem`
+	top:      usize,
+	/// Lines read so far
+	line:     usize,
+}
+
+/// Entry read from stdin
+enum Entry {
+	Command(String),
+	/// Tokens of the entry and the reporter of their errors, `None` if it could
+	/// not be lexed
+	Code(Option<(Vec<Token>, Reporter)>),
+}
+
+/// Number of blocks left open by the tokens, the entry goes on while it is
+/// positive
+fn depth(tokens: &[Token]) -> i64 {
+	use TokenType::*;
+	tokens
+		.iter()
+		.map(|token| {
+			match token.typ {
+				| If | While | OCurly | PreIf => 1,
+				| End | CCurly | PreEnd => -1,
+				| _ => 0,
+			}
+		})
+		.sum()
+}
+
+impl Repl<'_> {
+	fn reporter(&self) -> Reporter {
+		let mut reporter = Reporter::new(self.cli.level.clone());
+		reporter.quiet = self.quiet;
+		reporter
+	}
+
+	/// Lexes `input`, starting at `line`, `None` if it has errors
+	fn lex(&self, input: &str, line: usize) -> Option<(Vec<Token>, Reporter)> {
+		let chars = input.chars().collect();
+		let reporter = self.reporter();
+		let mut lexer = Lexer::new(chars, INPUT_PATH.to_string(), reporter).at_line(line);
+		let tokens = lexer.by_ref().collect();
+		if lexer.reporter.flush().has(Level::Error) {
+			return None;
+		}
+		Some((tokens, lexer.reporter))
+	}
+
+	/// Reads the next entry, lines are added to it until its blocks are closed.
+	/// `None` at the end of the input.
+	fn read(&mut self) -> Result<Option<Entry>, RuntimeError> {
+		let mut entry = String::new();
+		let mut prompt = "> ";
+		let first = self.line + 1;
+		loop {
+			write!(self.stdout, "{prompt}").map_err(shown)?;
+			self.stdout.flush().map_err(shown)?;
+			let mut line = String::new();
+			if self.stdin.read_line(&mut line).unwrap_or(0) == 0 {
+				writeln!(self.stdout).map_err(shown)?;
+				return Ok(
+					(!entry.is_empty()).then(|| Entry::Code(self.lex(&entry, first)))
+				);
+			}
+			self.line += 1;
+			if entry.is_empty() && line.trim().starts_with(':') {
+				return Ok(Some(Entry::Command(line.trim().to_string())));
+			}
+			entry += &line;
+			let lexed = self.lex(&entry, first);
+			if lexed.as_ref().is_none_or(|(tokens, _)| depth(tokens) <= 0) {
+				return Ok(Some(Entry::Code(lexed)));
+			}
+			prompt = "... ";
+		}
+	}
+
+	/// Parses and type checks the tokens of an entry on top of the ones before,
+	/// `None` if it has errors
+	fn check(
+		&mut self,
+		tokens: Vec<Token>,
+		reporter: Reporter,
+	) -> Option<(Program, Vec<Annotation>)> {
+		self.parser.reporter = reporter;
+		self.parser.ops.clear();
+		self.parser.push_tokens(tokens).ok()?;
+		let (mut program, types) =
+			Program::new(self.parser.clone()).type_check_from(self.types.clone()).ok()?;
+		if program.reporter.flush().has(Level::Error) {
+			return None;
+		}
+		Some((program, types))
+	}
+
+	/// Places the strings and `decla` regions the program added, they are
+	/// returned to be initialized
+	fn place(&mut self, program: &Program) -> Result<Vec<Region>, String> {
+		let strings = program.strings.iter().map(|lit| RegionKind::Str(lit.clone()));
+		let declas = program
+			.memory_regions_order
+			.iter()
+			.map(|name| RegionKind::Decla(name.clone()));
+		let mut placed = vec![];
+		for kind in strings.chain(declas) {
+			if self.layout.regions.iter().any(|region| region.kind == kind) {
+				continue;
+			}
+			let len = match &kind {
+				// Null terminated, like in the compiled program
+				| RegionKind::Str(lit) => lit.len() + 1,
+				| RegionKind::Decla(name) => program.memory_regions[name] as usize,
+				| RegionKind::Mem => unreachable!(),
+			};
+			if len > self.top {
+				return Err(format!(
+					"Not enough memory for the strings and `decla` regions: `--sim-mem` \
+					 is {}",
+					self.cli.sim_mem
+				));
+			}
+			self.top -= len;
+			let region = Region { kind, start: self.top, len };
+			self.layout.regions.insert(0, region.clone());
+			placed.push(region);
+		}
+		Ok(placed)
+	}
+
+	/// Runs the program on the stack and memory of the session, which are only
+	/// updated if it succeeds. The exit code if it exited.
+	fn execute(
+		&mut self,
+		program: &Program,
+		placed: Vec<Region>,
+	) -> Result<Option<i32>, RuntimeError> {
+		let types = self.types.iter().map(|annot| annot.typ).collect();
+		let lowered = program.lower_with(false, self.layout.clone(), types);
+		let io = Io {
+			stdin:  &mut self.stdin,
+			stdout: BufWriter::new(&mut *self.stdout),
+			stderr: &mut *self.stderr,
+		};
+		let mut m = Simulator::machine(program, lowered, &self.cli, io, &mut [])?;
+		m.regions.pop();
+		m.regions.insert(0, Region { kind: RegionKind::Mem, start: 0, len: self.top });
+		m.memory.copy_from_slice(&self.memory);
+		for Region { kind, start, len } in placed {
+			let bytes = &mut m.memory[start..start + len];
+			match kind {
+				| RegionKind::Str(lit) => {
+					bytes[..lit.len()].copy_from_slice(lit.as_bytes());
+					bytes[lit.len()] = 0;
+				}
+				| _ => bytes.fill(0),
+			}
+		}
+		m.stack.slots[..self.values.len()].copy_from_slice(&self.values);
+		m.stack.sp = self.values.len();
+		std::mem::swap(&mut m.syscalls, &mut self.syscalls);
+
+		let result = if self.cli.max_steps.is_some() || self.cli.timeout.is_some() {
+			program.run::<true>(&mut m, &self.cli)
+		} else {
+			program.run::<false>(&mut m, &self.cli)
+		};
+		std::mem::swap(&mut m.syscalls, &mut self.syscalls);
+		let flushed = m.io.stdout.flush().map_err(output_failed);
+		let status = result?.unwrap();
+		flushed?;
+		if m.exited {
+			return Ok(Some(status));
+		}
+		self.memory = m.memory;
+		self.values = m.stack.slots[..m.stack.sp].to_vec();
+		Ok(None)
+	}
+
+	/// Checks and runs an entry, the session is left as it was if it fails. The
+	/// exit code if it exited.
+	fn entry(&mut self, tokens: Vec<Token>, reporter: Reporter) -> Option<i32> {
+		let (parser, layout, top) = (self.parser.clone(), self.layout.clone(), self.top);
+		let result = match self.check(tokens, reporter) {
+			| None => Err(None),
+			| Some((program, types)) => {
+				self.place(&program)
+					.map_err(Some)
+					.and_then(|placed| {
+						self.execute(&program, placed).map_err(|e| Some(e.to_string()))
+					})
+					.map(|exited| (exited, types))
+			}
+		};
+		match result {
+			| Ok((Some(code), _)) => return Some(code),
+			| Ok((None, types)) => self.types = types,
+			| Err(error) => {
+				if let Some(error) = error {
+					self.reporter().add_error(error).flush();
+				}
+				(self.parser, self.layout, self.top) = (parser, layout, top);
+			}
+		}
+		None
+	}
+
+	fn print_stack(&mut self) -> Result<(), RuntimeError> {
+		let values = self
+			.types
+			.iter()
+			.zip(&self.values)
+			.map(|(annot, slot)| format!("{} {}", annot.typ, annot.typ.format(*slot)))
+			.collect::<Vec<_>>();
+		writeln!(self.stdout, "[{}]", values.join(", ")).map_err(shown)
+	}
+}
+
+/// Reads entries from `stdin` and runs them one after the other in the same
+/// simulation, until the end of the input or `:quit`. The errors are reported
+/// like by `reporter`, the exit code of the session is returned.
+pub fn run(
+	cli: &Cli<String>,
+	reporter: &Reporter,
+	stdin: &mut dyn BufRead,
+	stdout: &mut dyn Write,
+	stderr: &mut dyn Write,
+) -> Result<i32, RuntimeError> {
+	// Instrumenting a session makes no sense, only the limits apply to each entry
+	let cli = Cli {
+		input_path: INPUT_PATH.to_string(),
+		sanitize: false,
+		trace: None,
+		profile: false,
+		profile_folded: None,
+		coverage: None,
+		record: None,
+		replay: None,
+		exit_with_stack: false,
+		..cli.clone()
+	};
+	let lexer =
+		Lexer::new(vec![], INPUT_PATH.to_string(), Reporter::new(cli.level.clone()));
+	let mut repl = Repl {
+		parser: Parser::new(lexer, &cli).expect("an empty program has no errors"),
+		types: vec![],
+		values: vec![],
+		memory: vec![0; cli.sim_mem],
+		syscalls: Syscalls::new(cli.policy.clone()),
+		layout: Layout::default(),
+		top: cli.sim_mem,
+		line: 0,
+		cli,
+		quiet: reporter.quiet,
+		stdin,
+		stdout,
+		stderr,
+	};
+
+	writeln!(repl.stdout, "Ssmpl repl, `:load <path>` includes a file and `:quit` exits")
+		.map_err(shown)?;
+	loop {
+		let lexed = match repl.read()? {
+			| None => return Ok(0),
+			| Some(Entry::Code(lexed)) => lexed,
+			| Some(Entry::Command(command)) => {
+				match command.split_once(' ').unwrap_or((&command, "")) {
+					| (":quit" | ":q", "") => return Ok(0),
+					// Included the same way as by `include`
+					| (":load", path) if !path.trim().is_empty() => {
+						repl.lex(&format!("include \"{}\"", path.trim()), repl.line)
+					}
+					| _ => {
+						repl.reporter()
+							.add_error(format!(
+								"Unknown command `{command}`, expected `:load <path>` \
+								 or `:quit`"
+							))
+							.flush();
+						continue;
+					}
+				}
+			}
+		};
+		if let Some(code) =
+			lexed.and_then(|(tokens, reporter)| repl.entry(tokens, reporter))
+		{
+			return Ok(code);
+		}
+		repl.print_stack()?;
+	}
+}
@@ -0,0 +1,166 @@
+//#region Imports
+use std::fmt::Display;
+//#endregion
+
+/// How a recorded syscall ended
+#[derive(Clone, Copy, PartialEq)]
+pub enum Outcome {
+	Ret(u64),
+	Exit(i32),
+}
+
+/// Syscall of a `--record` log, one per line:
+///
+/// ```text
+/// 0 0 4096 16 out=68690a ret=3
+/// 1 1 4096 3 in=68690a ret=3
+/// 60 0 exit=0
+/// ```
+///
+/// The syscode and the arguments come first, then the bytes the syscall read
+/// from the memory (`in`, written data or opened path) and wrote to it (`out`,
+/// read data) in hex, and its result.
+#[derive(Clone)]
+pub struct Record {
+	pub syscode: u64,
+	pub args:    Vec<u64>,
+	pub input:   Option<Vec<u8>>,
+	pub output:  Option<Vec<u8>>,
+	pub outcome: Outcome,
+}
+
+fn hex(bytes: &[u8]) -> String {
+	bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn unhex(hex: &str) -> Option<Vec<u8>> {
+	if !hex.len().is_multiple_of(2) {
+		return None;
+	}
+	(0..hex.len())
+		.step_by(2)
+		.map(|idx| u8::from_str_radix(hex.get(idx..idx + 2)?, 16).ok())
+		.collect()
+}
+
+impl Display for Record {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}", self.syscode)?;
+		for arg in self.args.iter() {
+			write!(f, " {arg}")?;
+		}
+		if let Some(input) = &self.input {
+			write!(f, " in={}", hex(input))?;
+		}
+		if let Some(output) = &self.output {
+			write!(f, " out={}", hex(output))?;
+		}
+		match self.outcome {
+			| Outcome::Ret(ret) => write!(f, " ret={}", ret as i64),
+			| Outcome::Exit(code) => write!(f, " exit={code}"),
+		}
+	}
+}
+
+impl Record {
+	pub fn parse(line: &str) -> Option<Self> {
+		let mut fields = line.split_whitespace();
+		let syscode = fields.next()?.parse().ok()?;
+		let mut record = Record {
+			syscode,
+			args: vec![],
+			input: None,
+			output: None,
+			outcome: Outcome::Ret(0),
+		};
+		let mut outcome = None;
+		for field in fields {
+			match field.split_once('=') {
+				| None => record.args.push(field.parse().ok()?),
+				| Some(("in", bytes)) => record.input = Some(unhex(bytes)?),
+				| Some(("out", bytes)) => record.output = Some(unhex(bytes)?),
+				| Some(("ret", ret)) => {
+					outcome = Some(Outcome::Ret(ret.parse::<i64>().ok()? as u64))
+				}
+				| Some(("exit", code)) => {
+					outcome = Some(Outcome::Exit(code.parse().ok()?))
+				}
+				| Some(_) => return None,
+			}
+		}
+		record.outcome = outcome?;
+		Some(record)
+	}
+}
+
+/// Syscalls of a `--replay` log, fed back to the program in order
+pub struct Replay {
+	records: Vec<Record>,
+	/// Index of the next record
+	next:    usize,
+}
+
+/// Bytes, as text when possible
+fn show(bytes: &[u8]) -> String { format!("{:?}", String::from_utf8_lossy(bytes)) }
+
+impl Replay {
+	/// Replays `records` from the index `next`
+	pub fn new(records: Vec<Record>, next: usize) -> Self { Replay { records, next } }
+
+	pub fn parse(log: &str) -> Result<Self, String> {
+		let records = log
+			.lines()
+			.enumerate()
+			.filter(|(_, line)| !line.trim().is_empty())
+			.map(|(idx, line)| {
+				Record::parse(line)
+					.ok_or_else(|| format!("line {}: Invalid syscall `{line}`", idx + 1))
+			})
+			.collect::<Result<_, _>>()?;
+		Ok(Replay { records, next: 0 })
+	}
+
+	/// Next record, if it is the syscall `syscode` with the same arguments and
+	/// input, otherwise the first difference
+	pub fn next(
+		&mut self,
+		syscode: u64,
+		args: &[u64],
+		input: Option<&[u8]>,
+	) -> Result<&Record, String> {
+		let idx = self.next;
+		let nth = idx + 1;
+		let Some(record) = self.records.get(idx) else {
+			return Err(format!(
+				"syscall #{nth} is syscall {syscode} but the recording ended after {} \
+				 syscall(s)",
+				self.records.len()
+			));
+		};
+		if record.syscode != syscode {
+			return Err(format!(
+				"syscall #{nth} is syscall {syscode} but was syscall {} in the recording",
+				record.syscode
+			));
+		}
+		if record.args != args {
+			return Err(format!(
+				"syscall #{nth} ({syscode}) has the arguments {args:?} but had {:?} in \
+				 the recording",
+				record.args
+			));
+		}
+		if record.input.as_deref() != input {
+			return Err(format!(
+				"syscall #{nth} ({syscode}) reads {} but read {} in the recording",
+				input.map_or("nothing".to_string(), show),
+				record.input.as_deref().map_or("nothing".to_string(), show)
+			));
+		}
+		self.next += 1;
+		Ok(&self.records[idx])
+	}
+
+	/// Records left once the program stopped
+	pub fn left(&self) -> usize { self.records.len() - self.next }
+}
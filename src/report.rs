@@ -31,10 +31,19 @@ impl Report {
 	}
 }
 
+/// Error of the lexer, parser or type checker stopped by an error, with the
+/// errors reported so far
+#[derive(Debug)]
+pub struct Abort(pub Vec<String>);
+
 pub struct Reporter {
 	stdout:        StandardStream,
 	pub min_level: Level,
+	/// Whether the reports are only kept, never printed (library use)
+	pub quiet:     bool,
 	reports:       Vec<Report>,
+	/// Number of reports already printed by `flush`
+	printed:       usize,
 }
 
 impl Clone for Reporter {
@@ -45,7 +54,13 @@ impl Clone for Reporter {
 			ColorChoice::Auto
 		};
 		let stdout = StandardStream::stdout(choice);
-		Self { stdout, min_level: self.min_level.clone(), reports: self.reports.clone() }
+		Self {
+			stdout,
+			min_level: self.min_level.clone(),
+			quiet: self.quiet,
+			reports: self.reports.clone(),
+			printed: self.printed,
+		}
 	}
 }
 
@@ -57,7 +72,7 @@ impl Reporter {
 			ColorChoice::Auto
 		};
 		let stdout = StandardStream::stdout(choice);
-		Reporter { stdout, min_level, reports: Vec::new() }
+		Reporter { stdout, min_level, quiet: false, reports: Vec::new(), printed: 0 }
 	}
 
 	pub fn report(&mut self, report: &Report) -> Result<&mut Self, std::io::Error> {
@@ -93,17 +108,19 @@ impl Reporter {
 	}
 
 	pub fn flush(&mut self) -> &mut Self {
-		let mut selected_reports = self
-			.reports
+		let mut selected_reports = self.reports[self.printed..]
 			.iter()
 			.filter_map(
 				|x| if x.level >= self.min_level { Some(x.clone()) } else { None },
 			)
 			.collect::<Vec<_>>();
 		selected_reports.sort_by_key(|x| x.level.clone());
-		for report in selected_reports {
-			self.report(&report).unwrap();
+		if !self.quiet {
+			for report in selected_reports {
+				self.report(&report).unwrap();
+			}
 		}
+		self.printed = self.reports.len();
 		self.stdout.flush().unwrap();
 		self
 	}
@@ -113,6 +130,12 @@ impl Reporter {
 		std::process::exit(code)
 	}
 
+	/// Stops the stage that reported an error, its caller decides what to do
+	pub fn abort(&mut self) -> Abort {
+		self.flush();
+		Abort(self.errors())
+	}
+
 	pub fn exit_if(&mut self, level: Level, code: i32) -> &mut Self {
 		if !self
 			.reports
@@ -130,4 +153,13 @@ impl Reporter {
 	pub fn has(&self, level: Level) -> bool {
 		self.reports.iter().any(|x| x.level >= level)
 	}
+
+	/// Errors reported so far, as they are printed
+	pub fn errors(&self) -> Vec<String> {
+		self.reports
+			.iter()
+			.filter(|x| x.level == Level::Error)
+			.map(|x| x.msg.clone())
+			.collect()
+	}
 }
@@ -66,4 +66,573 @@ void dump_f_rounded(double f) {
   }
   write_i((long)f);
   write(0, "\n", 1);
-}
\ No newline at end of file
+}
+
+// Math functions, the same as the ones of the simulator (`src/math.rs`) which
+// give the same bits. Only basic IEEE operations so that nothing depends on a
+// libm. Rebuild `aux.o` with `make aux`.
+
+typedef unsigned long u64;
+typedef unsigned int u32;
+
+static const u64 two_over_pi[20] = {
+  0,
+  0xA2F9836E4E441529, 0xFC2757D1F534DDC0, 0xDB6295993C439041, 0xFE5163ABDEBBC561,
+  0xB7246E3A424DD2E0, 0x06492EEA09D1921C, 0xFE1DEB1CB129A73E, 0xE88235F52EBB4484,
+  0xE99C7026B45F7E41, 0x3991D639835339F4, 0x9C845F8BBDF9283B, 0x1FF897FFDE05980F,
+  0xEF2F118B5A0A6D1F, 0x6D367ECF27CB09B7, 0x4F463F669E5FEA2D, 0x7527BAC7EBE5F17B,
+  0x3D0739F78A5292EA, 0x6BFB5FB11F8D5D08, 0x56033046FC7B6BAB,
+};
+
+static u64 to_bits(double x) {
+  union { double f; u64 i; } u = {x};
+  return u.i;
+}
+
+static double from_bits(u64 i) {
+  union { u64 i; double f; } u = {i};
+  return u.f;
+}
+
+static int hi(double x) { return (int)(to_bits(x) >> 32); }
+
+static u32 lo(double x) { return (u32)to_bits(x); }
+
+static double from_words(int hi, u32 lo) { return from_bits((u64)(u32)hi << 32 | lo); }
+
+static double with_hi(double x, int hi) { return from_words(hi, lo(x)); }
+
+static double trunc_lo(double x) { return from_words(hi(x), 0); }
+
+static int is_nan(double x) { return x != x; }
+
+static double pow2(int n) { return from_bits((u64)(0x3ff + n) << 52); }
+
+static double scalbn(double x, int n) {
+  double y = x;
+  if (n > 1023) {
+    y *= pow2(1023);
+    n -= 1023;
+    if (n > 1023) {
+      y *= pow2(1023);
+      n -= 1023;
+      if (n > 1023) n = 1023;
+    }
+  } else if (n < -1022) {
+    y *= pow2(-969);
+    n += 969;
+    if (n < -1022) {
+      y *= pow2(-969);
+      n += 969;
+      if (n < -1022) n = -1022;
+    }
+  }
+  return y * pow2(n);
+}
+
+double math_fmod(double x, double y) {
+  if (is_nan(x)) return x + x;
+  if (is_nan(y)) return y + y;
+  u64 ux = to_bits(x), uy = to_bits(y), i;
+  int ex = ux >> 52 & 0x7ff;
+  int ey = uy >> 52 & 0x7ff;
+  u64 sx = ux >> 63;
+  if (uy << 1 == 0 || ex == 0x7ff) {
+    double z = x - x;
+    return z / z;
+  }
+  if (ux << 1 <= uy << 1) {
+    if (ux << 1 == uy << 1) return 0.0 * x;
+    return x;
+  }
+
+  if (!ex) {
+    for (i = ux << 12; i >> 63 == 0; ex--, i <<= 1);
+    ux <<= -ex + 1;
+  } else {
+    ux &= -1UL >> 12;
+    ux |= 1UL << 52;
+  }
+  if (!ey) {
+    for (i = uy << 12; i >> 63 == 0; ey--, i <<= 1);
+    uy <<= -ey + 1;
+  } else {
+    uy &= -1UL >> 12;
+    uy |= 1UL << 52;
+  }
+
+  for (; ex > ey; ex--) {
+    i = ux - uy;
+    if (i >> 63 == 0) {
+      if (i == 0) return 0.0 * x;
+      ux = i;
+    }
+    ux <<= 1;
+  }
+  i = ux - uy;
+  if (i >> 63 == 0) {
+    if (i == 0) return 0.0 * x;
+    ux = i;
+  }
+  for (; ux >> 52 == 0; ux <<= 1, ex--);
+
+  if (ex > 0) {
+    ux -= 1UL << 52;
+    ux |= (u64)ex << 52;
+  } else {
+    ux >>= -ex + 1;
+  }
+  return from_bits(ux | sx << 63);
+}
+
+double math_exp(double x) {
+  static const double half[2] = {0.5, -0.5};
+  static const double ln2_hi[2] = {6.93147180369123816490e-01, -6.93147180369123816490e-01};
+  static const double ln2_lo[2] = {1.90821492927058770002e-10, -1.90821492927058770002e-10};
+  const double inv_ln2 = 1.44269504088896338700e+00;
+  const double P1 = 1.66666666666666019037e-01;
+  const double P2 = -2.77777777770155933842e-03;
+  const double P3 = 6.61375632143793436117e-05;
+  const double P4 = -1.65339022054652515390e-06;
+  const double P5 = 4.13813679705723846039e-08;
+
+  int hx = hi(x);
+  int sign = hx >> 31 & 1;
+  hx &= 0x7fffffff;
+
+  if (hx >= 0x40862E42) {
+    if (is_nan(x)) return x + x;
+    if (hx >= 0x7ff00000) return sign == 0 ? x : 0.0;
+    if (x > 7.09782712893383973096e+02) return from_bits(0x7ff0000000000000);
+    if (x < -7.45133219101941108420e+02) return 0.0;
+  }
+
+  double hi_ = 0.0, lo_ = 0.0;
+  int k = 0;
+  if (hx > 0x3fd62e42) {
+    if (hx < 0x3FF0A2B2) {
+      hi_ = x - ln2_hi[sign];
+      lo_ = ln2_lo[sign];
+      k = 1 - sign - sign;
+    } else {
+      k = (int)(inv_ln2 * x + half[sign]);
+      double t = k;
+      hi_ = x - t * ln2_hi[0];
+      lo_ = t * ln2_lo[0];
+    }
+    x = hi_ - lo_;
+  } else if (hx < 0x3e300000) {
+    return 1.0 + x;
+  }
+
+  double t = x * x;
+  double c = x - t * (P1 + t * (P2 + t * (P3 + t * (P4 + t * P5))));
+  if (k == 0) return 1.0 - ((x * c) / (c - 2.0) - x);
+  double y = 1.0 - ((lo_ - (x * c) / (2.0 - c)) - hi_);
+  if (k >= -1021) return with_hi(y, (int)((u32)hi(y) + ((u32)k << 20)));
+  return with_hi(y, (int)((u32)hi(y) + ((u32)(k + 1000) << 20))) * pow2(-1000);
+}
+
+double math_log(double x) {
+  const double ln2_hi = 6.93147180369123816490e-01;
+  const double ln2_lo = 1.90821492927058770002e-10;
+  const double two54 = 1.80143985094819840000e+16;
+  const double Lg1 = 6.666666666666735130e-01;
+  const double Lg2 = 3.999999999940941908e-01;
+  const double Lg3 = 2.857142874366239149e-01;
+  const double Lg4 = 2.222219843214978396e-01;
+  const double Lg5 = 1.818357216161805012e-01;
+  const double Lg6 = 1.531383769920937332e-01;
+  const double Lg7 = 1.479819860511658591e-01;
+
+  int hx = hi(x);
+  int k = 0;
+  if (hx < 0x00100000) {
+    if (is_nan(x)) return x + x;
+    if (x == 0.0) return from_bits(0xfff0000000000000);
+    if (hx < 0) return (x - x) / 0.0;
+    k -= 54;
+    x *= two54;
+    hx = hi(x);
+  }
+  if (hx >= 0x7ff00000) return x + x;
+  k += (hx >> 20) - 1023;
+  hx &= 0x000fffff;
+  int i = (hx + 0x95f64) & 0x100000;
+  x = with_hi(x, hx | (i ^ 0x3ff00000));
+  k += i >> 20;
+  double f = x - 1.0;
+  double dk = k;
+  if ((0x000fffff & (2 + hx)) < 3) {
+    if (f == 0.0) {
+      if (k == 0) return 0.0;
+      return dk * ln2_hi + dk * ln2_lo;
+    }
+    double r = f * f * (0.5 - 0.33333333333333333 * f);
+    if (k == 0) return f - r;
+    return dk * ln2_hi - ((r - dk * ln2_lo) - f);
+  }
+  double s = f / (2.0 + f);
+  double z = s * s;
+  double w = z * z;
+  double t1 = w * (Lg2 + w * (Lg4 + w * Lg6));
+  double t2 = z * (Lg1 + w * (Lg3 + w * (Lg5 + w * Lg7)));
+  double r = t2 + t1;
+  if (((hx - 0x6147a) | (0x6b851 - hx)) > 0) {
+    double hfsq = 0.5 * f * f;
+    if (k == 0) return f - (hfsq - s * (hfsq + r));
+    return dk * ln2_hi - ((hfsq - (s * (hfsq + r) + dk * ln2_lo)) - f);
+  }
+  if (k == 0) return f - s * (f - r);
+  return dk * ln2_hi - ((s * (f - r) - dk * ln2_lo) - f);
+}
+
+static void two_prod(double a, double b, double *p, double *e) {
+  const double split = 134217729.0;
+  *p = a * b;
+  double c = split * a;
+  double ah = c - (c - a), al = a - (c - (c - a));
+  c = split * b;
+  double bh = c - (c - b), bl = b - (c - (c - b));
+  *e = ((ah * bh - *p) + ah * bl + al * bh) + al * bl;
+}
+
+static u64 window(int q, int r, int k) {
+  if (r == 0) return two_over_pi[q + k];
+  return two_over_pi[q + k] << r | two_over_pi[q + k + 1] >> (64 - r);
+}
+
+static int rem_pio2_large(double x, double *y0, double *y1) {
+  const double pio2_hi = 1.57079632679489655800e+00;
+  const double pio2_lo = 6.12323399573676603587e-17;
+
+  u64 bits = to_bits(x);
+  u64 m = (bits & (-1UL >> 12)) | 1UL << 52;
+  int e = (int)(bits >> 52 & 0x7ff) - 1075;
+  int start = e - 2 + 64;
+  int q = start / 64, r = start % 64;
+  unsigned __int128 t = (unsigned __int128)m * window(q, r, 2);
+  u64 p0 = (u64)t;
+  t = (unsigned __int128)m * window(q, r, 1) + (t >> 64);
+  u64 p1 = (u64)t;
+  t = (unsigned __int128)m * window(q, r, 0) + (t >> 64);
+  u64 p2 = (u64)t;
+  unsigned __int128 frac = (unsigned __int128)p2 << 66 | (unsigned __int128)p1 << 2 | (p0 >> 62);
+  int n = ((int)(p2 >> 62) + (int)(frac >> 127)) & 3;
+  int negative = (int)(frac >> 127);
+  unsigned __int128 mag = negative ? -frac : frac;
+  if (mag == 0) {
+    if (bits >> 63 == 0) {
+      *y0 = 0.0;
+      *y1 = 0.0;
+      return n;
+    }
+    *y0 = -0.0;
+    *y1 = -0.0;
+    return -n;
+  }
+  u64 mag_hi = (u64)(mag >> 64);
+  int lz = mag_hi ? __builtin_clzll(mag_hi) : 64 + __builtin_clzll((u64)mag);
+  unsigned __int128 top = mag << lz;
+  double a = (double)(long)(u64)(top >> 75) * pow2(-53 - lz);
+  double b = (double)(long)((u64)(top >> 22) & (-1UL >> 11)) * pow2(-106 - lz);
+  if (negative) {
+    a = -a;
+    b = -b;
+  }
+  double p, err;
+  two_prod(a, pio2_hi, &p, &err);
+  double tail = err + (a * pio2_lo + b * pio2_hi);
+  double z0 = p + tail;
+  double z1 = tail - (z0 - p);
+  if (bits >> 63 == 0) {
+    *y0 = z0;
+    *y1 = z1;
+    return n;
+  }
+  *y0 = -z0;
+  *y1 = -z1;
+  return -n;
+}
+
+static int rem_pio2(double x, double *y0, double *y1) {
+  const double to_int = 6755399441055744.0;
+  const double inv_pio2 = 6.36619772367581382433e-01;
+  const double pio2_1 = 1.57079632673412561417e+00;
+  const double pio2_1t = 6.07710050650619224932e-11;
+  const double pio2_2 = 6.07710050630396597660e-11;
+  const double pio2_2t = 2.02226624879595063154e-21;
+  const double pio2_3 = 2.02226624871116645580e-21;
+  const double pio2_3t = 8.47842766036889956997e-32;
+
+  int ix = hi(x) & 0x7fffffff;
+  if (ix >= 0x413921fb) return rem_pio2_large(x, y0, y1);
+  double fn = x * inv_pio2 + to_int - to_int;
+  int n = (int)fn;
+  double r = x - fn * pio2_1;
+  double w = fn * pio2_1t;
+  double z = r - w;
+  int ex = ix >> 20;
+  if (ex - (hi(z) >> 20 & 0x7ff) > 16) {
+    double t = r;
+    w = fn * pio2_2;
+    r = t - w;
+    w = fn * pio2_2t - ((t - r) - w);
+    z = r - w;
+    if (ex - (hi(z) >> 20 & 0x7ff) > 49) {
+      t = r;
+      w = fn * pio2_3;
+      r = t - w;
+      w = fn * pio2_3t - ((t - r) - w);
+      z = r - w;
+    }
+  }
+  *y0 = z;
+  *y1 = (r - z) - w;
+  return n;
+}
+
+static double kernel_sin(double x, double y) {
+  const double S1 = -1.66666666666666324348e-01;
+  const double S2 = 8.33333333332248946124e-03;
+  const double S3 = -1.98412698298579493134e-04;
+  const double S4 = 2.75573137070700676789e-06;
+  const double S5 = -2.50507602534068634195e-08;
+  const double S6 = 1.58969099521155010221e-10;
+
+  double z = x * x;
+  double v = z * x;
+  double r = S2 + z * (S3 + z * (S4 + z * (S5 + z * S6)));
+  return x - ((z * (0.5 * y - v * r) - y) - v * S1);
+}
+
+static double kernel_cos(double x, double y) {
+  const double C1 = 4.16666666666666019037e-02;
+  const double C2 = -1.38888888888741095749e-03;
+  const double C3 = 2.48015872894767294178e-05;
+  const double C4 = -2.75573143513906633035e-07;
+  const double C5 = 2.08757232129817482790e-09;
+  const double C6 = -1.13596475577881948265e-11;
+
+  double z = x * x;
+  double w = z * z;
+  double r = z * (C1 + z * (C2 + z * C3)) + w * w * (C4 + z * (C5 + z * C6));
+  double hz = 0.5 * z;
+  w = 1.0 - hz;
+  return w + (((1.0 - w) - hz) + (z * r - x * y));
+}
+
+double math_sin(double x) {
+  int ix = hi(x) & 0x7fffffff;
+  if (ix <= 0x3fe921fb) {
+    if (ix < 0x3e500000) return x;
+    return kernel_sin(x, 0.0);
+  }
+  if (ix >= 0x7ff00000) return x - x;
+  double y0, y1;
+  switch (rem_pio2(x, &y0, &y1) & 3) {
+  case 0: return kernel_sin(y0, y1);
+  case 1: return kernel_cos(y0, y1);
+  case 2: return -kernel_sin(y0, y1);
+  default: return -kernel_cos(y0, y1);
+  }
+}
+
+double math_cos(double x) {
+  int ix = hi(x) & 0x7fffffff;
+  if (ix <= 0x3fe921fb) {
+    if (ix < 0x3e46a09e) return 1.0;
+    return kernel_cos(x, 0.0);
+  }
+  if (ix >= 0x7ff00000) return x - x;
+  double y0, y1;
+  switch (rem_pio2(x, &y0, &y1) & 3) {
+  case 0: return kernel_cos(y0, y1);
+  case 1: return -kernel_sin(y0, y1);
+  case 2: return -kernel_cos(y0, y1);
+  default: return kernel_sin(y0, y1);
+  }
+}
+
+double math_pow(double x, double y) {
+  static const double bp[2] = {1.0, 1.5};
+  static const double dp_h[2] = {0.0, 5.84962487220764160156e-01};
+  static const double dp_l[2] = {0.0, 1.35003920212974897128e-08};
+  const double two53 = 9007199254740992.0;
+  const double huge = 1.0e300;
+  const double tiny = 1.0e-300;
+  const double L1 = 5.99999999999994648725e-01;
+  const double L2 = 4.28571428578550184252e-01;
+  const double L3 = 3.33333329818377432918e-01;
+  const double L4 = 2.72728123808534006489e-01;
+  const double L5 = 2.30660745775561754067e-01;
+  const double L6 = 2.06975017800338417784e-01;
+  const double P1 = 1.66666666666666019037e-01;
+  const double P2 = -2.77777777770155933842e-03;
+  const double P3 = 6.61375632143793436117e-05;
+  const double P4 = -1.65339022054652515390e-06;
+  const double P5 = 4.13813679705723846039e-08;
+  const double lg2 = 6.93147180559945286227e-01;
+  const double lg2_h = 6.93147182464599609375e-01;
+  const double lg2_l = -1.90465429995776804525e-09;
+  const double ovt = 8.0085662595372944372e-17;
+  const double cp = 9.61796693925975554329e-01;
+  const double cp_h = 9.61796700954437255859e-01;
+  const double cp_l = -7.02846165095275826516e-09;
+  const double ivln2 = 1.44269504088896338700e+00;
+  const double ivln2_h = 1.44269502162933349609e+00;
+  const double ivln2_l = 1.92596299112661746887e-08;
+
+  int hx = hi(x), hy = hi(y);
+  u32 lx = lo(x), ly = lo(y);
+  int ix = hx & 0x7fffffff, iy = hy & 0x7fffffff;
+
+  if (((u32)iy | ly) == 0 || (hx == 0x3ff00000 && lx == 0)) return 1.0;
+  if (is_nan(x)) return x + x;
+  if (is_nan(y)) return y + y;
+
+  int yisint = 0;
+  if (hx < 0) {
+    if (iy >= 0x43400000) {
+      yisint = 2;
+    } else if (iy >= 0x3ff00000) {
+      int k = (iy >> 20) - 0x3ff;
+      if (k > 20) {
+        u32 j = ly >> (52 - k);
+        if (j << (52 - k) == ly) yisint = 2 - (int)(j & 1);
+      } else if (ly == 0) {
+        int j = iy >> (20 - k);
+        if (j << (20 - k) == iy) yisint = 2 - (j & 1);
+      }
+    }
+  }
+
+  if (ly == 0) {
+    if (iy == 0x7ff00000) {
+      if (((u32)(ix - 0x3ff00000) | lx) == 0) return 1.0;
+      if (ix >= 0x3ff00000) return hy >= 0 ? y : 0.0;
+      return hy < 0 ? -y : 0.0;
+    }
+    if (iy == 0x3ff00000) return hy < 0 ? 1.0 / x : x;
+    if (hy == 0x40000000) return x * x;
+    if (hy == 0x3fe00000 && hx >= 0) return __builtin_sqrt(x);
+  }
+
+  double ax = __builtin_fabs(x);
+  if (lx == 0 && (ix == 0x7ff00000 || ix == 0 || ix == 0x3ff00000)) {
+    double z = ax;
+    if (hy < 0) z = 1.0 / z;
+    if (hx < 0) {
+      if (((ix - 0x3ff00000) | yisint) == 0) {
+        z = (z - z) / (z - z);
+      } else if (yisint == 1) {
+        z = -z;
+      }
+    }
+    return z;
+  }
+
+  int n = (hx >> 31) + 1;
+  if ((n | yisint) == 0) return (x - x) / (x - x);
+  double s = (n | (yisint - 1)) == 0 ? -1.0 : 1.0;
+
+  double t1, t2;
+  if (iy > 0x41e00000) {
+    if (iy > 0x43f00000) {
+      if (ix <= 0x3fefffff) return hy < 0 ? huge * huge : tiny * tiny;
+      if (ix >= 0x3ff00000) return hy > 0 ? huge * huge : tiny * tiny;
+    }
+    if (ix < 0x3fefffff) return hy < 0 ? s * huge * huge : s * tiny * tiny;
+    if (ix > 0x3ff00000) return hy > 0 ? s * huge * huge : s * tiny * tiny;
+    double t = ax - 1.0;
+    double w = (t * t) * (0.5 - t * (0.3333333333333333333333 - t * 0.25));
+    double u = ivln2_h * t;
+    double v = t * ivln2_l - w * ivln2;
+    t1 = trunc_lo(u + v);
+    t2 = v - (t1 - u);
+  } else {
+    n = 0;
+    if (ix < 0x00100000) {
+      ax *= two53;
+      n -= 53;
+      ix = hi(ax);
+    }
+    n += (ix >> 20) - 0x3ff;
+    int j = ix & 0x000fffff;
+    ix = j | 0x3ff00000;
+    int k;
+    if (j <= 0x3988E) {
+      k = 0;
+    } else if (j < 0xBB67A) {
+      k = 1;
+    } else {
+      k = 0;
+      n += 1;
+      ix -= 0x00100000;
+    }
+    ax = with_hi(ax, ix);
+
+    double u = ax - bp[k];
+    double v = 1.0 / (ax + bp[k]);
+    double ss = u * v;
+    double s_h = trunc_lo(ss);
+    double t_h = from_words(((ix >> 1) | 0x20000000) + 0x00080000 + (k << 18), 0);
+    double t_l = ax - (t_h - bp[k]);
+    double s_l = v * ((u - s_h * t_h) - s_h * t_l);
+    double s2 = ss * ss;
+    double r = s2 * s2 * (L1 + s2 * (L2 + s2 * (L3 + s2 * (L4 + s2 * (L5 + s2 * L6)))));
+    r += s_l * (s_h + ss);
+    s2 = s_h * s_h;
+    t_h = trunc_lo(3.0 + s2 + r);
+    t_l = r - ((t_h - 3.0) - s2);
+    u = s_h * t_h;
+    v = s_l * t_h + t_l * ss;
+    double p_h = trunc_lo(u + v);
+    double p_l = v - (p_h - u);
+    double z_h = cp_h * p_h;
+    double z_l = cp_l * p_h + p_l * cp + dp_l[k];
+    double t = n;
+    t1 = trunc_lo(((z_h + z_l) + dp_h[k]) + t);
+    t2 = z_l - (((t1 - t) - dp_h[k]) - z_h);
+  }
+
+  double y1 = trunc_lo(y);
+  double p_l = (y - y1) * t1 + y * t2;
+  double p_h = y1 * t1;
+  double z = p_l + p_h;
+  int j = hi(z);
+  u32 i = lo(z);
+  if (j >= 0x40900000) {
+    if (((u32)(j - 0x40900000) | i) != 0 || p_l + ovt > z - p_h) return s * huge * huge;
+  } else if ((j & 0x7fffffff) >= 0x4090cc00) {
+    if ((((u32)j - 0xc090cc00) | i) != 0 || p_l <= z - p_h) return s * tiny * tiny;
+  }
+
+  int ij = j & 0x7fffffff;
+  int k = (ij >> 20) - 0x3ff;
+  n = 0;
+  if (ij > 0x3fe00000) {
+    n = j + (0x00100000 >> (k + 1));
+    k = ((n & 0x7fffffff) >> 20) - 0x3ff;
+    double t = from_words(n & ~(0x000fffff >> k), 0);
+    n = ((n & 0x000fffff) | 0x00100000) >> (20 - k);
+    if (j < 0) n = -n;
+    p_h -= t;
+  }
+  double t = trunc_lo(p_l + p_h);
+  double u = t * lg2_h;
+  double v = (p_l - (t - p_h)) * lg2 + t * lg2_l;
+  z = u + v;
+  double w = v - (z - u);
+  t = z * z;
+  t1 = z - t * (P1 + t * (P2 + t * (P3 + t * (P4 + t * P5))));
+  double r = (z * t1) / (t1 - 2.0) - (w + z * w);
+  z = 1.0 - (r - z);
+  j = (int)((u32)hi(z) + ((u32)n << 20));
+  if ((j >> 20) <= 0) {
+    z = scalbn(z, n);
+  } else {
+    z = with_hi(z, j);
+  }
+  return s * z;
+}
@@ -0,0 +1,169 @@
+//#region Imports
+use std::collections::HashSet;
+
+use crate::bytecode::{Region, RegionKind};
+//#endregion
+
+/// What last wrote a byte of the memory
+#[derive(Clone, Copy, PartialEq)]
+pub enum Writer {
+	Never,
+	/// Initialized with a string literal
+	Literal,
+	/// Index of the store op
+	Op(usize),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Issue {
+	Uninitialized,
+	CrossRegion,
+	StringWrite,
+	PtrAsInt,
+	IntAsPtr,
+}
+
+/// A misuse of the memory found by the sanitizer
+pub struct Finding {
+	pub msg:        String,
+	/// Last writer of the bytes involved
+	pub last_write: Writer,
+}
+
+/// Shadow memory of the simulator, tracking for every byte what last wrote it
+/// and whether it is part of a pointer.
+pub struct Sanitizer {
+	/// Non empty regions, in address order
+	regions:    Vec<Region>,
+	last_write: Vec<Writer>,
+	ptr:        Vec<bool>,
+	/// Issues already reported for an instruction, to only report them once
+	reported:   HashSet<(usize, Issue)>,
+}
+
+impl Sanitizer {
+	pub fn new(regions: &[Region], memory_len: usize) -> Self {
+		let mut last_write = vec![Writer::Never; memory_len];
+		for region in regions.iter() {
+			if let RegionKind::Str(_) = region.kind {
+				last_write[region.start..region.start + region.len].fill(Writer::Literal);
+			}
+		}
+		let regions = regions.iter().filter(|region| region.len > 0).cloned().collect();
+		Self {
+			regions,
+			last_write,
+			ptr: vec![false; memory_len],
+			reported: HashSet::new(),
+		}
+	}
+
+	fn region(&self, addr: usize) -> &Region {
+		let idx = self.regions.partition_point(|region| region.start <= addr);
+		&self.regions[idx - 1]
+	}
+
+	fn report(
+		&mut self,
+		findings: &mut Vec<Finding>,
+		ip: usize,
+		issue: Issue,
+		msg: String,
+		last_write: Writer,
+	) {
+		if self.reported.insert((ip, issue)) {
+			findings.push(Finding { msg, last_write });
+		}
+	}
+
+	/// Checks that `[addr, addr + size)` lies in a single region
+	fn check_region(
+		&mut self,
+		findings: &mut Vec<Finding>,
+		ip: usize,
+		op: &str,
+		addr: usize,
+		size: usize,
+	) {
+		let (first, last) = (self.region(addr), self.region(addr + size - 1));
+		if first.start != last.start {
+			let msg = format!(
+				"{op} of {size} byte(s) at address {addr} crosses from {first} into \
+				 {last}"
+			);
+			let last_write = self.last_write[addr];
+			self.report(findings, ip, Issue::CrossRegion, msg, last_write);
+		}
+	}
+
+	/// Checks a load of `[addr, addr + size)` by the instruction `ip`, `as_ptr`
+	/// being whether the loaded value is used as a pointer
+	pub fn load(
+		&mut self,
+		ip: usize,
+		op: &str,
+		addr: usize,
+		size: usize,
+		as_ptr: bool,
+	) -> Vec<Finding> {
+		let mut findings = vec![];
+		self.check_region(&mut findings, ip, op, addr, size);
+		let bytes = addr..addr + size;
+		if let Some(byte) = bytes.clone().find(|b| self.last_write[*b] == Writer::Never) {
+			let msg = format!(
+				"{op} of {size} byte(s) at address {addr} reads the uninitialized byte \
+				 {byte} of {}",
+				self.region(byte)
+			);
+			self.report(&mut findings, ip, Issue::Uninitialized, msg, Writer::Never);
+		}
+		let initialized: Vec<_> =
+			bytes.filter(|b| self.last_write[*b] != Writer::Never).collect();
+		if let Some(&byte) = initialized.iter().find(|b| self.ptr[**b] && !as_ptr) {
+			let msg = format!(
+				"{op} at address {addr} reads a value stored as ptr as an integer, use \
+				 `<|64 cast(Ptr)` to load a pointer"
+			);
+			let last_write = self.last_write[byte];
+			self.report(&mut findings, ip, Issue::PtrAsInt, msg, last_write);
+		}
+		if let Some(&byte) = initialized.iter().find(|b| !self.ptr[**b] && as_ptr) {
+			let msg = format!(
+				"{op} at address {addr} reads as a ptr a value that was not stored as \
+				 ptr"
+			);
+			let last_write = self.last_write[byte];
+			self.report(&mut findings, ip, Issue::IntAsPtr, msg, last_write);
+		}
+		findings
+	}
+
+	/// Checks a store to `[addr, addr + size)` by the instruction `ip` of the op
+	/// `origin` and records it
+	pub fn store(
+		&mut self,
+		ip: usize,
+		origin: usize,
+		op: &str,
+		addr: usize,
+		size: usize,
+		ptr: bool,
+	) -> Vec<Finding> {
+		let mut findings = vec![];
+		self.check_region(&mut findings, ip, op, addr, size);
+		let bytes = addr..addr + size;
+		if let Some(byte) =
+			bytes.clone().find(|b| matches!(self.region(*b).kind, RegionKind::Str(_)))
+		{
+			let msg = format!(
+				"{op} of {size} byte(s) at address {addr} writes into {}",
+				self.region(byte)
+			);
+			let last_write = self.last_write[byte];
+			self.report(&mut findings, ip, Issue::StringWrite, msg, last_write);
+		}
+		self.last_write[bytes.clone()].fill(Writer::Op(origin));
+		self.ptr[bytes].fill(ptr);
+		findings
+	}
+}
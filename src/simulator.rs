@@ -1,576 +1,1363 @@
 //#region Imports
-use std::collections::HashMap;
+use std::{
+	collections::HashSet,
+	fmt::Display,
+	fs::File,
+	io::{stderr, stdin, stdout, BufWriter, Read, Write},
+	time::Instant,
+};
 
 use crate::{
 	annotation::Type,
-	parser::{Op, OpType, Program},
+	bytecode::{Bytecode, Instr, Region, RegionKind},
+	coverage::{Counters, Coverage},
+	math,
+	parser::{OpType, Program},
+	policy::SANDBOX_EXIT_CODE,
+	profiler::Profiler,
+	replay::Replay,
+	report::Level,
+	sanitizer::{Finding, Sanitizer, Writer},
+	syscalls::{output_error, written, Io, Stop, Syscalls},
+	tracer::Tracer,
+	Cli,
 };
 //#endregion
 
-#[derive(Clone, PartialEq)]
-enum Data {
-	I64(i64),
-	F64(f64),
-	Bool(bool),
-	Ptr(i64),
+/// Maximum number of steps between two checks of the `--timeout`
+const TIMEOUT_CHECK_INTERVAL: u64 = 1 << 16;
+
+/// Stack of the virtual machine, every value is a raw `u64` slot.
+/// `sp` is kept apart from the slots so that it can live in a register.
+pub struct Stack {
+	pub slots: Vec<u64>,
+	pub sp:    usize,
 }
 
-impl Data {
-	fn to_i64(&self) -> i64 {
-		match self {
-			| Data::Ptr(v) | Data::I64(v) => *v,
-			| Data::Bool(v) => *v as i64,
-			| Data::F64(v) => i64::from_ne_bytes(v.to_ne_bytes()),
-		}
+impl Stack {
+	#[inline(always)]
+	fn push(&mut self, v: u64) {
+		self.slots[self.sp] = v;
+		self.sp += 1;
 	}
 
-	fn to_bool(&self) -> bool {
+	#[inline(always)]
+	fn pop(&mut self) -> u64 {
+		self.sp -= 1;
+		self.slots[self.sp]
+	}
+
+	#[inline(always)]
+	fn drop(&mut self, n: usize) { self.sp -= n; }
+
+	#[inline(always)]
+	fn top(&mut self) -> &mut u64 { &mut self.slots[self.sp - 1] }
+
+	#[inline(always)]
+	fn nth(&mut self, depth: usize) -> &mut u64 { &mut self.slots[self.sp - 1 - depth] }
+}
+
+/// Bytes `[ptr, ptr + N)` of the memory, if they are all inside of it
+#[inline(always)]
+fn access_bytes<const N: usize>(memory: &mut [u8], ptr: u64) -> Option<&mut [u8; N]> {
+	let start = usize::try_from(ptr).ok()?;
+	memory.get_mut(start..start.checked_add(N)?)?.try_into().ok()
+}
+
+#[inline(always)]
+fn binop(stack: &mut Stack, f: impl Fn(u64, u64) -> u64) {
+	let a = stack.pop();
+	let b = stack.top();
+	*b = f(*b, a);
+}
+
+#[inline(always)]
+fn binop_i(stack: &mut Stack, f: impl Fn(i64, i64) -> i64) {
+	binop(stack, |b, a| f(b as i64, a as i64) as u64)
+}
+
+#[inline(always)]
+fn binop_f(stack: &mut Stack, f: impl Fn(f64, f64) -> f64) {
+	binop(stack, |b, a| f(f64::from_bits(b), f64::from_bits(a)).to_bits())
+}
+
+/// Replaces the top two values by the wrapped result and whether it overflowed
+#[inline(always)]
+fn checked(stack: &mut Stack, f: impl Fn(i64, i64) -> (i64, bool)) {
+	let a = stack.pop() as i64;
+	let (result, overflowed) = f(*stack.top() as i64, a);
+	*stack.top() = result as u64;
+	stack.push(overflowed as u64);
+}
+
+#[inline(always)]
+fn unop_f(stack: &mut Stack, f: impl Fn(f64) -> f64) {
+	let v = stack.top();
+	*v = f(f64::from_bits(*v)).to_bits();
+}
+
+/// Shifts by 64 or more, which x86 masks to 6 bits, are defined like the
+/// compiled code does: everything is shifted out
+fn shr(v: u64, n: u64) -> u64 {
+	if n < 64 {
+		v >> n
+	} else {
+		0
+	}
+}
+
+fn shl(v: u64, n: u64) -> u64 {
+	if n < 64 {
+		v << n
+	} else {
+		0
+	}
+}
+
+/// Only the sign is left when shifting by 63 or more
+fn sar(v: u64, n: u64) -> u64 { ((v as i64) >> n.min(63)) as u64 }
+
+/// `f` truncated like `cvttsd2si` does: `i64::MIN` when it is NaN or out of
+/// range
+fn truncate(f: f64) -> i64 {
+	if (-9223372036854775808.0..9223372036854775808.0).contains(&f) {
+		f as i64
+	} else {
+		i64::MIN
+	}
+}
+
+#[inline(always)]
+fn cmp_i(stack: &mut Stack, f: impl Fn(i64, i64) -> bool) {
+	binop(stack, |b, a| f(b as i64, a as i64) as u64)
+}
+
+#[inline(always)]
+fn cmp_f(stack: &mut Stack, f: impl Fn(f64, f64) -> bool) {
+	binop(stack, |b, a| f(f64::from_bits(b), f64::from_bits(a)) as u64)
+}
+
+/// How a simulated program exited
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExitStatus {
+	pub code: i32,
+}
+
+impl ExitStatus {
+	pub fn success(&self) -> bool { self.code == 0 }
+}
+
+/// Error stopping a simulation, the messages start with the position of the op
+#[derive(Debug)]
+pub enum RuntimeError {
+	/// Invalid operation of the program, like an out of bounds access
+	Fault(String),
+	/// Limit exceeded or syscall policy violated
+	Sandbox(String),
+	/// Failure to write the trace, profile or coverage
+	Io(String),
+}
+
+impl RuntimeError {
+	/// Exit code of `ssmpl sim` for this error
+	pub fn exit_code(&self) -> i32 {
 		match self {
-			| Data::Ptr(v) | Data::I64(v) => *v != 0,
-			| Data::F64(v) => *v != 0.,
-			| Data::Bool(v) => *v,
+			| RuntimeError::Sandbox(_) => SANDBOX_EXIT_CODE,
+			| RuntimeError::Fault(_) | RuntimeError::Io(_) => 1,
 		}
 	}
+}
 
-	fn bytes_to_data_f64(&self) -> Data {
+/// Failure to write the output of the simulated program
+pub(crate) fn output_failed(e: std::io::Error) -> RuntimeError {
+	RuntimeError::Io(output_error(e))
+}
+
+impl Display for RuntimeError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
 		match self {
-			| Data::F64(v) => Data::F64(*v),
-			| a => Data::F64(f64::from_ne_bytes(a.to_i64().to_ne_bytes())),
+			| RuntimeError::Fault(msg)
+			| RuntimeError::Sandbox(msg)
+			| RuntimeError::Io(msg) => {
+				write!(f, "{msg}")
+			}
 		}
 	}
 }
 
-#[derive(Default)]
-struct CFLabels {
-	else_label:  i64,
-	while_label: i64,
-	end_label:   i64,
+impl std::error::Error for RuntimeError {}
+
+/// Function of the program embedding the simulator, called by `host "name" (ins
+/// -- outs)`. It gets the arguments and the memory, and returns the results or
+/// why it failed. Values are in stack order, the deepest first.
+pub type HostFn<'a> = dyn FnMut(&[u64], &mut [u8]) -> Result<Vec<u64>, String> + 'a;
+
+/// Host function registered in a `Simulator`
+pub struct Host<'io> {
+	name: String,
+	ins:  Vec<Type>,
+	outs: Vec<Type>,
+	f:    Box<HostFn<'io>>,
+}
+
+/// Stack effect as written in Ssmpl, `(ptr i64 -- i64)`
+fn signature(ins: &[Type], outs: &[Type]) -> String {
+	let types = |types: &[Type]| types.iter().map(Type::to_string).collect::<Vec<_>>();
+	format!("({})", [types(ins), vec!["--".into()], types(outs)].concat().join(" "))
+}
+
+/// Bytes of the memory overwritten by an instruction, to undo it
+pub struct Undo {
+	/// Step of the instruction
+	pub step: u64,
+	pub addr: usize,
+	pub old:  Vec<u8>,
+}
+
+/// Load or store of `N` bytes, with the conversion between the bytes and the
+/// value on the stack
+#[derive(Clone, Copy)]
+enum Access<const N: usize> {
+	Load(fn([u8; N]) -> u64),
+	Store(fn(u64) -> [u8; N]),
 }
 
-fn simulate_syscall(syscode: &usize) -> Option<i64> {
-	match syscode {
-		| 1 => Some(42),
-		| _ => None,
+/// Keeps the bytes about to be overwritten by the instruction `step`
+#[inline(always)]
+fn save(undo: &mut Option<Vec<Undo>>, step: u64, addr: usize, old: &[u8]) {
+	if let Some(undo) = undo {
+		undo.push(Undo { step, addr, old: old.to_vec() })
 	}
 }
 
-const MEM_LENGTH: usize = 256 * 1024;
+/// Keeps the bytes of `before` that the instruction `step` changed in the
+/// memory, one range of consecutive bytes at a time
+fn save_changes(undo: &mut Option<Vec<Undo>>, step: u64, before: &[u8], after: &[u8]) {
+	let mut addr = 0;
+	while let Some(start) = (addr..before.len()).find(|&i| before[i] != after[i]) {
+		addr = (start..before.len())
+			.find(|&i| before[i] == after[i])
+			.unwrap_or(before.len());
+		save(undo, step, start, &before[start..addr])
+	}
+}
+
+/// State of a simulation
+pub struct Machine<'io> {
+	pub code:       Vec<Instr>,
+	pub origins:    Vec<usize>,
+	pub firsts:     Vec<usize>,
+	pub stacks:     Vec<Vec<Type>>,
+	pub ptr_stores: HashSet<usize>,
+	pub ptr_loads:  HashSet<usize>,
+	pub regions:    Vec<Region>,
+	pub stack:      Stack,
+	pub memory:     Vec<u8>,
+	/// Next instruction
+	pub ip:         usize,
+	/// Instructions executed, only counted exactly by `CHECKED` runs
+	pub steps:      u64,
+	/// The run pauses before this step
+	pub pause_at:   u64,
+	/// Memory written by every step, only logged by `CHECKED` runs
+	pub undo:       Option<Vec<Undo>>,
+	/// Whether the program stopped with the exit syscall rather than after its
+	/// last op
+	pub exited:     bool,
+	pub sanitizer:  Option<Sanitizer>,
+	/// Findings of the sanitizer, formatted
+	pub reports:    Vec<String>,
+	pub tracer:     Option<Tracer>,
+	pub profiler:   Option<Profiler>,
+	pub counters:   Option<Counters>,
+	pub syscalls:   Syscalls,
+	pub io:         Io<'io>,
+	/// Registered host functions
+	pub hosts:      Vec<&'io mut HostFn<'io>>,
+	/// Host function, number of arguments and number of results of every host
+	/// call
+	pub calls:      Vec<(usize, usize, usize)>,
+	/// Last executed instruction, its op is traced once left
+	pub prev:       usize,
+}
 
 impl Program {
-	fn find_op_by_label(&self, label: &i64, op: &str) -> Option<(usize, &Op)> {
-		self.ops.iter().enumerate().find(|(_, f_op)| {
-			let Op { typ, .. } = f_op;
-			match op {
-				| "Then" => {
-					if let OpType::Then(f_label, ..) = typ {
-						if f_label == label {
-							return true;
-						}
-					}
-				}
-				| "Else" => {
-					if let OpType::Else(f_label, ..) = typ {
-						if f_label == label {
-							return true;
-						}
-					}
-				}
-				| "While" => {
-					if let OpType::While(f_label, ..) = typ {
-						if f_label == label {
-							return true;
-						}
-					}
-				}
-				| "Do" => {
-					if let OpType::Do(f_label, ..) = typ {
-						if f_label == label {
-							return true;
-						}
-					}
+	/// Error raised while simulating the op at `origin`
+	fn runtime_error(&self, origin: usize, msg: String) -> RuntimeError {
+		let annot = &self.ops[origin].annot;
+		RuntimeError::Fault(format!(
+			"{}: {msg}{}",
+			annot.get_pos(),
+			annot.expansion_trace()
+		))
+	}
+
+	/// Limit exceeded or policy violation at the op `origin`
+	fn sandbox_violation(&self, origin: usize, msg: String) -> RuntimeError {
+		let annot = &self.ops[origin].annot;
+		RuntimeError::Sandbox(format!(
+			"{}: {msg}{}",
+			annot.get_pos(),
+			annot.expansion_trace()
+		))
+	}
+
+	/// `--overflow-checks`, the compiled program prints the same message
+	#[cold]
+	pub fn integer_overflow(&self, origin: usize) -> RuntimeError {
+		let msg = format!("Integer overflow in {}", self.ops[origin].typ);
+		self.runtime_error(origin, msg)
+	}
+
+	/// Integer division by zero, the compiled program prints the same message
+	#[cold]
+	pub fn division_by_zero(&self, origin: usize) -> RuntimeError {
+		let msg = format!("Division by zero in {}", self.ops[origin].typ);
+		self.runtime_error(origin, msg)
+	}
+
+	#[cold]
+	fn stack_overflow(&self, origin: usize, max_stack: usize) -> RuntimeError {
+		let msg = format!(
+			"Exceeded the limit of {max_stack} values on the stack (`--max-stack`)"
+		);
+		self.sandbox_violation(origin, msg)
+	}
+
+	fn out_of_bounds(
+		&self,
+		origin: usize,
+		addr: u64,
+		size: usize,
+		memory: &[u8],
+		regions: &[Region],
+	) -> RuntimeError {
+		let nearest =
+			regions.iter().min_by_key(|region| region.distance(addr as i64)).unwrap();
+		let msg = format!(
+			"Out of bounds {}: {size} byte(s) at address {} but the memory is {} bytes \
+			 long (see `--sim-mem`)\n\tNearest region: {nearest}",
+			self.ops[origin].typ,
+			addr as i64,
+			memory.len()
+		);
+		self.runtime_error(origin, msg)
+	}
+
+	/// Loads or stores `N` bytes at the pointer on the stack (under the value
+	/// stored): checks the bounds, reports what the sanitizer finds, keeps the
+	/// bytes overwritten for the undo log and traces the ones written.
+	/// `ptrs` are the loads or stores of pointers.
+	#[inline(always)]
+	#[allow(clippy::too_many_arguments)]
+	fn access_memory<const N: usize, const CHECKED: bool>(
+		&self,
+		ip: usize,
+		origin: usize,
+		steps: u64,
+		access: Access<N>,
+		stack: &mut Stack,
+		memory: &mut [u8],
+		regions: &[Region],
+		ptrs: &HashSet<usize>,
+		sanitizer: &mut Option<Sanitizer>,
+		reports: &mut Vec<String>,
+		undo: &mut Option<Vec<Undo>>,
+		tracer: &mut Option<Tracer>,
+	) -> Result<(), RuntimeError> {
+		let val = if let Access::Store(_) = access { stack.pop() } else { 0 };
+		let ptr = stack.pop();
+		let Some(bytes) = access_bytes::<N>(memory, ptr) else {
+			return Err(self.out_of_bounds(origin, ptr, N, memory, regions));
+		};
+		if let Some(sanitizer) = sanitizer {
+			let op = self.ops[origin].typ.to_string();
+			let is_ptr = ptrs.contains(&ip);
+			let findings = match access {
+				| Access::Load(_) => sanitizer.load(ip, &op, ptr as usize, N, is_ptr),
+				| Access::Store(_) => {
+					sanitizer.store(ip, origin, &op, ptr as usize, N, is_ptr)
 				}
-				| "EndIf" => {
-					if let OpType::End(f_label, false, ..) = typ {
-						if f_label == label {
-							return true;
-						}
-					}
+			};
+			self.report_findings(origin, findings, reports)
+		}
+		match access {
+			| Access::Load(from) => stack.push(from(*bytes)),
+			| Access::Store(to) => {
+				if CHECKED {
+					save(undo, steps, ptr as usize, bytes)
 				}
-				| "EndWhile" => {
-					if let OpType::End(f_label, true, ..) = typ {
-						if f_label == label {
-							return true;
-						}
-					}
+				*bytes = to(val);
+				if let Some(tracer) = tracer {
+					tracer.write(ptr, bytes)
 				}
-				| _ => unreachable!(),
 			}
-			false
-		})
+		}
+		Ok(())
 	}
 
-	#[allow(clippy::identity_op)]
-	pub fn simulate(&mut self) {
-		use OpType::*;
-
-		let mut stack: Vec<Data> = vec![];
-		let mut cf_labels: Vec<CFLabels> = vec![];
-		let mut ip = 0;
-		let mut labels_map: HashMap<i64, (usize, usize)> = HashMap::new(); // label -> (if_idx, while_idx)
-		let mut memory: [u8; MEM_LENGTH] = [0; MEM_LENGTH];
-		let mut strings_ptr: HashMap<String, usize> = HashMap::new();
-		let mut memory_regions_ptr: HashMap<String, usize> = HashMap::new();
-		let mut mem_free_ptr = 0;
-
-		for lit in self.strings.iter() {
-			strings_ptr.insert(lit.clone(), mem_free_ptr);
-			for byte in lit.as_bytes() {
-				if mem_free_ptr >= MEM_LENGTH {
-					self.add_error("Not enough memory for strings allocation".into())
-						.exit(1);
-				}
-				memory[mem_free_ptr] = *byte;
-				mem_free_ptr += 1;
-			}
+	fn report_findings(
+		&self,
+		origin: usize,
+		findings: Vec<Finding>,
+		reports: &mut Vec<String>,
+	) {
+		for Finding { msg, last_write } in findings {
+			let last_write = match last_write {
+				| Writer::Never => "Never written".to_string(),
+				| Writer::Literal => "Initialized with a string literal".to_string(),
+				| Writer::Op(op) => {
+					format!("Last written at {}", self.ops[op].annot.get_pos())
+				}
+			};
+			let annot = &self.ops[origin].annot;
+			reports.push(format!(
+				"{}: Sanitizer: {msg}{}\n\t{last_write}",
+				annot.get_pos(),
+				annot.expansion_trace()
+			));
 		}
+	}
 
-		for (name, size) in self.memory_regions.iter() {
-			memory_regions_ptr.insert(name.clone(), mem_free_ptr);
-			mem_free_ptr += *size as usize;
-		}
+	/// Runs the machine until the end of the program and returns its exit code,
+	/// or `None` if it paused at `pause_at`. The machine is left at the start of
+	/// the run on errors.
+	///
+	/// The limits and the instrumentation are only `CHECKED` when needed, so that
+	/// they cost nothing otherwise.
+	pub fn run<const CHECKED: bool>(
+		&self,
+		m: &mut Machine,
+		cli: &Cli<String>,
+	) -> Result<Option<i32>, RuntimeError> {
+		use Instr::*;
 
-		while ip < self.ops.len() {
-			let Op { typ, annot } = &self.ops[ip];
-			match typ {
-				| PushI(i) => stack.push(Data::I64(*i)),
-				| PushB(b) => stack.push(Data::Bool(*b)),
-				| PushF(f) => stack.push(Data::F64(*f)),
-				| PushStr(s) => {
-					stack.push(Data::Ptr(*strings_ptr.get(s).unwrap() as i64))
-				}
-				| Dump(_) => {
-					match stack.pop().unwrap() {
-						| Data::I64(i) | Data::Ptr(i) => println!("{}", i),
-						| Data::F64(f) => println!("{:?}", f),
-						| Data::Bool(b) => println!("{}", b),
-					}
+		let Machine {
+			code,
+			origins,
+			stacks,
+			ptr_stores,
+			ptr_loads,
+			regions,
+			stack,
+			memory,
+			ip: next,
+			steps: executed,
+			pause_at,
+			undo,
+			exited,
+			sanitizer,
+			reports,
+			tracer,
+			profiler,
+			counters,
+			syscalls,
+			io,
+			hosts,
+			calls,
+			prev,
+			..
+		} = m;
+		let mut status = 0;
+		let mut ip = *next;
+		let start = Instant::now();
+		let max_steps = cli.max_steps.unwrap_or(u64::MAX);
+		let max_stack = cli.max_stack;
+		let instrumented = tracer.is_some() || profiler.is_some() || counters.is_some();
+		// Instructions executed before the last check of the limits
+		let mut steps = *executed;
+		// The limits are checked and the instrumentation is run every `interval`
+		// instructions, `countdown` being the number left until the next time
+		let mut interval = 0;
+		let mut countdown = 1;
+
+		while ip < code.len() {
+			if CHECKED {
+				countdown -= 1;
+			}
+			if CHECKED && countdown == 0 {
+				steps += interval;
+				if steps == *pause_at {
+					*next = ip;
+					*executed = steps;
+					return Ok(None);
 				}
-				| Add(..) => {
-					match (stack.pop().unwrap(), stack.pop().unwrap()) {
-						| (Data::F64(v1), Data::I64(v2)) => {
-							stack.push(Data::F64(v2 as f64 + v1))
-						}
-						| (Data::I64(v1), Data::F64(v2)) => {
-							stack.push(Data::F64(v2 + v1 as f64))
-						}
-						| (Data::F64(v1), Data::F64(v2)) => {
-							stack.push(Data::F64(v2 + v1))
-						}
-						| (a, b) => stack.push(Data::I64(b.to_i64() + a.to_i64())),
+				if let Some(tracer) = tracer {
+					if *prev != usize::MAX
+						&& (ip != *prev + 1 || origins[ip] != origins[*prev])
+					{
+						let op = origins[*prev];
+						tracer.record(
+							op,
+							&self.ops[op],
+							&stack.slots[..stack.sp],
+							&stacks[op],
+						)?;
 					}
+					*prev = ip;
 				}
-				| Sub(..) => {
-					match (stack.pop().unwrap(), stack.pop().unwrap()) {
-						| (Data::F64(v1), Data::I64(v2)) => {
-							stack.push(Data::F64(v2 as f64 - v1))
-						}
-						| (Data::I64(v1), Data::F64(v2)) => {
-							stack.push(Data::F64(v2 - v1 as f64))
-						}
-						| (Data::F64(v1), Data::F64(v2)) => {
-							stack.push(Data::F64(v2 - v1))
-						}
-						| (a, b) => stack.push(Data::I64(b.to_i64() - a.to_i64())),
-					}
+				if let Some(profiler) = profiler {
+					profiler.hits[ip] += 1;
 				}
-				| Mul(..) => {
-					match (stack.pop().unwrap(), stack.pop().unwrap()) {
-						| (Data::F64(v1), Data::I64(v2)) => {
-							stack.push(Data::F64(v2 as f64 * v1))
-						}
-						| (Data::I64(v1), Data::F64(v2)) => {
-							stack.push(Data::F64(v2 * v1 as f64))
-						}
-						| (Data::F64(v1), Data::F64(v2)) => {
-							stack.push(Data::F64(v2 * v1))
-						}
-						| (a, b) => stack.push(Data::I64(b.to_i64() * a.to_i64())),
-					}
+				if let Some(counters) = counters {
+					counters.hit(ip);
 				}
-				| Div(..) => {
-					match (stack.pop().unwrap(), stack.pop().unwrap()) {
-						| (Data::F64(v1), Data::I64(v2)) => {
-							stack.push(Data::F64(v2 as f64 / v1))
-						}
-						| (Data::I64(v1), Data::F64(v2)) => {
-							stack.push(Data::F64(v2 / v1 as f64))
-						}
-						| (Data::F64(v1), Data::F64(v2)) => {
-							stack.push(Data::F64(v2 / v1))
-						}
-						| (a, b) => stack.push(Data::I64(b.to_i64() / a.to_i64())),
-					}
+				if steps == max_steps {
+					let msg = format!(
+						"Exceeded the limit of {max_steps} steps (`--max-steps`)"
+					);
+					return Err(self.sandbox_violation(origins[ip], msg));
 				}
-				| Increment(_) => {
-					match stack.pop().unwrap() {
-						| Data::F64(f) => stack.push(Data::F64(f + 1.)),
-						| a => stack.push(Data::I64(a.to_i64() + 1)),
-					}
+				if let Some(timeout) = cli.timeout.filter(|t| start.elapsed() > *t) {
+					let msg =
+						format!("Exceeded the timeout of {timeout:?} (`--timeout`)");
+					return Err(self.sandbox_violation(origins[ip], msg));
 				}
-				| Decrement(_) => {
-					match stack.pop().unwrap() {
-						| Data::F64(f) => stack.push(Data::F64(f - 1.)),
-						| a => stack.push(Data::I64(a.to_i64() - 1)),
+				interval = if instrumented {
+					1
+				} else {
+					(max_steps.min(*pause_at) - steps).min(TIMEOUT_CHECK_INTERVAL)
+				};
+				countdown = interval;
+			}
+			match code[ip] {
+				| Push(v) => {
+					stack.push(v);
+					if stack.sp > max_stack {
+						return Err(self.stack_overflow(origins[ip], max_stack));
 					}
 				}
-				| Mod(..) => {
-					match (stack.pop().unwrap(), stack.pop().unwrap()) {
-						| (a, b) => {
-							stack.push(Data::I64(b.to_i64().rem_euclid(a.to_i64())))
-						}
-					}
+				| DumpI => {
+					writeln!(io.stdout, "{}", stack.pop() as i64)
+						.map_err(output_failed)?
 				}
-				| Drop(n) => {
-					for _ in 0..*n {
-						stack.pop();
-					}
+				| DumpF => {
+					writeln!(io.stdout, "{:?}", f64::from_bits(stack.pop()))
+						.map_err(output_failed)?
 				}
-				| Swap => {
-					let a = stack.pop().unwrap();
-					let b = stack.pop().unwrap();
-					stack.push(a);
-					stack.push(b);
+				| DumpB => {
+					writeln!(io.stdout, "{}", stack.pop() != 0).map_err(output_failed)?
 				}
-				| Over(n) => stack.push(stack[stack.len() - *n as usize - 1].clone()),
-				| Dup(n) => {
-					for _ in 0..*n {
-						stack.push(stack[stack.len() - *n as usize].clone());
-					}
+				| ToF64(depth) => {
+					let v = stack.nth(depth as usize);
+					*v = (*v as i64 as f64).to_bits();
 				}
-				| If(label_count) => {
-					let if_label = cf_labels.len();
-					match labels_map.get_mut(label_count) {
-						| Some((curr, _)) => {
-							*curr = if_label;
-						}
-						| None => {
-							labels_map.insert(*label_count, (if_label, 0));
-						}
+				| ToI64 => {
+					let v = stack.top();
+					*v = truncate(f64::from_bits(*v)) as u64;
+				}
+				// Like `roundsd`, which rounds ties to even
+				| RoundF => unop_f(stack, f64::round_ties_even),
+				| FloorF => unop_f(stack, f64::floor),
+				| CeilF => unop_f(stack, f64::ceil),
+				| SqrtF => unop_f(stack, f64::sqrt),
+				| AbsF => *stack.top() &= !(1 << 63),
+				| NegF => *stack.top() ^= 1 << 63,
+				| ExpF => unop_f(stack, math::exp),
+				| LogF => unop_f(stack, math::log),
+				| SinF => unop_f(stack, math::sin),
+				| CosF => unop_f(stack, math::cos),
+				| FmodF => binop_f(stack, math::fmod),
+				// Like `minsd` and `maxsd`, which give the second operand if the
+				// comparison is false
+				| MinF => binop_f(stack, |b, a| if b < a { b } else { a }),
+				| MaxF => binop_f(stack, |b, a| if b > a { b } else { a }),
+				| PowF => binop_f(stack, math::pow),
+				| FToBool(depth) => {
+					let v = stack.nth(depth as usize);
+					*v = (f64::from_bits(*v) != 0.) as u64;
+				}
+				| ToBool => {
+					let v = stack.top();
+					*v = (*v != 0) as u64;
+				}
+				| AddI => binop_i(stack, i64::wrapping_add),
+				| SubI => binop_i(stack, i64::wrapping_sub),
+				| MulI => binop_i(stack, i64::wrapping_mul),
+				| AddSat => binop_i(stack, i64::saturating_add),
+				| SubSat => binop_i(stack, i64::saturating_sub),
+				| MulSat => binop_i(stack, i64::saturating_mul),
+				| AddChecked => checked(stack, i64::overflowing_add),
+				| SubChecked => checked(stack, i64::overflowing_sub),
+				| MulChecked => checked(stack, i64::overflowing_mul),
+				| AddTrap | SubTrap | MulTrap => {
+					let a = stack.pop() as i64;
+					let b = stack.top();
+					let result = match code[ip] {
+						| AddTrap => (*b as i64).checked_add(a),
+						| SubTrap => (*b as i64).checked_sub(a),
+						| _ => (*b as i64).checked_mul(a),
+					};
+					match result {
+						| Some(v) => *b = v as u64,
+						| None => return Err(self.integer_overflow(origins[ip])),
 					}
-					cf_labels.push(CFLabels::default());
-					let (else_label, _) = self
-						.find_op_by_label(label_count, "Else")
-						.unwrap_or((0, &Op { typ: Nop, annot: annot.clone() }));
-					let (end_label, _) =
-						self.find_op_by_label(label_count, "EndIf").unwrap();
-					cf_labels.as_mut_slice()[if_label].else_label = else_label as i64;
-					cf_labels.as_mut_slice()[if_label].end_label = end_label as i64;
-				}
-				| While(label_count) => {
-					let while_label = cf_labels.len();
-					match labels_map.get_mut(label_count) {
-						| Some((curr, _)) => {
-							*curr = while_label;
-						}
-						| None => {
-							labels_map.insert(*label_count, (while_label, 0));
-						}
+				}
+				| DivI | Mod | DivModI | DivModFloorI => {
+					let a = stack.pop() as i64;
+					if a == 0 {
+						return Err(self.division_by_zero(origins[ip]));
 					}
-					cf_labels.push(CFLabels::default());
-					let (end_label, _) =
-						self.find_op_by_label(label_count, "EndWhile").unwrap();
-					cf_labels.as_mut_slice()[*label_count as usize].while_label =
-						ip as i64;
-					cf_labels.as_mut_slice()[*label_count as usize].end_label =
-						end_label as i64;
-				}
-				| Then(label_count, else_) => {
-					let (idx, _) = labels_map.get(label_count).unwrap();
-					match stack.pop().unwrap() {
-						| Data::Bool(false)
-						| Data::I64(0)
-						| Data::F64(0.0)
-						| Data::Ptr(0) => {
-							if *else_ {
-								ip = cf_labels[*idx].else_label as usize;
-							} else {
-								ip = cf_labels[*idx].end_label as usize;
+					let b = stack.top();
+					let mut q = (*b as i64).wrapping_div(a);
+					let mut r = (*b as i64).wrapping_rem(a);
+					match code[ip] {
+						| DivI => *b = q as u64,
+						| Mod => *b = r as u64,
+						| instr => {
+							if matches!(instr, DivModFloorI) && r != 0 && (r ^ a) < 0 {
+								q -= 1;
+								r += a;
 							}
+							*b = q as u64;
+							stack.push(r as u64);
 						}
-						| _ => (),
 					}
 				}
-				| Else(label_count, ..) => {
-					let (idx, _) = labels_map.get(label_count).unwrap();
-					ip = cf_labels[*idx].end_label as usize
+				| AddF => binop_f(stack, |b, a| b + a),
+				| SubF => binop_f(stack, |b, a| b - a),
+				| MulF => binop_f(stack, |b, a| b * a),
+				| DivF => binop_f(stack, |b, a| b / a),
+				| IncI => {
+					let v = stack.top();
+					*v = v.wrapping_add(1);
 				}
-				| End(label_count, while_, ..) => {
-					let (_, idx) = labels_map.get(label_count).unwrap();
-					if *while_ {
-						ip = cf_labels[*idx].while_label as usize - 1;
-					}
+				| DecI => {
+					let v = stack.top();
+					*v = v.wrapping_sub(1);
+				}
+				| IncF => {
+					let v = stack.top();
+					*v = (f64::from_bits(*v) + 1.).to_bits();
+				}
+				| DecF => {
+					let v = stack.top();
+					*v = (f64::from_bits(*v) - 1.).to_bits();
+				}
+				| Drop(n) => stack.drop(n as usize),
+				| Swap => {
+					let sp = stack.sp;
+					stack.slots.swap(sp - 1, sp - 2);
+				}
+				| Rot => {
+					let sp = stack.sp;
+					stack.slots[sp - 3..sp].rotate_left(1);
+				}
+				| Unrot => {
+					let sp = stack.sp;
+					stack.slots[sp - 3..sp].rotate_right(1);
+				}
+				| Nip => {
+					let v = stack.pop();
+					*stack.top() = v;
 				}
-				| Do(label_count, ..) => {
-					let (_, idx) = labels_map.get(label_count).unwrap();
-					match stack.pop().unwrap() {
-						| Data::Bool(false)
-						| Data::I64(0)
-						| Data::F64(0.0)
-						| Data::Ptr(0) => ip = cf_labels[*idx].end_label as usize,
-						| _ => (),
+				| Tuck => {
+					let v = *stack.top();
+					let sp = stack.sp;
+					stack.slots.swap(sp - 1, sp - 2);
+					stack.push(v);
+					if stack.sp > max_stack {
+						return Err(self.stack_overflow(origins[ip], max_stack));
 					}
 				}
-				| Eq(..) => {
-					match (stack.pop().unwrap(), stack.pop().unwrap()) {
-						| (Data::F64(val_r), Data::F64(val_l)) => {
-							stack.push(Data::Bool(val_l == val_r))
-						}
-						| (a, Data::F64(val_l)) => {
-							stack.push(Data::Bool(val_l == a.to_i64() as f64))
-						}
-						| (Data::F64(val_r), b) => {
-							stack.push(Data::Bool(b.to_i64() as f64 == val_r))
-						}
-						| (a, b) => stack.push(Data::Bool(b.to_i64() == a.to_i64())),
+				| Swap2 => {
+					let sp = stack.sp;
+					stack.slots[sp - 4..sp].rotate_left(2);
+				}
+				| Roll(n) => {
+					let sp = stack.sp;
+					stack.slots[sp - n as usize - 1..sp].rotate_left(1);
+				}
+				| Over(n) => {
+					let v = *stack.nth(n as usize);
+					stack.push(v);
+					if stack.sp > max_stack {
+						return Err(self.stack_overflow(origins[ip], max_stack));
 					}
 				}
-				| Neq(..) => {
-					match (stack.pop().unwrap(), stack.pop().unwrap()) {
-						| (Data::F64(val_r), Data::F64(val_l)) => {
-							stack.push(Data::Bool(val_l != val_r))
-						}
-						| (a, Data::F64(val_l)) => {
-							stack.push(Data::Bool(val_l != a.to_i64() as f64))
-						}
-						| (Data::F64(val_r), b) => {
-							stack.push(Data::Bool(b.to_i64() as f64 != val_r))
-						}
-						| (a, b) => stack.push(Data::Bool(b.to_i64() != a.to_i64())),
+				| Dup(n) => {
+					for _ in 0..n {
+						let v = *stack.nth(n as usize - 1);
+						stack.push(v)
+					}
+					if stack.sp > max_stack {
+						return Err(self.stack_overflow(origins[ip], max_stack));
 					}
 				}
-				| Lt(..) => {
-					match (stack.pop().unwrap(), stack.pop().unwrap()) {
-						| (Data::F64(val_r), Data::F64(val_l)) => {
-							stack.push(Data::Bool(val_l < val_r))
-						}
-						| (a, Data::F64(val_l)) => {
-							stack.push(Data::Bool(val_l < a.to_i64() as f64))
-						}
-						| (Data::F64(val_r), b) => {
-							stack.push(Data::Bool((b.to_i64() as f64) < val_r))
-						}
-						| (a, b) => stack.push(Data::Bool(b.to_i64() < a.to_i64())),
+				| SetOver(n) => {
+					let set = stack.pop();
+					*stack.nth(n as usize - 1) = set;
+				}
+				| Jmp(to) => {
+					ip = to as usize;
+					continue;
+				}
+				| JmpZ(to) => {
+					if stack.pop() == 0 {
+						ip = to as usize;
+						continue;
 					}
 				}
-				| Gt(..) => {
-					match (stack.pop().unwrap(), stack.pop().unwrap()) {
-						| (Data::F64(val_r), Data::F64(val_l)) => {
-							stack.push(Data::Bool(val_l > val_r))
-						}
-						| (a, Data::F64(val_l)) => {
-							stack.push(Data::Bool(val_l > a.to_i64() as f64))
-						}
-						| (Data::F64(val_r), b) => {
-							stack.push(Data::Bool(b.to_i64() as f64 > val_r))
-						}
-						| (a, b) => stack.push(Data::Bool(b.to_i64() > a.to_i64())),
+				| JmpZF(to) => {
+					if f64::from_bits(stack.pop()) == 0. {
+						ip = to as usize;
+						continue;
 					}
 				}
-				| Lte(..) => {
-					match (stack.pop().unwrap(), stack.pop().unwrap()) {
-						| (Data::F64(val_r), Data::F64(val_l)) => {
-							stack.push(Data::Bool(val_l <= val_r))
+				| EqI => cmp_i(stack, |b, a| b == a),
+				| NeqI => cmp_i(stack, |b, a| b != a),
+				| LtI => cmp_i(stack, |b, a| b < a),
+				| GtI => cmp_i(stack, |b, a| b > a),
+				| LteI => cmp_i(stack, |b, a| b <= a),
+				| GteI => cmp_i(stack, |b, a| b >= a),
+				| EqF => cmp_f(stack, |b, a| b == a),
+				| NeqF => cmp_f(stack, |b, a| b != a),
+				| LtF => cmp_f(stack, |b, a| b < a),
+				| GtF => cmp_f(stack, |b, a| b > a),
+				| LteF => cmp_f(stack, |b, a| b <= a),
+				| GteF => cmp_f(stack, |b, a| b >= a),
+				| Syscall(syscode, argc) => {
+					let args = &stack.slots[stack.sp - argc as usize..stack.sp];
+					if CHECKED && undo.is_some() {
+						if let Some((ptr, old)) = written(syscode as u64, args, memory) {
+							save(undo, steps, ptr, old)
 						}
-						| (a, Data::F64(val_l)) => {
-							stack.push(Data::Bool(val_l <= a.to_i64() as f64))
+					}
+					match syscalls.call(syscode as u64, args, memory, io) {
+						| Ok(ret) => {
+							if let Some(tracer) = tracer {
+								// Bytes read into the memory, none on errors
+								let read = (ret as i64).max(0) as usize;
+								if let Some((ptr, bytes)) =
+									written(syscode as u64, args, memory)
+										.filter(|_| read > 0)
+								{
+									tracer.write(
+										ptr as u64,
+										&bytes[..read.min(bytes.len())],
+									)
+								}
+							}
+							stack.drop(argc as usize);
+							stack.push(ret);
+							if stack.sp > max_stack {
+								return Err(self.stack_overflow(origins[ip], max_stack));
+							}
 						}
-						| (Data::F64(val_r), b) => {
-							stack.push(Data::Bool(b.to_i64() as f64 <= val_r))
+						| Err(Stop::Exit(exit_code)) => {
+							status = exit_code;
+							*exited = true;
+							ip = code.len();
+							continue;
 						}
-						| (a, b) => stack.push(Data::Bool(b.to_i64() <= a.to_i64())),
-					}
-				}
-				| Gte(..) => {
-					match (stack.pop().unwrap(), stack.pop().unwrap()) {
-						| (Data::F64(val_r), Data::F64(val_l)) => {
-							stack.push(Data::Bool(val_l >= val_r))
+						| Err(Stop::NotImplemented) => {
+							let msg = format!(
+								"Syscall {syscode} not implemented in simulation mode"
+							);
+							return Err(self.runtime_error(origins[ip], msg));
 						}
-						| (a, Data::F64(val_l)) => {
-							stack.push(Data::Bool(val_l >= a.to_i64() as f64))
+						| Err(Stop::Denied(reason)) => {
+							let msg = format!(
+								"Syscall policy violation: {reason} (`--policy`)"
+							);
+							return Err(self.sandbox_violation(origins[ip], msg));
 						}
-						| (Data::F64(val_r), b) => {
-							stack.push(Data::Bool(b.to_i64() as f64 >= val_r))
+						| Err(Stop::Diverged(difference)) => {
+							let msg =
+								format!("Replay diverged: {difference} (`--replay`)");
+							return Err(self.runtime_error(origins[ip], msg));
 						}
-						| (a, b) => stack.push(Data::Bool(b.to_i64() >= a.to_i64())),
+						| Err(Stop::Io(msg)) => return Err(RuntimeError::Io(msg)),
 					}
 				}
-				| Syscall(syscode, _argc) => {
-					match simulate_syscall(syscode) {
-						| Some(val) => stack.push(Data::I64(val)),
-						| None => {
-							self.add_error(format!(
-								"{}: Syscall {} not implemented",
-								annot.get_pos(),
-								syscode
-							))
-							.exit(1)
-						}
+				| Host(call) => {
+					let (host, argc, outc) = calls[call as usize];
+					let args = &stack.slots[stack.sp - argc..stack.sp];
+					// Host functions may write anywhere in the memory
+					let before = (CHECKED && undo.is_some()).then(|| memory.to_vec());
+					let results = hosts[host](args, memory);
+					if let Some(before) = before {
+						save_changes(undo, steps, &before, memory)
+					}
+					if !results.as_ref().is_ok_and(|results| results.len() == outc) {
+						let OpType::Host(name, ..) = &self.ops[origins[ip]].typ else {
+							unreachable!()
+						};
+						let msg = match results {
+							| Ok(results) => {
+								format!(
+									"Host function `{name}` returned {} value(s) but \
+									 declares {outc}",
+									results.len()
+								)
+							}
+							| Err(e) => format!("Host function `{name}` failed: {e}"),
+						};
+						return Err(self.runtime_error(origins[ip], msg));
+					}
+					stack.drop(argc);
+					for v in results.unwrap() {
+						stack.push(v)
+					}
+					if stack.sp > max_stack {
+						return Err(self.stack_overflow(origins[ip], max_stack));
 					}
 				}
-				| Argc | Argv => {
-					self.add_error(
-						"Program Argument is not supported in simulation mode".into(),
-					)
-					.exit(1)
+				| Exit => {
+					status = stack.pop() as i32;
+					*exited = true;
+					ip = code.len();
+					continue;
+				}
+				| ProgramArgs => {
+					let msg = "Program Argument is not supported in simulation mode";
+					return Err(self.runtime_error(origins[ip], msg.into()));
 				}
 				| Load8 => {
-					let ptr = stack.pop().unwrap().to_i64() as usize;
-					stack.push(Data::I64(memory[ptr] as i64))
+					self.access_memory::<1, CHECKED>(
+						ip,
+						origins[ip],
+						steps,
+						Access::Load(|bytes| u8::from_ne_bytes(bytes) as u64),
+						stack,
+						memory,
+						regions,
+						ptr_loads,
+						sanitizer,
+						reports,
+						undo,
+						tracer,
+					)?
 				}
 				| Load16 => {
-					let ptr = stack.pop().unwrap().to_i64() as usize;
-					let bytes: [u8; 2] = memory[ptr..ptr + 2].try_into().unwrap();
-					stack.push(Data::I64(u16::from_ne_bytes(bytes) as i64))
+					self.access_memory::<2, CHECKED>(
+						ip,
+						origins[ip],
+						steps,
+						Access::Load(|bytes| u16::from_ne_bytes(bytes) as u64),
+						stack,
+						memory,
+						regions,
+						ptr_loads,
+						sanitizer,
+						reports,
+						undo,
+						tracer,
+					)?
 				}
 				| Load32 => {
-					let ptr = stack.pop().unwrap().to_i64() as usize;
-					let bytes: [u8; 4] = memory[ptr..ptr + 4].try_into().unwrap();
-					stack.push(Data::I64(u32::from_ne_bytes(bytes) as i64))
+					self.access_memory::<4, CHECKED>(
+						ip,
+						origins[ip],
+						steps,
+						Access::Load(|bytes| u32::from_ne_bytes(bytes) as u64),
+						stack,
+						memory,
+						regions,
+						ptr_loads,
+						sanitizer,
+						reports,
+						undo,
+						tracer,
+					)?
 				}
 				| Load64 => {
-					let ptr = stack.pop().unwrap().to_i64() as usize;
-					let bytes: [u8; 8] = memory[ptr..ptr + 8].try_into().unwrap();
-					stack.push(Data::I64(u64::from_ne_bytes(bytes) as i64))
+					self.access_memory::<8, CHECKED>(
+						ip,
+						origins[ip],
+						steps,
+						Access::Load(u64::from_ne_bytes),
+						stack,
+						memory,
+						regions,
+						ptr_loads,
+						sanitizer,
+						reports,
+						undo,
+						tracer,
+					)?
 				}
 				| Store8 => {
-					let val = stack.pop().unwrap().to_i64();
-					let ptr = stack.pop().unwrap().to_i64() as usize;
-					memory[ptr] = (val & 0xFF) as u8
+					self.access_memory::<1, CHECKED>(
+						ip,
+						origins[ip],
+						steps,
+						Access::Store(|val| (val as u8).to_ne_bytes()),
+						stack,
+						memory,
+						regions,
+						ptr_stores,
+						sanitizer,
+						reports,
+						undo,
+						tracer,
+					)?
 				}
 				| Store16 => {
-					let val = stack.pop().unwrap().to_i64();
-					let ptr = stack.pop().unwrap().to_i64() as usize;
-					memory[ptr + 0] = (val & 0xFF00) as u8;
-					memory[ptr + 1] = (val & 0x00FF) as u8;
+					self.access_memory::<2, CHECKED>(
+						ip,
+						origins[ip],
+						steps,
+						Access::Store(|val| (val as u16).to_ne_bytes()),
+						stack,
+						memory,
+						regions,
+						ptr_stores,
+						sanitizer,
+						reports,
+						undo,
+						tracer,
+					)?
 				}
 				| Store32 => {
-					let val = stack.pop().unwrap().to_i64();
-					let ptr = stack.pop().unwrap().to_i64() as usize;
-					for i in 0..4 {
-						memory[ptr + i] = (val & (0xFF << (3 - i))) as u8
-					}
+					self.access_memory::<4, CHECKED>(
+						ip,
+						origins[ip],
+						steps,
+						Access::Store(|val| (val as u32).to_ne_bytes()),
+						stack,
+						memory,
+						regions,
+						ptr_stores,
+						sanitizer,
+						reports,
+						undo,
+						tracer,
+					)?
 				}
 				| Store64 => {
-					let val = stack.pop().unwrap().to_i64();
-					let ptr = stack.pop().unwrap().to_i64() as usize;
-					for i in 0..8 {
-						memory[ptr + i] = (val & (0xFF << (7 - i))) as u8
-					}
+					self.access_memory::<8, CHECKED>(
+						ip,
+						origins[ip],
+						steps,
+						Access::Store(u64::to_ne_bytes),
+						stack,
+						memory,
+						regions,
+						ptr_stores,
+						sanitizer,
+						reports,
+						undo,
+						tracer,
+					)?
 				}
-				| Cast(typ) => {
-					let stack_val = stack.pop().unwrap();
-					stack.push(match typ {
-						| Type::I64 => Data::I64(stack_val.to_i64()),
-						| Type::F64 => {
-							Data::F64(f64::from_ne_bytes(
-								stack_val.to_i64().to_ne_bytes(),
-							))
-						}
-						| Type::Bool => Data::Bool(stack_val.to_i64() != 0),
-						| Type::Ptr => Data::Ptr(stack_val.to_i64()),
-					})
-				}
-				| ShiftR => {
-					match (stack.pop().unwrap(), stack.pop().unwrap()) {
-						| (Data::I64(v1), Data::I64(v2)) => {
-							stack.push(Data::I64(v2 >> v1))
-						}
-						| (Data::I64(v1), Data::F64(v2)) => {
-							stack.push(
-								Data::I64(Data::F64(v2).to_i64() >> v1)
-									.bytes_to_data_f64(),
-							)
-						}
-						| (Data::I64(v1), Data::Bool(v2)) => {
-							stack.push(Data::Bool((v2 as i64 >> v1) != 0))
-						}
-						| (Data::I64(v1), Data::Ptr(v2)) => {
-							stack.push(Data::Ptr(v2 >> v1))
-						}
-						| _ => unreachable!("Prevented by type check"),
-					}
+				| ShiftR => binop(stack, shr),
+				| ShiftRA => binop(stack, sar),
+				| ShiftL => binop(stack, shl),
+				| RotL => binop(stack, |b, a| b.rotate_left((a % 64) as u32)),
+				| RotR => binop(stack, |b, a| b.rotate_right((a % 64) as u32)),
+				| BitAnd => binop(stack, |b, a| b & a),
+				| BitOr => binop(stack, |b, a| b | a),
+				| Xor => binop(stack, |b, a| b ^ a),
+				| Popcount => *stack.top() = stack.top().count_ones() as u64,
+				| Clz => *stack.top() = stack.top().leading_zeros() as u64,
+				| Ctz => *stack.top() = stack.top().trailing_zeros() as u64,
+				| And => binop(stack, |b, a| (b != 0 && a != 0) as u64),
+				| Or => binop(stack, |b, a| (b != 0 || a != 0) as u64),
+				| Not => {
+					let v = stack.top();
+					*v = !*v;
 				}
-				| ShiftL => {
-					match (stack.pop().unwrap(), stack.pop().unwrap()) {
-						| (Data::I64(v1), Data::I64(v2)) => {
-							stack.push(Data::I64(v2 << v1))
-						}
-						| (Data::I64(v1), Data::F64(v2)) => {
-							stack.push(
-								Data::I64(Data::F64(v2).to_i64() << v1)
-									.bytes_to_data_f64(),
-							)
-						}
-						| (Data::I64(v1), Data::Bool(v2)) => {
-							stack.push(Data::Bool(((v2 as i64) << v1) != 0))
-						}
-						| (Data::I64(v1), Data::Ptr(v2)) => {
-							stack.push(Data::Ptr(v2 << v1))
-						}
-						| _ => unreachable!("Prevented by type check"),
+				| NotB => *stack.top() ^= 1,
+				| AddImm(v) => *stack.top() = stack.top().wrapping_add(v),
+				| SubImm(v) => *stack.top() = stack.top().wrapping_sub(v),
+				| MulImm(v) => *stack.top() = stack.top().wrapping_mul(v),
+				| BitAndImm(v) => *stack.top() &= v,
+				| BitOrImm(v) => *stack.top() |= v,
+				| ShiftRImm(v) => *stack.top() = shr(*stack.top(), v),
+				| ShiftLImm(v) => *stack.top() = shl(*stack.top(), v),
+				| EqImm(v) => *stack.top() = (*stack.top() as i64 == v as i64) as u64,
+				| NeqImm(v) => *stack.top() = (*stack.top() as i64 != v as i64) as u64,
+				| LtImm(v) => *stack.top() = ((*stack.top() as i64) < v as i64) as u64,
+				| GtImm(v) => *stack.top() = (*stack.top() as i64 > v as i64) as u64,
+				| LteImm(v) => *stack.top() = (*stack.top() as i64 <= v as i64) as u64,
+				| GteImm(v) => *stack.top() = (*stack.top() as i64 >= v as i64) as u64,
+				| PushUnder(v) => {
+					let top = std::mem::replace(stack.top(), v);
+					stack.push(top);
+					if stack.sp > max_stack {
+						return Err(self.stack_overflow(origins[ip], max_stack));
 					}
 				}
-				| BitAnd => {
-					let v2 = stack.pop().unwrap().to_i64();
-					let v1 = stack.pop().unwrap().to_i64();
-					stack.push(Data::I64(v1 & v2))
-				}
-				| BitOr => {
-					let v2 = stack.pop().unwrap().to_i64();
-					let v1 = stack.pop().unwrap().to_i64();
-					stack.push(Data::I64(v1 | v2))
+				| JmpNotEqImm(v, to) => {
+					if stack.pop() as i64 != v as i64 {
+						ip = to as usize;
+						continue;
+					}
 				}
-				| And => {
-					let v2 = stack.pop().unwrap().to_bool();
-					let v1 = stack.pop().unwrap().to_bool();
-					stack.push(Data::Bool(v1 && v2))
+				| JmpNotNeqImm(v, to) => {
+					if stack.pop() as i64 == v as i64 {
+						ip = to as usize;
+						continue;
+					}
 				}
-				| Or => {
-					let v2 = stack.pop().unwrap().to_bool();
-					let v1 = stack.pop().unwrap().to_bool();
-					stack.push(Data::Bool(v1 || v2))
+				| JmpNotLtImm(v, to) => {
+					if stack.pop() as i64 >= v as i64 {
+						ip = to as usize;
+						continue;
+					}
 				}
-				| Not => {
-					match stack.pop().unwrap() {
-						| Data::I64(v) => stack.push(Data::I64(!v)),
-						| Data::F64(v) => {
-							stack.push(
-								Data::I64(Data::F64(v).to_i64()).bytes_to_data_f64(),
-							)
-						}
-						| Data::Bool(v) => stack.push(Data::Bool(!v)),
-						| Data::Ptr(v) => stack.push(Data::Ptr(!v)),
+				| JmpNotGtImm(v, to) => {
+					if stack.pop() as i64 <= v as i64 {
+						ip = to as usize;
+						continue;
 					}
 				}
-				| Mem(name) => {
-					match name {
-						| Some(name) => {
-							stack.push(Data::Ptr(
-								*memory_regions_ptr.get(name).unwrap() as i64
-							))
-						}
-						| None => stack.push(Data::Ptr(mem_free_ptr as i64)),
+				| JmpNotLteImm(v, to) => {
+					if stack.pop() as i64 > v as i64 {
+						ip = to as usize;
+						continue;
 					}
 				}
-				| SetOver(size) => {
-					let set = stack.pop().unwrap();
-					let index = stack.len() - *size as usize;
-					let val = stack.get_mut(index).unwrap();
-					*val = set;
+				| JmpNotGteImm(v, to) => {
+					if (stack.pop() as i64) < v as i64 {
+						ip = to as usize;
+						continue;
+					}
 				}
-				| Nop => unreachable!(),
 			}
 			ip += 1;
 		}
+		if CHECKED {
+			steps += interval + 1 - countdown;
+		}
+		*next = ip;
+		*executed = steps;
+		Ok(Some(status))
+	}
+}
+
+/// Simulator usable as a library: the program runs on the given standard
+/// streams, its errors are returned instead of exiting, and its stack and memory
+/// can be inspected once it stopped.
+pub struct Simulator<'io> {
+	pub(crate) cli:    Cli<String>,
+	pub(crate) stdin:  Box<dyn Read + 'io>,
+	pub(crate) stdout: Box<dyn Write + 'io>,
+	pub(crate) stderr: Box<dyn Write + 'io>,
+	pub(crate) hosts:  Vec<Host<'io>>,
+	stack:             Vec<u64>,
+	memory:            Vec<u8>,
+	findings:          Vec<String>,
+	coverage:          Option<Coverage>,
+}
+
+impl<'io> Simulator<'io> {
+	/// Simulator with the options of `cli`, on the standard streams of the process
+	pub fn new(cli: &Cli<String>) -> Self {
+		Simulator {
+			cli:      cli.clone(),
+			stdin:    Box::new(stdin()),
+			stdout:   Box::new(stdout()),
+			stderr:   Box::new(stderr()),
+			hosts:    vec![],
+			stack:    vec![],
+			memory:   vec![],
+			findings: vec![],
+			coverage: None,
+		}
+	}
+
+	pub fn with_stdin(mut self, stdin: impl Read + 'io) -> Self {
+		self.stdin = Box::new(stdin);
+		self
+	}
+
+	pub fn with_stdout(mut self, stdout: impl Write + 'io) -> Self {
+		self.stdout = Box::new(stdout);
+		self
+	}
+
+	pub fn with_stderr(mut self, stderr: impl Write + 'io) -> Self {
+		self.stderr = Box::new(stderr);
+		self
+	}
+
+	/// Registers `f` as the host function `name`, the programs must call it with
+	/// the same stack effect
+	pub fn with_host(
+		mut self,
+		name: &str,
+		ins: &[Type],
+		outs: &[Type],
+		f: impl FnMut(&[u64], &mut [u8]) -> Result<Vec<u64>, String> + 'io,
+	) -> Self {
+		self.hosts.retain(|host| host.name != name);
+		self.hosts.push(Host {
+			name: name.to_string(),
+			ins:  ins.to_vec(),
+			outs: outs.to_vec(),
+			f:    Box::new(f),
+		});
+		self
+	}
+
+	/// Values left on the stack by the last run, bottom first
+	pub fn stack(&self) -> &[u64] { &self.stack }
+
+	/// Memory of the last run, strings and `decla` regions first
+	pub fn memory(&self) -> &[u8] { &self.memory }
+
+	/// Findings of `--sanitize` in the last run, they don't stop the simulation
+	pub fn findings(&self) -> &[String] { &self.findings }
+
+	/// Coverage of the last run with `--coverage`, if it did not stop on an error
+	pub fn coverage(&self) -> Option<&Coverage> { self.coverage.as_ref() }
+
+	/// Machine ready to run `lowered` from its first instruction, on `io` and
+	/// with the host functions `hosts`
+	pub fn machine<'a>(
+		program: &Program,
+		lowered: Bytecode,
+		cli: &Cli<String>,
+		io: Io<'a>,
+		hosts: &'a mut [Host<'io>],
+	) -> Result<Machine<'a>, RuntimeError> {
+		let Bytecode {
+			code,
+			origins,
+			firsts,
+			data,
+			mut regions,
+			mem,
+			ptr_stores,
+			ptr_loads,
+			stacks,
+			hosts: host_calls,
+		} = lowered;
+		if mem > cli.sim_mem {
+			return Err(RuntimeError::Fault(format!(
+				"Not enough memory for the strings and `decla` regions: {mem} bytes are \
+				 needed but `--sim-mem` is {}",
+				cli.sim_mem
+			)));
+		}
+		regions.push(Region {
+			kind:  RegionKind::Mem,
+			start: mem,
+			len:   cli.sim_mem - mem,
+		});
+
+		let mut calls = vec![];
+		for op in host_calls {
+			let OpType::Host(name, ins, outs) = &program.ops[op].typ else {
+				unreachable!()
+			};
+			let Some(host) = hosts.iter().position(|host| host.name == *name) else {
+				let msg = format!("Host function `{name}` is not registered");
+				return Err(program.runtime_error(op, msg));
+			};
+			let Host { ins: host_ins, outs: host_outs, .. } = &hosts[host];
+			if host_ins != ins || host_outs != outs {
+				let msg = format!(
+					"Host function `{name}` is called with {} but registered with {}",
+					signature(ins, outs),
+					signature(host_ins, host_outs)
+				);
+				return Err(program.runtime_error(op, msg));
+			}
+			calls.push((host, ins.len(), outs.len()));
+		}
+
+		// Room for the values pushed by the instruction exceeding `--max-stack`
+		let headroom = code
+			.iter()
+			.map(|instr| {
+				match instr {
+					| Instr::Dup(n) => *n as usize,
+					| Instr::Host(call) => calls[*call as usize].2,
+					| _ => 1,
+				}
+			})
+			.max();
+		let slots = vec![0; cli.max_stack + headroom.unwrap_or(0)];
+		let mut memory = vec![0u8; cli.sim_mem];
+		memory[..data.len()].copy_from_slice(&data);
+		let tracer = match &cli.trace {
+			| Some(path) => {
+				Some(Tracer::new(path, cli.trace_filter.clone()).map_err(|e| {
+					RuntimeError::Io(format!(
+						"Failed to create the trace file `{path}`: {e}"
+					))
+				})?)
+			}
+			| None => None,
+		};
+		let mut syscalls = Syscalls::new(cli.policy.clone());
+		if let Some(path) = &cli.record {
+			let log = File::create(path).map_err(|e| {
+				RuntimeError::Io(format!(
+					"Failed to create the syscall log `{path}`: {e}"
+				))
+			})?;
+			syscalls.record = Some(BufWriter::new(log));
+		}
+		if let Some(path) = &cli.replay {
+			let replay = std::fs::read_to_string(path)
+				.map_err(|e| e.to_string())
+				.and_then(|log| Replay::parse(&log));
+			syscalls.replay = Some(replay.map_err(|e| {
+				RuntimeError::Io(format!("Invalid syscall log `{path}`: {e}"))
+			})?);
+		}
+		Ok(Machine {
+			sanitizer: cli.sanitize.then(|| Sanitizer::new(&regions, memory.len())),
+			reports: vec![],
+			profiler: (cli.profile || cli.profile_folded.is_some())
+				.then(|| Profiler::new(code.len())),
+			counters: cli.coverage.as_ref().map(|_| Counters::new(code.len())),
+			code,
+			origins,
+			firsts,
+			stacks,
+			ptr_stores,
+			ptr_loads,
+			regions,
+			stack: Stack { slots, sp: 0 },
+			memory,
+			ip: 0,
+			steps: 0,
+			pause_at: u64::MAX,
+			undo: None,
+			exited: false,
+			tracer,
+			syscalls,
+			io,
+			hosts: hosts.iter_mut().map(|host| &mut *host.f as &mut HostFn).collect(),
+			calls,
+			prev: usize::MAX,
+		})
+	}
+
+	/// Simulates `program` until it exits or stops on an error
+	pub fn run(&mut self, program: &Program) -> Result<ExitStatus, RuntimeError> {
+		let cli = &self.cli;
+		let io = Io {
+			stdin:  &mut *self.stdin,
+			stdout: BufWriter::new(&mut *self.stdout),
+			stderr: &mut *self.stderr,
+		};
+		let lowered = program.lower(cli.trace.is_some());
+		let mut m = Simulator::machine(program, lowered, cli, io, &mut self.hosts)?;
+
+		let checked = m.tracer.is_some()
+			|| m.profiler.is_some()
+			|| m.counters.is_some()
+			|| cli.max_steps.is_some()
+			|| cli.timeout.is_some();
+		let result = if checked {
+			program.run::<true>(&mut m, cli)
+		} else {
+			program.run::<false>(&mut m, cli)
+		};
+
+		let Machine {
+			code,
+			origins,
+			firsts,
+			stacks,
+			mut stack,
+			memory,
+			reports,
+			mut tracer,
+			profiler,
+			counters,
+			mut io,
+			mut syscalls,
+			prev,
+			..
+		} = m;
+		let mut flushed = Ok(());
+		if let (Ok(_), Some(tracer)) = (&result, &mut tracer) {
+			if prev != usize::MAX {
+				let op = origins[prev];
+				flushed = tracer.record(
+					op,
+					&program.ops[op],
+					&stack.slots[..stack.sp],
+					&stacks[op],
+				);
+			}
+		}
+		flushed = flushed.and(io.stdout.flush().map_err(output_failed));
+		if let Some(tracer) = &mut tracer {
+			flushed = flushed.and(tracer.flush())
+		}
+		stack.slots.truncate(stack.sp);
+		self.stack = stack.slots;
+		self.memory = memory;
+		self.findings = reports;
+		self.coverage = None;
+		let status = result?.unwrap();
+		flushed?;
+		if let Some(log) = &mut syscalls.record {
+			log.flush().map_err(|e| {
+				RuntimeError::Io(format!("Failed to write the syscall log: {e}"))
+			})?;
+		}
+		if let (Some(replay), Some(path)) = (&syscalls.replay, &cli.replay) {
+			if replay.left() > 0 {
+				return Err(RuntimeError::Fault(format!(
+					"Replay diverged: the program stopped but {} syscall(s) of `{path}` \
+					 were not replayed (`--replay`)",
+					replay.left()
+				)));
+			}
+		}
+
+		if let Some(profiler) = profiler {
+			let (report, folded) = profiler.report(
+				&program.ops,
+				&origins,
+				&firsts,
+				&program.resolve_jumps(),
+			);
+			if cli.profile {
+				write!(io.stderr, "{report}").map_err(|e| {
+					RuntimeError::Io(format!("Failed to write the profile: {e}"))
+				})?;
+			}
+			if let Some(path) = &cli.profile_folded {
+				std::fs::write(path, folded).map_err(|e| {
+					RuntimeError::Io(format!(
+						"Failed to write the folded stacks to `{path}`: {e}"
+					))
+				})?;
+			}
+		}
+		if let Some(mut counters) = counters {
+			// Counts the jump out of the program, if any
+			counters.hit(code.len());
+			self.coverage = Some(Coverage::new(program, counters, &origins, &firsts));
+		}
+		Ok(ExitStatus { code: status })
+	}
+}
+
+impl Program {
+	/// Simulates the program on the standard streams of the process (`sim` mode)
+	pub fn simulate(&mut self, cli: &Cli<String>) {
+		let mut simulator = Simulator::new(cli);
+		let result = simulator.run(self);
+		for finding in simulator.findings() {
+			self.add_error(finding.clone());
+		}
+		if let (Some(coverage), Some(path)) = (simulator.coverage(), &cli.coverage) {
+			if let Err(e) = std::fs::write(path, coverage.to_lcov()) {
+				self.add_error(format!("Failed to write the coverage to `{path}`: {e}"))
+					.exit(1)
+			}
+		}
+		match result {
+			| Ok(status) => {
+				if self.reporter.has(Level::Error) {
+					self.exit(1)
+				}
+				if !status.success() {
+					self.exit(status.code)
+				}
+			}
+			| Err(e) => self.add_error(e.to_string()).exit(e.exit_code()),
+		}
 	}
 }
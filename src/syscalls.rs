@@ -0,0 +1,290 @@
+//#region Imports
+use std::{
+	collections::HashMap,
+	fs::{File, OpenOptions},
+	io::{BufWriter, Read, Write},
+	os::unix::fs::OpenOptionsExt,
+	path::Path,
+};
+
+use crate::{
+	policy::Policy,
+	replay::{Outcome, Record, Replay},
+};
+//#endregion
+
+const READ: u64 = 0;
+const WRITE: u64 = 1;
+const OPEN: u64 = 2;
+const CLOSE: u64 = 3;
+const EXIT: u64 = 60;
+const EXIT_GROUP: u64 = 231;
+
+const EBADF: i64 = 9;
+const EFAULT: i64 = 14;
+
+const O_ACCMODE: u64 = 0o3;
+const O_CREAT: u64 = 0o100;
+const O_TRUNC: u64 = 0o1000;
+const O_APPEND: u64 = 0o2000;
+
+/// Why a syscall stopped the simulation
+pub enum Stop {
+	Exit(i32),
+	NotImplemented,
+	/// Forbidden by the policy, with the reason
+	Denied(String),
+	/// Different from the `--replay` log, with the first difference
+	Diverged(String),
+	/// Failed to write the `--record` log or the output of the program, with
+	/// the message
+	Io(String),
+}
+
+/// Message of a failure to write the output of the simulated program
+pub fn output_error(e: std::io::Error) -> String {
+	format!("Failed to write the output of the program: {e}")
+}
+
+fn output_stop(e: std::io::Error) -> Stop { Stop::Io(output_error(e)) }
+
+/// Standard streams of the simulated program
+pub struct Io<'a> {
+	pub stdin:  &'a mut dyn Read,
+	pub stdout: BufWriter<&'a mut dyn Write>,
+	pub stderr: &'a mut dyn Write,
+}
+
+/// Emulation of the Linux syscalls used by the programs, on top of the
+/// simulator's memory. Errors are returned as negative errno like the kernel
+/// does.
+pub struct Syscalls {
+	policy:      Policy,
+	/// Files opened by the program, by fd
+	files:       HashMap<u64, File>,
+	/// Log every syscall is written to (`--record`)
+	pub record:  Option<BufWriter<File>>,
+	/// Log the syscalls are replayed from instead of being done (`--replay`)
+	pub replay:  Option<Replay>,
+	/// Every syscall done, kept by the debugger to replay them
+	pub history: Option<Vec<Record>>,
+}
+
+/// Bytes `[ptr, ptr + len)` of the memory
+fn buffer(memory: &mut [u8], ptr: u64, len: u64) -> Option<&mut [u8]> {
+	let start = usize::try_from(ptr).ok()?;
+	memory.get_mut(start..start.checked_add(usize::try_from(len).ok()?)?)
+}
+
+fn arg(args: &[u64], idx: usize) -> u64 { args.get(idx).copied().unwrap_or(0) }
+
+/// Bytes of the memory the syscall reads: the written data or the opened path
+fn input(syscode: u64, args: &[u64], memory: &mut [u8]) -> Option<Vec<u8>> {
+	match syscode {
+		| WRITE => buffer(memory, arg(args, 1), arg(args, 2)).map(|buf| buf.to_vec()),
+		| OPEN => {
+			let bytes = memory.get(usize::try_from(arg(args, 0)).ok()?..)?;
+			Some(bytes.split(|b| *b == 0).next().unwrap().to_vec())
+		}
+		| _ => None,
+	}
+}
+
+/// Address and current content of the bytes the syscall may write to
+pub fn written<'m>(
+	syscode: u64,
+	args: &[u64],
+	memory: &'m mut [u8],
+) -> Option<(usize, &'m [u8])> {
+	match syscode {
+		| READ => {
+			let (ptr, len) = (arg(args, 1), arg(args, 2));
+			buffer(memory, ptr, len).map(|buf| (ptr as usize, &*buf))
+		}
+		| _ => None,
+	}
+}
+
+fn errno(e: std::io::Error) -> u64 {
+	(-(e.raw_os_error().unwrap_or(EBADF as i32) as i64)) as u64
+}
+
+impl Syscalls {
+	pub fn new(policy: Policy) -> Self {
+		Syscalls {
+			policy,
+			files: HashMap::new(),
+			record: None,
+			replay: None,
+			history: None,
+		}
+	}
+
+	/// Does the syscall `syscode` with `args` (`rdi` first), or replays it
+	pub fn call(
+		&mut self,
+		syscode: u64,
+		args: &[u64],
+		memory: &mut [u8],
+		io: &mut Io,
+	) -> Result<u64, Stop> {
+		if !self.policy.allows_syscall(syscode) {
+			return Err(Stop::Denied(format!("syscall {syscode} is not allowed")));
+		}
+		if let Some(replay) = &mut self.replay {
+			let input = input(syscode, args, memory);
+			let record =
+				replay.next(syscode, args, input.as_deref()).map_err(Stop::Diverged)?;
+			// Only the output of the program is redone
+			if let (WRITE, 1 | 2, Some(input)) = (syscode, arg(args, 0), &record.input) {
+				let written = match arg(args, 0) {
+					| 1 => io.stdout.write_all(input),
+					| _ => {
+						io.stdout.flush().map_err(output_stop)?;
+						io.stderr.write_all(input)
+					}
+				};
+				written.map_err(output_stop)?;
+			}
+			if let Some(output) = &record.output {
+				let len = output.len() as u64;
+				if let Some(buf) = buffer(memory, arg(args, 1), len) {
+					buf.copy_from_slice(output)
+				}
+			}
+			return match record.outcome {
+				| Outcome::Ret(ret) => Ok(ret),
+				| Outcome::Exit(code) => Err(Stop::Exit(code)),
+			};
+		}
+
+		let logged = self.record.is_some() || self.history.is_some();
+		let input = logged.then(|| input(syscode, args, memory)).flatten();
+		let result = self.emulate(syscode, args, memory, io);
+		if logged {
+			let outcome = match result {
+				| Ok(ret) => Outcome::Ret(ret),
+				| Err(Stop::Exit(code)) => Outcome::Exit(code),
+				| Err(_) => return result,
+			};
+			let output = match (syscode, outcome) {
+				| (READ, Outcome::Ret(ret)) if (ret as i64) >= 0 => {
+					buffer(memory, arg(args, 1), ret).map(|buf| buf.to_vec())
+				}
+				| _ => None,
+			};
+			let record = Record { syscode, args: args.to_vec(), input, output, outcome };
+			if let Some(log) = &mut self.record {
+				writeln!(log, "{record}").map_err(|e| {
+					Stop::Io(format!("Failed to write the syscall log: {e}"))
+				})?;
+			}
+			if let Some(history) = &mut self.history {
+				history.push(record)
+			}
+		}
+		result
+	}
+
+	fn emulate(
+		&mut self,
+		syscode: u64,
+		args: &[u64],
+		memory: &mut [u8],
+		io: &mut Io,
+	) -> Result<u64, Stop> {
+		let arg = |idx: usize| arg(args, idx);
+		match syscode {
+			| READ => {
+				let (fd, ptr, len) = (arg(0), arg(1), arg(2));
+				let Some(buf) = buffer(memory, ptr, len) else {
+					return Ok(-EFAULT as u64);
+				};
+				let read = match fd {
+					| 0 => {
+						// Prompts are shown before waiting for the input
+						io.stdout.flush().map_err(output_stop)?;
+						io.stdin.read(buf)
+					}
+					| fd => {
+						match self.files.get_mut(&fd) {
+							| Some(file) => file.read(buf),
+							| None => return Ok(-EBADF as u64),
+						}
+					}
+				};
+				Ok(read.map_or_else(errno, |read| read as u64))
+			}
+			| WRITE => {
+				let (fd, ptr, len) = (arg(0), arg(1), arg(2));
+				if fd > 2 && !self.policy.write {
+					return Err(Stop::Denied(format!(
+						"writing to fd {fd} is not allowed"
+					)));
+				}
+				let Some(buf) = buffer(memory, ptr, len) else {
+					return Ok(-EFAULT as u64);
+				};
+				let written = match fd {
+					| 1 => io.stdout.write(buf),
+					| 2 => {
+						io.stdout.flush().map_err(output_stop)?;
+						io.stderr.write(buf)
+					}
+					| fd => {
+						match self.files.get_mut(&fd) {
+							| Some(file) => file.write(buf),
+							| None => return Ok(-EBADF as u64),
+						}
+					}
+				};
+				Ok(written.map_or_else(errno, |written| written as u64))
+			}
+			| OPEN => {
+				let (ptr, flags, mode) = (arg(0), arg(1), arg(2));
+				let Some(bytes) =
+					usize::try_from(ptr).ok().and_then(|ptr| memory.get(ptr..))
+				else {
+					return Ok(-EFAULT as u64);
+				};
+				let path = bytes.split(|b| *b == 0).next().unwrap();
+				let path = String::from_utf8_lossy(path).to_string();
+				if !self.policy.allows_open(Path::new(&path)) {
+					return Err(Stop::Denied(format!("opening `{path}` is not allowed")));
+				}
+				let access = flags & O_ACCMODE;
+				// Creating, truncating or appending writes even with `O_RDONLY`
+				let writes = access != 0 || flags & (O_CREAT | O_TRUNC | O_APPEND) != 0;
+				if writes && !self.policy.write {
+					return Err(Stop::Denied(format!(
+						"opening `{path}` for writing is not allowed"
+					)));
+				}
+				let file = OpenOptions::new()
+					.read(access != 1)
+					.write(access != 0)
+					.create(flags & O_CREAT != 0)
+					.truncate(flags & O_TRUNC != 0)
+					.append(flags & O_APPEND != 0)
+					.mode(mode as u32)
+					.open(&path);
+				Ok(match file {
+					| Ok(file) => {
+						let fd = (3..).find(|fd| !self.files.contains_key(fd)).unwrap();
+						self.files.insert(fd, file);
+						fd
+					}
+					| Err(e) => errno(e),
+				})
+			}
+			| CLOSE => {
+				Ok(match self.files.remove(&arg(0)) {
+					| Some(_) => 0,
+					| None => -EBADF as u64,
+				})
+			}
+			| EXIT | EXIT_GROUP => Err(Stop::Exit(arg(0) as i32)),
+			| _ => Err(Stop::NotImplemented),
+		}
+	}
+}
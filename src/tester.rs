@@ -0,0 +1,154 @@
+//#region Imports
+use std::{
+	io::empty,
+	panic::catch_unwind,
+	path::{Path, PathBuf},
+	process::exit,
+};
+
+use crate::{
+	coverage::Coverage,
+	load,
+	report::{Level, Reporter},
+	simulator::Simulator,
+	Cli,
+	Mode,
+};
+//#endregion
+
+/// Test programs of `path`, the file itself or the `.ssmpl` files of the
+/// directory
+pub fn collect_tests(path: &Path) -> std::io::Result<Vec<PathBuf>> {
+	if !path.is_dir() {
+		return Ok(vec![path.to_path_buf()]);
+	}
+	let mut tests = vec![];
+	for entry in path.read_dir()? {
+		let path = entry?.path();
+		if path.extension().is_some_and(|ext| ext == "ssmpl") {
+			tests.push(path);
+		}
+	}
+	tests.sort();
+	Ok(tests)
+}
+
+/// Result of the simulation of a test program
+pub struct Outcome {
+	/// Why the test failed, `None` if it passed
+	pub failure:  Option<String>,
+	/// Errors of the program and what it printed to stderr
+	pub stderr:   String,
+	/// Lines and branches executed, with `--coverage`
+	pub coverage: Option<Coverage>,
+}
+
+/// Simulates the test program `test` with the options of `cli`, without input.
+///
+/// It passes when its simulation succeeds and, if there is a `.expected` file
+/// next to it, prints exactly its content.
+pub fn run_test(cli: &Cli<String>, test: &Path) -> Outcome {
+	// A crash of the simulator must not stop the other tests
+	catch_unwind(|| simulate_test(cli, test)).unwrap_or_else(|payload| {
+		let msg = payload
+			.downcast_ref::<&str>()
+			.map(|msg| msg.to_string())
+			.or_else(|| payload.downcast_ref::<String>().cloned())
+			.unwrap_or_default();
+		Outcome {
+			failure:  Some(format!("panicked: {msg}")),
+			stderr:   String::new(),
+			coverage: None,
+		}
+	})
+}
+
+fn simulate_test(cli: &Cli<String>, test: &Path) -> Outcome {
+	let mut cli = cli.clone();
+	cli.mode = Mode::Sim;
+	cli.input_path = test.display().to_string();
+	let failed = |failure: String, stderr: String| {
+		Outcome { failure: Some(failure), stderr, coverage: None }
+	};
+
+	let input = match std::fs::read_to_string(test) {
+		| Ok(input) => input,
+		| Err(e) => return failed(format!("failed to read it: {e}"), String::new()),
+	};
+	let mut reporter = Reporter::new(Level::Error);
+	reporter.quiet = true;
+	let program = match load(&input, &cli, reporter) {
+		| Ok(program) => program,
+		| Err(errors) => return failed("invalid program".into(), errors.join("\n")),
+	};
+
+	let (mut stdout, mut stderr) = (vec![], vec![]);
+	let mut simulator = Simulator::new(&cli)
+		.with_stdin(empty())
+		.with_stdout(&mut stdout)
+		.with_stderr(&mut stderr);
+	let result = simulator.run(&program);
+	let findings = simulator.findings().to_vec();
+	let coverage = simulator.coverage().cloned();
+	drop(simulator);
+
+	let mut stderr = String::from_utf8_lossy(&stderr).into_owned();
+	for finding in findings.iter() {
+		stderr += &format!("ERROR: {finding}\n");
+	}
+	let expected = test.with_extension("expected");
+	let failure = match result {
+		| Err(e) => {
+			stderr += &format!("ERROR: {e}\n");
+			Some(format!("exited with {}", e.exit_code()))
+		}
+		| Ok(status) if !status.success() => Some(format!("exited with {}", status.code)),
+		| Ok(_) if !findings.is_empty() => Some("sanitizer findings".into()),
+		| Ok(_) if expected.exists() && std::fs::read(&expected).ok() != Some(stdout) => {
+			Some(format!("output differs from `{}`", expected.display()))
+		}
+		| Ok(_) => None,
+	};
+	Outcome { failure, stderr, coverage }
+}
+
+/// Simulates every test program of the input (`test` mode)
+pub fn run_tests(cli: &Cli<String>, reporter: &mut Reporter) -> ! {
+	let tests = collect_tests(Path::new(&cli.input_path)).unwrap_or_else(|e| {
+		reporter
+			.add_error(format!("Failed to read the tests of `{}`: {e}", cli.input_path))
+			.exit(1)
+	});
+	let mut coverage = Coverage::default();
+	let mut failed = 0;
+
+	for test in tests.iter() {
+		let outcome = run_test(cli, test);
+		// A test stopped by a runtime error has no coverage
+		if let Some(test_coverage) = &outcome.coverage {
+			coverage.merge(test_coverage);
+		}
+		match outcome.failure {
+			| None => println!("PASS {}", test.display()),
+			| Some(failure) => {
+				failed += 1;
+				println!("FAIL {}: {failure}", test.display());
+				for line in outcome.stderr.lines() {
+					println!("    {line}");
+				}
+			}
+		}
+	}
+
+	println!("{} passed, {failed} failed", tests.len() - failed);
+	if let Some(path) = &cli.coverage {
+		if let Err(e) = std::fs::write(path, coverage.to_lcov()) {
+			reporter.add_error(format!("Failed to write the coverage to `{path}`: {e}"));
+		}
+	}
+	if failed > 0 {
+		reporter.add_error(format!("{failed} test(s) failed"));
+	}
+	reporter.exit_if(Level::Error, 1);
+	exit(0)
+}
@@ -0,0 +1,143 @@
+//#region Imports
+use std::{
+	fs::File,
+	io::{BufWriter, Write},
+	ops::RangeInclusive,
+};
+
+use crate::{
+	annotation::{Position, Type},
+	parser::Op,
+	simulator::RuntimeError,
+};
+//#endregion
+
+/// Keeps only the ops located in a file between two lines (inclusive), or
+/// expanded from a macro invoked there
+#[derive(Clone)]
+pub struct TraceFilter {
+	pub file:  String,
+	pub lines: RangeInclusive<usize>,
+}
+
+impl TraceFilter {
+	/// Parses `file:line` or `file:first-last`
+	pub fn parse(filter: &str) -> Option<Self> {
+		let (file, lines) = filter.rsplit_once(':')?;
+		let (first, last) = lines.split_once('-').unwrap_or((lines, lines));
+		let lines = first.parse().ok()?..=last.parse().ok()?;
+		Some(TraceFilter { file: file.to_string(), lines })
+	}
+
+	fn matches(&self, pos: &Position) -> bool {
+		pos.file_path().ends_with(&self.file) && self.lines.contains(&pos.line())
+	}
+
+	/// Whether the op is in the lines, or expanded from there
+	pub fn keeps(&self, op: &Op) -> bool {
+		let annot = &op.annot;
+		self.matches(annot.get_pos())
+			|| annot.get_expansions().iter().any(|e| self.matches(&e.pos))
+	}
+}
+
+/// Writes one json record per executed op (`--trace`)
+pub struct Tracer {
+	out:    BufWriter<File>,
+	filter: Option<TraceFilter>,
+	/// Bytes written to the memory by the op being executed
+	writes: Vec<(u64, Vec<u8>)>,
+}
+
+fn json_string(str: &str) -> String {
+	let mut json = String::from('"');
+	for c in str.chars() {
+		match c {
+			| '"' => json.push_str("\\\""),
+			| '\\' => json.push_str("\\\\"),
+			| '\n' => json.push_str("\\n"),
+			| '\t' => json.push_str("\\t"),
+			| c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+			| c => json.push(c),
+		}
+	}
+	json.push('"');
+	json
+}
+
+fn json_value(slot: u64, typ: Type) -> String {
+	match typ {
+		| Type::I64 | Type::Ptr => (slot as i64).to_string(),
+		| Type::Bool => (slot != 0).to_string(),
+		| Type::F64 => {
+			let f = f64::from_bits(slot);
+			if f.is_finite() {
+				format!("{f:?}")
+			} else {
+				json_string(&f.to_string())
+			}
+		}
+	}
+}
+
+impl Tracer {
+	pub fn new(path: &str, filter: Option<TraceFilter>) -> std::io::Result<Self> {
+		Ok(Tracer { out: BufWriter::new(File::create(path)?), filter, writes: vec![] })
+	}
+
+	/// Records bytes written to the memory by the op being executed
+	pub fn write(&mut self, addr: u64, bytes: &[u8]) {
+		self.writes.push((addr, bytes.to_vec()))
+	}
+
+	/// Writes the record of the op `ip`, now that it has been executed
+	pub fn record(
+		&mut self,
+		ip: usize,
+		op: &Op,
+		stack: &[u64],
+		types: &[Type],
+	) -> Result<(), RuntimeError> {
+		let writes = std::mem::take(&mut self.writes);
+		if self.filter.as_ref().is_some_and(|filter| !filter.keeps(op)) {
+			return Ok(());
+		}
+		let annot = &op.annot;
+		let pos = annot.get_pos();
+		let stack = stack
+			.iter()
+			.zip(types)
+			.map(|(slot, typ)| json_value(*slot, *typ))
+			.collect::<Vec<_>>()
+			.join(",");
+		let writes = writes
+			.iter()
+			.map(|(addr, bytes)| {
+				let bytes = bytes.iter().map(u8::to_string).collect::<Vec<_>>().join(",");
+				format!("{{\"addr\":{addr},\"bytes\":[{bytes}]}}")
+			})
+			.collect::<Vec<_>>()
+			.join(",");
+		let pos = format!(
+			"{{\"file\":{},\"line\":{},\"col\":{}}}",
+			json_string(pos.file_path()),
+			pos.line(),
+			pos.col()
+		);
+		let op = json_string(&op.typ.to_string());
+		writeln!(
+			self.out,
+			"{{\"index\":{ip},\"pos\":{pos},\"op\":{op},\"stack\":[{stack}],\"writes\":\
+			 [{writes}]}}"
+		)
+		.map_err(Self::io_error)
+	}
+
+	pub fn flush(&mut self) -> Result<(), RuntimeError> {
+		self.out.flush().map_err(Self::io_error)
+	}
+
+	fn io_error(e: std::io::Error) -> RuntimeError {
+		RuntimeError::Io(format!("Failed to write the trace file: {e}"))
+	}
+}
@@ -3,7 +3,8 @@ use std::fmt::Display;
 
 use crate::{
 	annotation::{Annotation, Type},
-	parser::{Op, OpType, Program},
+	parser::{Op, OpType, Overflow, Program},
+	report::Abort,
 };
 //#endregion
 
@@ -35,7 +36,6 @@ impl PartialEq for Stack {
 impl Op {
 	pub fn required_stack_len(&self) -> usize {
 		match self.typ {
-			| OpType::Nop
 			| OpType::Argc
 			| OpType::Argv
 			| OpType::PushI(_)
@@ -55,6 +55,22 @@ impl Op {
 			| OpType::Decrement(_)
 			| OpType::Cast(_)
 			| OpType::Not
+			| OpType::Exit
+			| OpType::ToF64
+			| OpType::ToI64
+			| OpType::Round
+			| OpType::Floor
+			| OpType::Ceil
+			| OpType::Sqrt
+			| OpType::Abs
+			| OpType::Neg
+			| OpType::Exp
+			| OpType::Log
+			| OpType::Sin
+			| OpType::Cos
+			| OpType::Popcount
+			| OpType::Clz
+			| OpType::Ctz
 			| OpType::Then(..)
 			| OpType::Do(_)
 			| OpType::Dump(_) => 1,
@@ -73,15 +89,31 @@ impl Op {
 			| OpType::Mul(..)
 			| OpType::Div(..)
 			| OpType::Mod(..)
+			| OpType::DivMod(_)
+			| OpType::IntArith(..)
 			| OpType::ShiftR
+			| OpType::ShiftRA
 			| OpType::ShiftL
+			| OpType::RotL
+			| OpType::RotR
 			| OpType::BitAnd
+			| OpType::Xor
 			| OpType::And
 			| OpType::BitOr
 			| OpType::Or
-			| OpType::Swap => 2,
+			| OpType::Fmod
+			| OpType::Min
+			| OpType::Max
+			| OpType::Pow
+			| OpType::Swap
+			| OpType::Nip
+			| OpType::Tuck => 2,
+			| OpType::Rot | OpType::Unrot => 3,
+			| OpType::Swap2 => 4,
+			| OpType::Roll(n) => n as usize + 1,
 			| OpType::SetOver(size) | OpType::Over(size) => size as usize + 1,
 			| OpType::Syscall(_, size) => size,
+			| OpType::Asm(ref ins, ..) | OpType::Host(_, ref ins, _) => ins.len(),
 			| OpType::Drop(size) | OpType::Dup(size) => size as usize,
 		}
 	}
@@ -97,8 +129,24 @@ impl Program {
 		(Type::Ptr, Type::I64),
 	];
 
-	pub fn type_check(mut self) -> Self {
-		let mut stack: Vec<Annotation> = vec![];
+	pub fn type_check(self) -> Result<Self, Abort> {
+		let (mut program, stack) = self.type_check_from(vec![])?;
+		if !stack.is_empty() {
+			program.reporter.add_warning(format!(
+				"The stack is not empty at the end of the program\n{}",
+				Stack::from_vec(stack)
+			));
+		}
+		Ok(program)
+	}
+
+	/// Type checks the program starting with `stack`, and returns the stack at
+	/// its end
+	pub fn type_check_from(
+		mut self,
+		stack: Vec<Annotation>,
+	) -> Result<(Self, Vec<Annotation>), Abort> {
+		let mut stack = stack;
 		let mut cf: Vec<&mut OpType> = vec![];
 		let mut stack_snapshots: Vec<Vec<Annotation>> = vec![];
 		let mut if_else_count = 0;
@@ -107,11 +155,10 @@ impl Program {
 
 		use OpType::*;
 
-		ops.iter_mut().for_each(|op| {
-			self.check_args(op, &stack);
+		for op in ops.iter_mut() {
+			self.check_args(op, &stack)?;
 			let Op { typ, annot } = op;
 			match typ {
-				| Nop => unreachable!(),
 				| PushI(_) => {
 					annot.set_type(Type::I64);
 					stack.push(annot.clone());
@@ -155,6 +202,20 @@ impl Program {
 					*type2 = *stack.pop().unwrap().get_type();
 					stack.push(annot.clone().with_type(Type::I64));
 				}
+				| DivMod(_) => {
+					stack.pop();
+					stack.pop();
+					stack.push(annot.clone().with_type(Type::I64));
+					stack.push(annot.clone().with_type(Type::I64));
+				}
+				| IntArith(_, overflow) => {
+					stack.pop();
+					stack.pop();
+					stack.push(annot.clone().with_type(Type::I64));
+					if *overflow == Overflow::Checked {
+						stack.push(annot.clone().with_type(Type::Bool));
+					}
+				}
 				| Increment(typ) | Decrement(typ) => {
 					let a_typ = *stack.pop().unwrap().get_type();
 					if a_typ != Type::F64 && a_typ != Type::Ptr {
@@ -175,6 +236,30 @@ impl Program {
 					stack.push(a);
 					stack.push(b);
 				}
+				| Rot => {
+					let len = stack.len();
+					stack[len - 3..].rotate_left(1);
+				}
+				| Unrot => {
+					let len = stack.len();
+					stack[len - 3..].rotate_right(1);
+				}
+				| Nip => {
+					let a = stack.pop().unwrap();
+					*stack.last_mut().unwrap() = a;
+				}
+				| Tuck => {
+					let typ = *stack.last().unwrap().get_type();
+					stack.insert(stack.len() - 2, annot.clone().with_type(typ));
+				}
+				| Swap2 => {
+					let len = stack.len();
+					stack[len - 4..].rotate_left(2);
+				}
+				| Roll(n) => {
+					let len = stack.len();
+					stack[len - *n as usize - 1..].rotate_left(1);
+				}
 				| Over(n) => {
 					let typ = *stack[stack.len() - *n as usize - 1].get_type();
 					stack.push(annot.clone().with_type(typ));
@@ -200,20 +285,24 @@ impl Program {
 						let stack_snapshot = stack_snapshots.last_mut().unwrap();
 						let a = stack.pop().unwrap();
 						if stack_snapshot.clone() != stack {
-							self.add_error(format!(
-								"{}: Condition between If and Then must only add one \
-								 value to the stack",
-								a.get_pos()
-							))
-							.exit(1);
+							return Err(self
+								.add_error(format!(
+									"{}: Condition between If and Then must only add \
+									 one value to the stack{}",
+									a.get_pos(),
+									a.expansion_trace()
+								))
+								.abort());
 						}
 						cf.push(typ)
 					} else {
-						self.add_error(format!(
-							"{}: Expected If before Then",
-							annot.get_pos()
-						))
-						.exit(1);
+						return Err(self
+							.add_error(format!(
+								"{}: Expected If before Then{}",
+								annot.get_pos(),
+								annot.expansion_trace()
+							))
+							.abort());
 					}
 				}
 				| Else(label_count) => {
@@ -232,29 +321,34 @@ impl Program {
 							*label_count = *then_label_count;
 							let stack_snapshot = stack_snapshots.pop().unwrap();
 							if stack_snapshot != stack {
-								self.add_error(format!(
-									"{}: The code inside a IF ... THEN ... END block \
-									 should not alter the stack\nBefore: {}\nAfter: {}",
-									annot.get_pos(),
-									Stack::from_vec(stack_snapshot),
-									Stack::from_vec(stack.clone())
-								))
-								.exit(1);
+								return Err(self
+									.add_error(format!(
+										"{}: The code inside a IF ... THEN ... END \
+										 block should not alter the stack{}\nBefore: \
+										 {}\nAfter: {}",
+										annot.get_pos(),
+										annot.expansion_trace(),
+										Stack::from_vec(stack_snapshot),
+										Stack::from_vec(stack.clone())
+									))
+									.abort());
 							}
 						}
 						| Some(Else(else_label_count, ..)) => {
 							*label_count = *else_label_count;
 							let stack_snapshot = stack_snapshots.pop().unwrap();
 							if stack_snapshot != stack {
-								self.add_error(format!(
-									"{}: code inside both of IF ... THEN ... ELSE ... \
-									 END blocks should alter the stack in the same \
-									 way\nThen: {}\nElse: {}",
-									annot.get_pos(),
-									Stack::from_vec(stack_snapshot),
-									Stack::from_vec(stack.clone())
-								))
-								.exit(1);
+								return Err(self
+									.add_error(format!(
+										"{}: code inside both of IF ... THEN ... ELSE \
+										 ... END blocks should alter the stack in the \
+										 same way{}\nThen: {}\nElse: {}",
+										annot.get_pos(),
+										annot.expansion_trace(),
+										Stack::from_vec(stack_snapshot),
+										Stack::from_vec(stack.clone())
+									))
+									.abort());
 							}
 						}
 						| Some(Do(do_label_count, ..)) => {
@@ -262,22 +356,27 @@ impl Program {
 							*while_ = true;
 							let stack_snapshot = stack_snapshots.pop().unwrap();
 							if stack_snapshot != stack {
-								self.add_error(format!(
-									"{}: code inside of WHILE ... DO ... END block \
-									 should not alter the stack\nBefore: {}\nAfter: {}",
-									annot.get_pos(),
-									Stack::from_vec(stack_snapshot),
-									Stack::from_vec(stack.clone())
-								))
-								.exit(1);
+								return Err(self
+									.add_error(format!(
+										"{}: code inside of WHILE ... DO ... END block \
+										 should not alter the stack{}\nBefore: \
+										 {}\nAfter: {}",
+										annot.get_pos(),
+										annot.expansion_trace(),
+										Stack::from_vec(stack_snapshot),
+										Stack::from_vec(stack.clone())
+									))
+									.abort());
 							}
 						}
 						| _ => {
-							self.add_error(format!(
-								"{}: Expected Then or Else before End",
-								annot.get_pos()
-							))
-							.exit(1)
+							return Err(self
+								.add_error(format!(
+									"{}: Expected Then or Else before End{}",
+									annot.get_pos(),
+									annot.expansion_trace()
+								))
+								.abort())
 						}
 					}
 				}
@@ -293,20 +392,24 @@ impl Program {
 						let stack_snapshot = stack_snapshots.last_mut().unwrap();
 						let a = stack.pop().unwrap();
 						if stack_snapshot.clone() != stack {
-							self.add_error(format!(
-								"{}: Condition between While and Do must only add one \
-								 value to the stack",
-								a.get_pos()
-							))
-							.exit(1);
+							return Err(self
+								.add_error(format!(
+									"{}: Condition between While and Do must only add \
+									 one value to the stack{}",
+									a.get_pos(),
+									a.expansion_trace()
+								))
+								.abort());
 						}
 						cf.push(typ)
 					} else {
-						self.add_error(format!(
-							"{}: Expected While before Do",
-							annot.get_pos()
-						))
-						.exit(1);
+						return Err(self
+							.add_error(format!(
+								"{}: Expected While before Do{}",
+								annot.get_pos(),
+								annot.expansion_trace()
+							))
+							.abort());
 					}
 				}
 				| Eq(type_l, type_r)
@@ -327,6 +430,23 @@ impl Program {
 					}
 					stack.push(annot.clone().with_type(Type::I64));
 				}
+				| Exit => {
+					stack.pop();
+				}
+				| ToF64 | Round | Floor | Ceil | Sqrt | Abs | Neg | Exp | Log | Sin
+				| Cos => {
+					stack.pop();
+					stack.push(annot.clone().with_type(Type::F64));
+				}
+				| Fmod | Min | Max | Pow => {
+					stack.pop();
+					stack.pop();
+					stack.push(annot.clone().with_type(Type::F64));
+				}
+				| ToI64 | Popcount | Clz | Ctz => {
+					stack.pop();
+					stack.push(annot.clone().with_type(Type::I64));
+				}
 				| Argc => stack.push(annot.clone().with_type(Type::I64)),
 				| Argv => stack.push(annot.clone().with_type(Type::Ptr)),
 				| Load8 | Load16 | Load32 | Load64 => {
@@ -338,12 +458,12 @@ impl Program {
 					stack.pop().unwrap();
 				}
 				| Cast(typ) => stack.last_mut().unwrap().set_type(*typ),
-				| ShiftR | ShiftL => {
+				| ShiftR | ShiftRA | ShiftL | RotL | RotR => {
 					stack.pop();
 					let b_typ = *stack.pop().unwrap().get_type();
 					stack.push(annot.clone().with_type(b_typ));
 				}
-				| BitAnd | BitOr => {
+				| BitAnd | BitOr | Xor => {
 					let a_typ = *stack.pop().unwrap().get_type();
 					stack.pop();
 					stack.push(annot.clone().with_type(a_typ));
@@ -354,6 +474,12 @@ impl Program {
 					stack.push(annot.clone().with_type(arg_typ));
 				}
 				| Mem(_) => stack.push(annot.clone().with_type(Type::Ptr)),
+				| Asm(ins, outs, _) | Host(_, ins, outs) => {
+					stack.truncate(stack.len() - ins.len());
+					for typ in outs.iter() {
+						stack.push(annot.clone().with_type(*typ));
+					}
+				}
 				| SetOver(size) => {
 					let set_type = *stack.pop().unwrap().get_type();
 					let index = stack.len() - *size as usize;
@@ -361,7 +487,7 @@ impl Program {
 					*val = annot.clone().with_type(set_type);
 				}
 			}
-		});
+		}
 
 		if !cf.is_empty() {
 			self.reporter.add_error(format!(
@@ -369,31 +495,30 @@ impl Program {
 				Stack::from_vec(stack.clone())
 			));
 		}
-		if !stack.is_empty() {
-			self.reporter.add_warning(format!(
-				"The stack is not empty at the end of the program\n{}",
-				Stack::from_vec(stack)
-			));
-		}
-		Program {
+		let program = Program {
 			ops,
 			reporter: self.reporter,
 			strings: self.strings,
 			memory_regions: self.memory_regions,
 			memory_regions_order: self.memory_regions_order,
-		}
+			macros: self.macros,
+			overflow_checks: self.overflow_checks,
+		};
+		Ok((program, stack))
 	}
 
 	#[allow(clippy::ptr_arg)]
-	pub fn check_args(&mut self, op: &Op, stack: &Vec<Annotation>) {
+	pub fn check_args(&mut self, op: &Op, stack: &Vec<Annotation>) -> Result<(), Abort> {
 		if stack.len() < op.required_stack_len() {
-			self.add_error(format!(
-				"{} requires at least{} values on the stack but got {}",
-				op,
-				op.required_stack_len(),
-				stack.len()
-			))
-			.exit(1);
+			return Err(self
+				.add_error(format!(
+					"{} requires at least {} values on the stack but got {}{}",
+					op,
+					op.required_stack_len(),
+					stack.len(),
+					op.annot.expansion_trace()
+				))
+				.abort());
 		}
 		let Op { typ, .. } = op;
 		let mut cloned_stack = stack.clone();
@@ -408,6 +533,12 @@ impl Program {
 			| OpType::SetOver(_)
 			| OpType::Dup(_)
 			| OpType::Swap
+			| OpType::Rot
+			| OpType::Unrot
+			| OpType::Nip
+			| OpType::Tuck
+			| OpType::Swap2
+			| OpType::Roll(_)
 			| OpType::Not
 			| OpType::Dump(_) => (),
 			| OpType::Add(..) | OpType::Sub(..) => {
@@ -433,7 +564,46 @@ impl Program {
 				self.check_implicit_conversion(&arg[0], &typ);
 				self.check_implicit_conversion(&arg[1], &typ);
 			}
-			| OpType::Mod(..) => {
+			| OpType::Exit
+			| OpType::ToF64
+			| OpType::Popcount
+			| OpType::Clz
+			| OpType::Ctz => self.check_implicit_conversion(&arg[0], &Type::I64),
+			| OpType::ToI64
+			| OpType::Round
+			| OpType::Floor
+			| OpType::Ceil
+			| OpType::Sqrt
+			| OpType::Abs
+			| OpType::Neg
+			| OpType::Exp
+			| OpType::Log
+			| OpType::Sin
+			| OpType::Cos => {
+				if arg[0].get_type() != &Type::F64 {
+					return Err(self
+						.add_error(format!(
+							"{op} Expected an F64 on top of the stack but got {}{}\n",
+							arg[0],
+							op.annot.expansion_trace()
+						))
+						.abort());
+				}
+			}
+			| OpType::Fmod | OpType::Min | OpType::Max | OpType::Pow => {
+				if arg[0].get_type() != &Type::F64 || arg[1].get_type() != &Type::F64 {
+					return Err(self
+						.add_error(format!(
+							"{op} Expected two F64 on top of the stack but got {} and \
+							 {}{}\n",
+							arg[1],
+							arg[0],
+							op.annot.expansion_trace()
+						))
+						.abort());
+				}
+			}
+			| OpType::Mod(..) | OpType::DivMod(_) | OpType::IntArith(..) => {
 				self.check_implicit_conversion(&arg[0], &Type::I64);
 				self.check_implicit_conversion(&arg[1], &Type::I64);
 			}
@@ -465,41 +635,59 @@ impl Program {
 			}
 			| OpType::Load8 | OpType::Load16 | OpType::Load32 | OpType::Load64 => {
 				if arg[0].get_type() != &Type::Ptr {
-					self.add_error(format!(
-						"{op} Expected a PTR on top of the stack but got {}\n",
-						arg[0]
-					))
-					.exit(1)
+					return Err(self
+						.add_error(format!(
+							"{op} Expected a PTR on top of the stack but got {}{}\n",
+							arg[0],
+							op.annot.expansion_trace()
+						))
+						.abort());
 				}
 			}
 			| OpType::Store8 | OpType::Store16 | OpType::Store32 | OpType::Store64 => {
 				if arg[1].get_type() != &Type::Ptr {
-					self.add_error(format!(
-						"{op} Expected a PTR on second position of the stack but got \
-						 {}\n",
-						arg[1]
-					))
-					.exit(1)
+					return Err(self
+						.add_error(format!(
+							"{op} Expected a PTR on second position of the stack but \
+							 got {}{}\n",
+							arg[1],
+							op.annot.expansion_trace()
+						))
+						.abort());
 				}
 			}
-			| OpType::ShiftR | OpType::ShiftL => {
+			| OpType::ShiftR
+			| OpType::ShiftRA
+			| OpType::ShiftL
+			| OpType::RotL
+			| OpType::RotR => {
 				if arg[0].get_type() != &Type::I64 {
-					self.add_error(format!(
-						"{op} Expected an I64 on top of the stack but got {}\n",
-						arg[0]
-					))
-					.exit(1)
+					return Err(self
+						.add_error(format!(
+							"{op} Expected an I64 on top of the stack but got {}{}\n",
+							arg[0],
+							op.annot.expansion_trace()
+						))
+						.abort());
 				}
 			}
-			| OpType::BitAnd | OpType::BitOr => {
+			| OpType::BitAnd | OpType::BitOr | OpType::Xor => {
 				self.check_implicit_conversion(&arg[1], arg[0].get_type())
 			}
 			| OpType::And | OpType::Or => {
 				self.check_implicit_conversion(&arg[0], &Type::Bool);
 				self.check_implicit_conversion(&arg[1], &Type::Bool);
 			}
+			| OpType::Asm(ins, ..) | OpType::Host(_, ins, _) => {
+				// The declared stack effect is trusted, only the arguments are
+				// checked against it.
+				for (typ, arg) in ins.iter().rev().zip(arg) {
+					self.check_implicit_conversion(arg, typ);
+				}
+			}
 			| _ => unreachable!(),
 		}
+		Ok(())
 	}
 
 	fn check_implicit_conversion(&mut self, from: &Annotation, to: &Type) {
@@ -508,16 +696,18 @@ impl Program {
 		}
 		if !Self::ALLOWED_IMPLICIT_CAST.contains(&(*from.get_type(), *to)) {
 			self.add_error(format!(
-				"{}: Attempting to implicitly convert from {from} to {to}",
-				from.get_pos()
+				"{}: Attempting to implicitly convert from {from} to {to}{}",
+				from.get_pos(),
+				from.expansion_trace()
 			));
 			return;
 		}
 		self.reporter.add_warning(format!(
-			"{}: Implicit conversion from {} to {}",
+			"{}: Implicit conversion from {} to {}{}",
 			from.get_pos(),
 			from.get_type(),
-			to
+			to,
+			from.expansion_trace()
 		));
 	}
 }
@@ -0,0 +1,92 @@
+//! Helpers shared by the integration tests, each of them only uses some
+#![allow(dead_code)]
+
+use std::{
+	io::Write,
+	path::{Path, PathBuf},
+	process::{Command, Stdio},
+};
+
+use ssmpl::{
+	load,
+	parser::Program,
+	report::{Level, Reporter},
+	Cli,
+	Mode,
+};
+
+pub const SSMPL: &str = env!("CARGO_BIN_EXE_ssmpl");
+
+/// Command line of `mode` without any option, for the program `test.ssmpl`
+pub fn cli(mode: Mode) -> Cli<String> {
+	Cli::new("ssmpl".into(), mode, "test.ssmpl".into())
+}
+
+/// Reporter keeping the reports of the program without printing them
+pub fn quiet() -> Reporter {
+	let mut reporter = Reporter::new(Level::Error);
+	reporter.quiet = true;
+	reporter
+}
+
+/// Parses and type checks `input` without printing the reports
+pub fn load_quietly(input: &str, cli: &Cli<String>) -> Result<Program, Vec<String>> {
+	load(input, cli, quiet())
+}
+
+/// The valid program `input`
+pub fn program(input: &str, cli: &Cli<String>) -> Program {
+	load_quietly(input, cli).unwrap_or_else(|errors| panic!("{errors:?}"))
+}
+
+/// Directory of its own for a test, removed with everything in it once dropped
+pub struct TestDir {
+	pub path: PathBuf,
+}
+
+impl TestDir {
+	pub fn new(name: &str) -> Self {
+		let path =
+			std::env::temp_dir().join(format!("ssmpl-{}-{name}", std::process::id()));
+		std::fs::create_dir_all(&path).unwrap();
+		TestDir { path }
+	}
+}
+
+impl Drop for TestDir {
+	fn drop(&mut self) { std::fs::remove_dir_all(&self.path).unwrap() }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Output {
+	pub code:   i32,
+	pub stdout: String,
+	pub stderr: String,
+}
+
+/// Runs `ssmpl <args>` in `dir` on the stdin `stdin`
+pub fn ssmpl(dir: &Path, args: &[&str], stdin: &str) -> Output {
+	let mut child = Command::new(SSMPL)
+		.current_dir(dir)
+		.args(args)
+		.stdin(Stdio::piped())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped())
+		.spawn()
+		.unwrap();
+	// The program may exit before reading all of it
+	let _ = child.stdin.take().unwrap().write_all(stdin.as_bytes());
+	let output = child.wait_with_output().unwrap();
+	Output {
+		code:   output.status.code().unwrap_or_else(|| panic!("{args:?} was killed")),
+		stdout: String::from_utf8(output.stdout).unwrap(),
+		stderr: String::from_utf8(output.stderr).unwrap(),
+	}
+}
+
+/// Simulates `program` as `test.ssmpl` in `dir` with `args`, only the errors
+/// being reported
+pub fn sim(dir: &Path, program: &str, args: &[&str], stdin: &str) -> Output {
+	std::fs::write(dir.join("test.ssmpl"), program).unwrap();
+	ssmpl(dir, &[&["sim", "test.ssmpl", "--log", "Error"][..], args].concat(), stdin)
+}
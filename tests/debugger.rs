@@ -0,0 +1,135 @@
+mod common;
+
+use common::{cli, program};
+use ssmpl::{
+	annotation::Type,
+	simulator::{RuntimeError, Simulator},
+	Mode,
+};
+
+/// What the debugger prints for each of the `commands`, the line it starts with
+/// first, and what the program printed
+fn debug(input: &str, commands: &[&str]) -> (Vec<String>, String) {
+	let cli = cli(Mode::Debug);
+	let program = program(input, &cli);
+	let (mut out, mut stdout) = (vec![], vec![]);
+	let commands = commands.iter().map(|command| command.to_string());
+	Simulator::new(&cli)
+		.with_stdout(&mut stdout)
+		.debug(&program, commands, &mut out)
+		.unwrap();
+	let out = String::from_utf8(out).unwrap();
+	let mut shown: Vec<String> = out.split("(debug) ").map(str::to_string).collect();
+	// Nothing follows the prompt of the command that was never given
+	assert_eq!(shown.pop().as_deref(), Some(""));
+	shown.remove(0);
+	(shown, String::from_utf8(stdout).unwrap())
+}
+
+const PROGRAM: &str = "decla x 8\nmem(x) 1 |>8\nmem(x) 2 |>8\n3 dump";
+
+#[test]
+fn steps_forwards_and_backwards() {
+	let (shown, stdout) = debug(PROGRAM, &["s", "s 3", "rs", "rs 2", "stack", "s 10"]);
+	assert_eq!(shown, [
+		"step 1: [test.ssmpl:2:8] PushI(1)\n",
+		"step 4: [test.ssmpl:3:8] PushI(2)\n",
+		"step 3: [test.ssmpl:3:1] Mem\n",
+		"step 1: [test.ssmpl:2:8] PushI(1)\n",
+		"ptr 0\n",
+		"step 8: The program exited with code 0\n",
+	]);
+	// The steps executed again are not printed twice
+	assert_eq!(stdout, "3\n");
+}
+
+#[test]
+fn continues_backwards_to_the_previous_breakpoint() {
+	let (shown, _) = debug(PROGRAM, &["b 2", "b 4", "c", "c", "rc", "rc", "rc"]);
+	assert_eq!(shown, [
+		"Breakpoint 0\n",
+		"Breakpoint 1\n",
+		"step 6: [test.ssmpl:4:1] PushI(3)\n",
+		"step 8: The program exited with code 0\n",
+		"step 6: [test.ssmpl:4:1] PushI(3)\n",
+		"step 0: [test.ssmpl:2:1] Mem\n",
+		"No breakpoint was hit before, back to the start\nstep 0: [test.ssmpl:2:1] Mem\n",
+	]);
+}
+
+#[test]
+fn watches_the_writes_backwards() {
+	let (shown, _) = debug(PROGRAM, &["c", "rw x", "mem x 1", "rw x", "mem x 1", "rw x"]);
+	assert_eq!(shown[1..], [
+		"step 5: [test.ssmpl:3:10] Store8\n",
+		"0: 01\n",
+		"step 2: [test.ssmpl:2:10] Store8\n",
+		"0: 00\n",
+		"Address 0 was not written before\n",
+	]);
+}
+
+#[test]
+fn forgets_the_oldest_steps() {
+	let (shown, _) =
+		debug("decla x 8\nmem(x) 1 |>8\n0 while dup 300000 < do 1 + end drop", &[
+			"c",
+			"rw x",
+			"b 2",
+			"rc",
+			"rs 2000000",
+		]);
+	assert_eq!(shown[1..], [
+		"Address 0 was not written since step 1052672, the oldest kept\n",
+		"Breakpoint 0\n",
+		"No breakpoint was hit since step 1052672, the oldest kept\nstep 1052672: \
+		 [test.ssmpl:3:13] PushI(300000)\n",
+		"step 1052672: [test.ssmpl:3:13] PushI(300000)\n",
+	]);
+}
+
+#[test]
+fn watches_the_bytes_changed_by_host_functions() {
+	let cli = cli(Mode::Debug);
+	let program = program(
+		"decla x 8\nmem(x) 1 |>8\nmem(x) host \"fill\" (ptr -- i64) drop\n3 dump",
+		&cli,
+	);
+	let mut out = vec![];
+	let commands = ["c", "rw 2", "mem x 3", "c", "rw 0"].map(String::from).into_iter();
+	Simulator::new(&cli)
+		.with_host("fill", &[Type::Ptr], &[Type::I64], |args, memory| {
+			// The first byte is written but keeps its value
+			let x = args[0] as usize;
+			memory[x..x + 3].copy_from_slice(&[1, 7, 7]);
+			Ok(vec![0])
+		})
+		.debug(&program, commands, &mut out)
+		.unwrap();
+	let out = String::from_utf8(out).unwrap();
+	let shown: Vec<&str> = out.split("(debug) ").collect();
+	assert_eq!(shown[2..6], [
+		"step 4: [test.ssmpl:3:8] Host(fill)\n",
+		"0: 01 00 00\n",
+		"step 8: The program exited with code 0\n",
+		"step 2: [test.ssmpl:2:10] Store8\n",
+	]);
+}
+
+#[test]
+fn returns_the_failures_to_write_what_it_shows() {
+	let cli = cli(Mode::Debug);
+	let program = program(PROGRAM, &cli);
+	let mut full = std::fs::OpenOptions::new().write(true).open("/dev/full").unwrap();
+	let commands = ["s"].map(str::to_string).into_iter();
+	match Simulator::new(&cli).debug(&program, commands, &mut full) {
+		| Err(RuntimeError::Io(msg)) => {
+			assert_eq!(
+				msg,
+				"Failed to write the output of the debugger: No space left on device \
+				 (os error 28)"
+			)
+		}
+		| other => panic!("expected a failure to write but got {other:?}"),
+	}
+}
@@ -0,0 +1,45 @@
+mod common;
+
+use common::{ssmpl, TestDir};
+
+const STATUSES: [i64; 7] = [0, 3, 255, 256, 300, -1, -300];
+
+/// Exit code of `ssmpl <mode> test.ssmpl <options>` on the program `program`
+fn status(dir: &TestDir, program: &str, mode: &str, options: &[&str]) -> i32 {
+	std::fs::write(dir.path.join("test.ssmpl"), program).unwrap();
+	ssmpl(&dir.path, &[&[mode, "test.ssmpl"][..], options].concat(), "").code
+}
+
+/// The program exiting with `status`, with the `exit` intrinsic or the
+/// `--exit-with-stack` flag
+fn programs(status: i64) -> [(String, &'static [&'static str]); 2] {
+	[(format!("{status} exit"), &[]), (format!("{status}"), &["--exit-with-stack"])]
+}
+
+#[test]
+fn sim_exits_with_the_low_byte_of_the_status() {
+	let dir = TestDir::new("sim");
+	for status_ in STATUSES {
+		for (program, flags) in programs(status_) {
+			assert_eq!(
+				status(&dir, &program, "sim", flags),
+				(status_ & 0xFF) as i32,
+				"{program} {flags:?}"
+			);
+		}
+	}
+}
+
+#[test]
+#[ignore = "needs nasm and ld, run with `cargo test -- --ignored`"]
+fn sim_and_compiled_programs_exit_with_the_same_status() {
+	let dir = TestDir::new("com");
+	for status_ in STATUSES {
+		for (program, flags) in programs(status_) {
+			let sim = status(&dir, &program, "sim", flags);
+			let options = [&["--run", "-o", "test"][..], flags].concat();
+			let com = status(&dir, &program, "com", &options);
+			assert_eq!(sim, com, "{program} {flags:?}");
+		}
+	}
+}
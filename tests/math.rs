@@ -0,0 +1,150 @@
+//! The math functions of the simulator must give the same bits as the ones of
+//! the runtime of the compiled programs (`src/resources/aux.o`, linked into the
+//! tests by `build.rs` with the `aux` feature)
+#![cfg(all(target_arch = "x86_64", target_os = "linux"))]
+
+use ssmpl::math;
+
+extern "C" {
+	fn math_fmod(x: f64, y: f64) -> f64;
+	fn math_exp(x: f64) -> f64;
+	fn math_log(x: f64) -> f64;
+	fn math_sin(x: f64) -> f64;
+	fn math_cos(x: f64) -> f64;
+	fn math_pow(x: f64, y: f64) -> f64;
+}
+
+/// Zeros, subnormals, infinities, NaNs and the values around the branches of
+/// the functions
+const EDGES: [f64; 36] = [
+	0.0,
+	-0.0,
+	f64::from_bits(1),
+	-f64::from_bits(1),
+	f64::from_bits(0x000F_FFFF_FFFF_FFFF),
+	f64::MIN_POSITIVE,
+	-f64::MIN_POSITIVE,
+	f64::EPSILON,
+	0.5,
+	-0.5,
+	1.0,
+	-1.0,
+	1.0 + f64::EPSILON,
+	1.0 - f64::EPSILON / 2.0,
+	2.0,
+	-2.0,
+	3.0,
+	0.1,
+	std::f64::consts::FRAC_PI_4,
+	std::f64::consts::FRAC_PI_2,
+	std::f64::consts::PI,
+	-std::f64::consts::PI,
+	709.782712893384,
+	709.8,
+	-745.1332191019411,
+	-745.2,
+	1e22,
+	1e300,
+	-1e300,
+	1.0e16 * std::f64::consts::PI,
+	f64::MAX,
+	f64::MIN,
+	f64::INFINITY,
+	f64::NEG_INFINITY,
+	f64::NAN,
+	-f64::NAN,
+];
+
+/// Deterministic random bits (xorshift64*)
+struct Random(u64);
+
+impl Random {
+	fn next(&mut self) -> u64 {
+		self.0 ^= self.0 >> 12;
+		self.0 ^= self.0 << 25;
+		self.0 ^= self.0 >> 27;
+		self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+	}
+
+	/// Any f64, NaNs with payloads included, or one of magnitude up to 2^64 half
+	/// of the time
+	fn f64(&mut self) -> f64 {
+		let bits = self.next();
+		if bits & 1 == 0 {
+			f64::from_bits(bits)
+		} else {
+			let scale = 2f64.powi((bits >> 1) as i32 % 64);
+			(self.next() as i64 as f64) / i64::MAX as f64 * scale
+		}
+	}
+}
+
+/// Arguments to check, the edges and random ones
+fn arguments(seed: u64) -> Vec<f64> {
+	let mut random = Random(seed);
+	EDGES.into_iter().chain((0..100_000).map(|_| random.f64())).collect()
+}
+
+fn check_unary(name: &str, sim: fn(f64) -> f64, com: unsafe extern "C" fn(f64) -> f64) {
+	for x in arguments(0x5EED ^ name.len() as u64) {
+		let (sim, com) = (sim(x), unsafe { com(x) });
+		assert_eq!(
+			sim.to_bits(),
+			com.to_bits(),
+			"{name}({x:e} = {:#x}) is {sim:e} in simulation but {com:e} compiled",
+			x.to_bits()
+		);
+	}
+}
+
+fn check_binary(
+	name: &str,
+	sim: fn(f64, f64) -> f64,
+	com: unsafe extern "C" fn(f64, f64) -> f64,
+) {
+	let xs = arguments(0xF00D ^ name.len() as u64);
+	let mut pairs: Vec<(f64, f64)> =
+		EDGES.iter().flat_map(|x| EDGES.iter().map(move |y| (*x, *y))).collect();
+	pairs.extend(xs.iter().zip(xs.iter().rev()).map(|(x, y)| (*x, *y)));
+	for (x, y) in pairs {
+		let (sim, com) = (sim(x, y), unsafe { com(x, y) });
+		assert_eq!(
+			sim.to_bits(),
+			com.to_bits(),
+			"{name}({x:e}, {y:e}) = ({:#x}, {:#x}) is {sim:e} in simulation but {com:e} \
+			 compiled",
+			x.to_bits(),
+			y.to_bits()
+		);
+	}
+}
+
+#[test]
+fn fmod_gives_the_bits_of_the_compiled_program() {
+	check_binary("fmod", math::fmod, math_fmod)
+}
+
+#[test]
+fn exp_gives_the_bits_of_the_compiled_program() {
+	check_unary("exp", math::exp, math_exp)
+}
+
+#[test]
+fn log_gives_the_bits_of_the_compiled_program() {
+	check_unary("log", math::log, math_log)
+}
+
+#[test]
+fn sin_gives_the_bits_of_the_compiled_program() {
+	check_unary("sin", math::sin, math_sin)
+}
+
+#[test]
+fn cos_gives_the_bits_of_the_compiled_program() {
+	check_unary("cos", math::cos, math_cos)
+}
+
+#[test]
+fn pow_gives_the_bits_of_the_compiled_program() {
+	check_binary("pow", math::pow, math_pow)
+}
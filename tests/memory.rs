@@ -0,0 +1,52 @@
+mod common;
+
+use common::{sim, TestDir};
+
+/// Exit code and output of the simulation of `program` with 64 bytes of memory
+fn sim_64(name: &str, program: &str) -> (i32, String) {
+	let dir = TestDir::new(name);
+	let output = sim(&dir.path, program, &["--sim-mem", "64"], "");
+	(output.code, output.stdout)
+}
+
+#[test]
+fn reports_out_of_bounds_accesses_with_the_nearest_region() {
+	for (name, program, error) in [
+		(
+			"load",
+			"decla a 8\nmem(a) 16 - <|8",
+			"ERROR: [test.ssmpl:2:13]: Out of bounds Load8: 1 byte(s) at address -16 \
+			 but the memory is 64 bytes long (see `--sim-mem`)\n\tNearest region: decla \
+			 region `a` at [0, 8)\n",
+		),
+		(
+			"store",
+			"decla a 8\nmem(a) 100 + 1 |>16",
+			"ERROR: [test.ssmpl:2:16]: Out of bounds Store16: 2 byte(s) at address 100 \
+			 but the memory is 64 bytes long (see `--sim-mem`)\n\tNearest region: \
+			 builtin `mem` at [8, 64)\n",
+		),
+		(
+			"expansion",
+			"macro peek { <|16 }\nmem 63 + peek",
+			"ERROR: [test.ssmpl:1:14]: Out of bounds Load16: 2 byte(s) at address 63 \
+			 but the memory is 64 bytes long (see `--sim-mem`)\n\tNearest region: \
+			 builtin `mem` at [0, 64)\n\tin expansion of `peek` at test.ssmpl:2:10\n",
+		),
+	] {
+		assert_eq!(sim_64(name, program), (1, error.into()), "{name}");
+	}
+}
+
+#[test]
+fn needs_room_for_the_strings_and_regions() {
+	assert_eq!(
+		sim_64("room", "decla a 100"),
+		(
+			1,
+			"ERROR: Not enough memory for the strings and `decla` regions: 100 bytes \
+			 are needed but `--sim-mem` is 64\n"
+				.into()
+		)
+	);
+}
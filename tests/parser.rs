@@ -0,0 +1,187 @@
+mod common;
+
+use common::{cli, load_quietly, program, sim, TestDir};
+use ssmpl::{annotation::Type, parser::OpType, Cli, Mode};
+
+/// Ops of the valid program `input`
+fn ops(input: &str) -> Vec<String> {
+	let program = program(input, &cli(Mode::Sim));
+	program.ops.iter().map(|op| op.typ.to_string()).collect()
+}
+
+/// The only error of the invalid program `input`
+fn error(input: &str) -> String { error_with(input, &cli(Mode::Sim)) }
+
+/// The only error of the invalid program `input` with the command line `cli`
+fn error_with(input: &str, cli: &Cli<String>) -> String {
+	match load_quietly(input, cli) {
+		| Ok(_) => panic!("`{input}` should not load"),
+		| Err(errors) => errors[0].clone(),
+	}
+}
+
+#[test]
+fn macros_without_parameters_take_an_empty_argument_list() {
+	assert_eq!(ops("macro two { 2 } two() two"), ["PushI(2)", "PushI(2)"]);
+	assert_eq!(
+		error("macro two { 2 } two(1)"),
+		"ERROR: [test.ssmpl:1:17]: Macro two takes 0 arguments but 1 was given"
+	);
+}
+
+#[test]
+fn macro_arguments_are_substituted() {
+	assert_eq!(ops("macro add(a, b) { a b + } add(1, 2)"), [
+		"PushI(1)", "PushI(2)", "Add"
+	]);
+	assert_eq!(
+		error("macro add(a, b) { a b + } add(1)"),
+		"ERROR: [test.ssmpl:1:27]: Macro add takes 2 arguments but 1 was given"
+	);
+	assert_eq!(
+		error("macro opp(a) { 0 a - } opp(1, 2)"),
+		"ERROR: [test.ssmpl:1:24]: Macro opp takes 1 argument but 2 were given"
+	);
+}
+
+#[test]
+fn macro_cycles_are_reported_with_their_expansion_path() {
+	assert_eq!(
+		error("macro a { b }\nmacro b { c }\nmacro c { a }\nmacro d { 1 a }\nd"),
+		"ERROR: [test.ssmpl:3:11]: Macro expansion cycle: `a` -> `b` -> `c` -> \
+		 `a`\n\tin expansion of `c` at test.ssmpl:2:11\n\tin expansion of `b` at \
+		 test.ssmpl:1:11\n\tin expansion of `a` at test.ssmpl:4:13\n\tin expansion of \
+		 `d` at test.ssmpl:5:1"
+	);
+}
+
+#[test]
+fn macro_expansions_are_limited_by_macro_depth() {
+	let input = "macro a { 1 }\nmacro b { a }\nmacro c { b }\nc";
+	let mut cli = cli(Mode::Sim);
+	cli.max_macro_depth = 3;
+	assert_eq!(program(input, &cli).ops.len(), 1);
+	cli.max_macro_depth = 2;
+	assert_eq!(
+		error_with(input, &cli),
+		"ERROR: [test.ssmpl:2:11]: Maximum macro expansion depth (2) exceeded while \
+		 expanding `a`\n\tin expansion of `b` at test.ssmpl:3:11\n\tin expansion of `c` \
+		 at test.ssmpl:4:1"
+	);
+}
+
+#[test]
+fn macro_depth_must_be_positive() {
+	let dir = TestDir::new("macro-depth");
+	let output = sim(&dir.path, "1 drop", &["--macro-depth", "0"], "");
+	assert_eq!(output.code, 1);
+	assert!(
+		output.stdout.ends_with(
+			"ERROR: --macro-depth option requires a positive integer but got: `0`\n"
+		),
+		"{}",
+		output.stdout
+	);
+	assert_eq!(sim(&dir.path, "1 drop", &["--macro-depth", "1"], "").code, 0);
+}
+
+#[test]
+fn conditional_compilation_keeps_one_branch() {
+	assert_eq!(ops("#if SIM 1 #else 2 #end"), ["PushI(1)"]);
+	assert_eq!(ops("#if COM 1 #else 2 #end"), ["PushI(2)"]);
+	assert_eq!(ops("#if COM 1 #end 3"), ["PushI(3)"]);
+}
+
+#[test]
+fn conditional_compilation_errors() {
+	assert_eq!(
+		error("#if SIM 1 #else 2 #else 3 #end"),
+		"ERROR: [test.ssmpl:1:19]: Duplicate #else, the first one is at \
+		 [test.ssmpl:1:11]"
+	);
+	assert_eq!(
+		error("1 #else 2"),
+		"ERROR: [test.ssmpl:1:3]: Unexpected #else without a matching #if"
+	);
+	assert_eq!(error("#if SIM 1"), "ERROR: [test.ssmpl:1:1]: Unterminated #if");
+}
+
+#[test]
+fn exit_with_stack_exits_at_the_last_token() {
+	let mut cli = cli(Mode::Sim);
+	cli.exit_with_stack = true;
+	for (input, pos) in
+		[("1 2\n 3 ", "[test.ssmpl:2:2]"), ("macro m { 4 }\nm", "[test.ssmpl:2:1]")]
+	{
+		let program = program(input, &cli);
+		let exit = program.ops.last().unwrap();
+		assert_eq!(
+			(exit.typ.to_string(), exit.annot.get_pos().to_string()),
+			("Exit".into(), pos.into())
+		);
+	}
+}
+
+#[test]
+fn defines_are_named_by_identifiers() {
+	let dir = TestDir::new("defines");
+	let program = "#if _X1 _X1 #else 0 #end exit";
+	assert_eq!(sim(&dir.path, program, &["-D", "_X1=3"], "").code, 3);
+	for name in ["", "1x", "a-b"] {
+		let output = sim(&dir.path, program, &["-D", &format!("{name}=3")], "");
+		assert_eq!(output.code, 1, "{name}");
+		assert!(
+			output.stdout.ends_with(&format!(
+				"ERROR: Name of define must be an identifier: `{name}`\n"
+			)),
+			"{}",
+			output.stdout
+		);
+	}
+}
+
+const ASM: &str = "40 2\nasm (i64 i64 -- i64) {\n    pop rax\n    add [rsp], rax\n}";
+
+#[test]
+fn asm_blocks_keep_their_signature_and_code() {
+	let program = program(&format!("{ASM} else {{ + }} drop"), &cli(Mode::Com));
+	match &program.ops[2].typ {
+		| OpType::Asm(ins, outs, code) => {
+			assert_eq!(
+				(ins.as_slice(), outs.as_slice(), code.as_str()),
+				(
+					&[Type::I64, Type::I64][..],
+					&[Type::I64][..],
+					"    pop rax\n    add [rsp], rax"
+				)
+			)
+		}
+		| other => panic!("{other}"),
+	}
+}
+
+#[test]
+fn asm_blocks_are_simulated_with_their_fallback() {
+	assert_eq!(ops(&format!("{ASM} else {{ + }}")), ["PushI(40)", "PushI(2)", "Add"]);
+	let dir = TestDir::new("asm");
+	let output = sim(&dir.path, &format!("{ASM} else {{ + }} dump"), &[], "");
+	assert_eq!((output.code, output.stdout.as_str()), (0, "42\n"));
+}
+
+#[test]
+fn asm_blocks_without_fallback_cannot_be_simulated() {
+	assert_eq!(
+		error(ASM),
+		"ERROR: [test.ssmpl:2:1]: Inline assembly cannot be simulated, give it a \
+		 fallback written in Ssmpl with `asm (...) { ... } else { ... }`"
+	);
+}
+
+#[test]
+fn errors_of_the_lexer_are_returned() {
+	assert_eq!(error("1 \"abc"), "ERROR: [test.ssmpl:1:4]: Unterminated string literal");
+	assert_eq!(
+		error("1 'ab'"),
+		"ERROR: [test.ssmpl:1:5]: Unterminated or too long character literal"
+	);
+}
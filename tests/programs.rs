@@ -0,0 +1,41 @@
+mod common;
+
+use std::path::Path;
+
+use common::cli;
+use ssmpl::{
+	tester::{collect_tests, run_test, Outcome},
+	Mode,
+};
+
+/// Runs the programs like `ssmpl test` does, panicking with every failure
+fn check(tests: impl IntoIterator<Item = impl AsRef<Path>>) {
+	let mut failures = vec![];
+	for test in tests {
+		let test = test.as_ref();
+		if let Outcome { failure: Some(failure), stderr, .. } =
+			run_test(&cli(Mode::Test), test)
+		{
+			failures.push(format!("{}: {failure}\n{stderr}", test.display()));
+		}
+	}
+	assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn programs_print_their_expected_output() {
+	check(collect_tests(Path::new("tests/programs")).unwrap())
+}
+
+#[test]
+fn examples_print_their_expected_output() {
+	let examples = collect_tests(Path::new("examples")).unwrap();
+	// `argv` is not supported in simulation mode and `push_dump` has hexadecimal
+	// and octal literals
+	let skipped = ["examples/argv.ssmpl", "examples/push_dump.ssmpl"];
+	check(
+		examples
+			.into_iter()
+			.filter(|example| !skipped.iter().any(|s| example.ends_with(s))),
+	)
+}
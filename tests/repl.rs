@@ -0,0 +1,77 @@
+mod common;
+
+use common::{cli, quiet, TestDir};
+use ssmpl::{repl, Mode};
+
+/// Exit code of a session reading `input`, and the stacks it printed after the
+/// banner
+fn repl(input: &str) -> (i32, Vec<String>) {
+	let (mut stdout, mut stderr) = (vec![], vec![]);
+	let code = repl::run(
+		&cli(Mode::Repl),
+		&quiet(),
+		&mut input.as_bytes(),
+		&mut stdout,
+		&mut stderr,
+	)
+	.unwrap();
+	let stdout = String::from_utf8(stdout).unwrap();
+	let mut shown: Vec<String> = stdout.lines().map(str::to_string).collect();
+	shown.remove(0);
+	(code, shown)
+}
+
+#[test]
+fn keeps_the_state_between_entries() {
+	let (code, shown) =
+		repl("decla x 8\nmacro sq { dup * }\nmem(x) 3 sq |>8\n1 2.5\nmem(x) <|8 sq\n");
+	assert_eq!(code, 0);
+	assert_eq!(shown, [
+		"> []",
+		"> []",
+		"> []",
+		"> [i64 1, f64 2.5]",
+		"> [i64 1, f64 2.5, i64 81]",
+		"> ",
+	]);
+}
+
+#[test]
+fn undoes_the_failing_entries() {
+	let (_, shown) = repl(
+		"decla x 8\n1 2\nmacro m { 3 } mem(x) 4 |>8 m undefined\nm\nmem(x) 5 |>8 1 0 \
+		 /\nmem(x) <|8\n",
+	);
+	assert_eq!(shown[2..], [
+		// The macro defined by the entry is undone with it
+		"> [i64 1, i64 2]",
+		"> [i64 1, i64 2]",
+		// So is the write to the memory before the division by zero
+		"> [i64 1, i64 2]",
+		"> [i64 1, i64 2, i64 0]",
+		"> ",
+	]);
+}
+
+#[test]
+fn loads_files() {
+	let dir = TestDir::new("repl-load");
+	let path = dir.path.join("lib.ssmpl");
+	std::fs::write(&path, "macro sq { dup * }\n").unwrap();
+	let (_, shown) = repl(&format!(":load {}\n3 sq\n:load\n", path.display()));
+	assert_eq!(shown, ["> []", "> [i64 9]", "> > "]);
+}
+
+#[test]
+fn reads_blocks_over_several_lines() {
+	let (_, shown) = repl("0 while dup 3 < do\n  1 +\nend\nmacro four {\n  4\n} four\n");
+	assert_eq!(shown, ["> ... ... [i64 3]", "> ... ... [i64 3, i64 4]", "> "]);
+}
+
+#[test]
+fn returns_the_exit_code() {
+	assert_eq!(
+		repl("1\n7 syscall(60)\n2\n"),
+		(7, vec!["> [i64 1]".to_string(), "> ".to_string()])
+	);
+}
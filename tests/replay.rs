@@ -0,0 +1,99 @@
+mod common;
+
+use common::{cli, program};
+use ssmpl::{
+	simulator::{RuntimeError, Simulator},
+	Cli,
+	Mode,
+};
+
+/// Reads 5 bytes and writes them back
+const ECHO: &str =
+	"decla buf 8\n0 mem(buf) 5 syscall(0) drop\n1 mem(buf) 5 syscall(1) drop";
+
+/// Log of `--record` or `--replay` of its own
+fn log_path(name: &str) -> String {
+	let path =
+		std::env::temp_dir().join(format!("ssmpl-replay-{}-{name}", std::process::id()));
+	path.display().to_string()
+}
+
+/// Simulates `input` on the stdin `stdin`, returns what it printed
+fn simulate(input: &str, cli: &Cli<String>, stdin: &str) -> Result<String, RuntimeError> {
+	let program = program(input, cli);
+	let mut stdout = vec![];
+	Simulator::new(cli)
+		.with_stdin(stdin.as_bytes())
+		.with_stdout(&mut stdout)
+		.run(&program)?;
+	Ok(String::from_utf8(stdout).unwrap())
+}
+
+/// Records `ECHO` reading `hello`, returns the log
+fn record(name: &str) -> String {
+	let path = log_path(name);
+	let cli = Cli { record: Some(path.clone()), ..cli(Mode::Sim) };
+	assert_eq!(simulate(ECHO, &cli, "hello world").unwrap(), "hello");
+	path
+}
+
+/// Why the replay of `input` stopped
+fn diverged(input: &str, log: &str) -> String {
+	let cli = Cli { replay: Some(log.to_string()), ..cli(Mode::Sim) };
+	match simulate(input, &cli, "") {
+		| Err(RuntimeError::Fault(msg)) => msg,
+		| other => panic!("expected `{input}` to diverge but got {other:?}"),
+	}
+}
+
+#[test]
+fn replays_the_recorded_syscalls() {
+	let log = record("same");
+	assert_eq!(
+		std::fs::read_to_string(&log).unwrap(),
+		"0 0 0 5 out=68656c6c6f ret=5\n1 1 0 5 in=68656c6c6f ret=5\n"
+	);
+	// Without reading stdin
+	let cli = Cli { replay: Some(log.clone()), ..cli(Mode::Sim) };
+	assert_eq!(simulate(ECHO, &cli, "").unwrap(), "hello");
+	std::fs::remove_file(log).unwrap();
+}
+
+#[test]
+fn stops_at_the_first_difference_with_the_recording() {
+	let log = record("diverged");
+	// Another syscall
+	assert_eq!(
+		diverged("decla buf 8\n1 mem(buf) 5 syscall(1)", &log),
+		"[test.ssmpl:2:14]: Replay diverged: syscall #1 is syscall 1 but was syscall 0 \
+		 in the recording (`--replay`)"
+	);
+	// Other arguments
+	assert_eq!(
+		diverged("decla buf 8\n0 mem(buf) 4 syscall(0)", &log),
+		"[test.ssmpl:2:14]: Replay diverged: syscall #1 (0) has the arguments [0, 0, 4] \
+		 but had [0, 0, 5] in the recording (`--replay`)"
+	);
+	// Other data to write
+	assert_eq!(
+		diverged(&ECHO.replace("1 mem(buf)", "mem(buf) 'j' |>8 1 mem(buf)"), &log),
+		"[test.ssmpl:3:31]: Replay diverged: syscall #2 (1) reads \"jello\" but read \
+		 \"hello\" in the recording (`--replay`)"
+	);
+	// More syscalls
+	assert_eq!(
+		diverged(&format!("{ECHO}\n1 mem(buf) 5 syscall(1)"), &log),
+		"[test.ssmpl:4:14]: Replay diverged: syscall #3 is syscall 1 but the recording \
+		 ended after 2 syscall(s) (`--replay`)"
+	);
+	// Fewer syscalls
+	let msg = diverged("decla buf 8\n0 mem(buf) 5 syscall(0)", &log);
+	assert_eq!(
+		msg,
+		format!(
+			"Replay diverged: the program stopped but 1 syscall(s) of `{log}` were not \
+			 replayed (`--replay`)"
+		)
+	);
+	std::fs::remove_file(log).unwrap();
+}
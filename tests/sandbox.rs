@@ -0,0 +1,101 @@
+mod common;
+
+use common::{sim, TestDir};
+
+/// Why the simulation of `program` in `dir` with `args` was stopped by its
+/// limits or its policy
+fn stopped(dir: &TestDir, program: &str, args: &[&str]) -> String {
+	let output = sim(&dir.path, program, args, "");
+	assert_eq!(output.code, 125, "{program}");
+	output.stdout.lines().last().unwrap().to_string()
+}
+
+/// Why the simulation of `program` was stopped by the policy `policy`
+fn denied(name: &str, program: &str, policy: &str) -> String {
+	let dir = TestDir::new(name);
+	std::fs::write(dir.path.join("policy"), policy).unwrap();
+	stopped(&dir, program, &["--policy", "policy"])
+}
+
+#[test]
+fn stops_after_the_maximum_number_of_steps() {
+	let dir = TestDir::new("steps");
+	let msg = stopped(&dir, "while true do end", &["--max-steps", "10"]);
+	assert!(msg.ends_with("Exceeded the limit of 10 steps (`--max-steps`)"), "{msg}");
+}
+
+#[test]
+fn stops_when_the_stack_is_too_big() {
+	let dir = TestDir::new("stack");
+	assert_eq!(
+		stopped(&dir, "1 2 3 4\n5", &["--max-stack", "4"]),
+		"ERROR: [test.ssmpl:2:1]: Exceeded the limit of 4 values on the stack \
+		 (`--max-stack`)"
+	);
+}
+
+#[test]
+fn stops_after_the_timeout() {
+	let dir = TestDir::new("timeout");
+	let msg = stopped(&dir, "while true do end", &["--timeout", "0.05"]);
+	assert!(msg.ends_with("Exceeded the timeout of 50ms (`--timeout`)"), "{msg}");
+}
+
+#[test]
+fn denies_the_syscalls_not_allowed() {
+	assert_eq!(
+		denied("syscalls", "decla buf 8\n0 mem(buf) 8 syscall(0)", "syscalls = 1 60"),
+		"ERROR: [test.ssmpl:2:14]: Syscall policy violation: syscall 0 is not allowed \
+		 (`--policy`)"
+	);
+}
+
+#[test]
+fn denies_the_paths_not_allowed() {
+	assert_eq!(
+		denied("paths", "\"/etc/passwd\" 0 0 syscall(2)", "open = /nonexistent/sandbox/"),
+		"ERROR: [test.ssmpl:1:19]: Syscall policy violation: opening `/etc/passwd` is \
+		 not allowed (`--policy`)"
+	);
+}
+
+#[test]
+fn denies_writes_outside_of_stdout_and_stderr() {
+	assert_eq!(
+		denied("write", "\"out.txt\" 65 420 syscall(2)", "write = false"),
+		"ERROR: [test.ssmpl:1:18]: Syscall policy violation: opening `out.txt` for \
+		 writing is not allowed (`--policy`)"
+	);
+	// Writing to stdout is still allowed
+	assert_eq!(
+		denied(
+			"write_fd",
+			"1 \"ok\\n\" 3 syscall(1) drop 3 \"no\" 2 syscall(1)",
+			"write = false"
+		),
+		"ERROR: [test.ssmpl:1:37]: Syscall policy violation: writing to fd 3 is not \
+		 allowed (`--policy`)"
+	);
+}
+
+#[test]
+fn denies_creating_truncating_and_appending_without_write() {
+	// O_RDONLY with O_CREAT, O_TRUNC and O_APPEND
+	for (name, flags) in [("creat", 64), ("trunc", 512), ("append", 1024)] {
+		let dir = TestDir::new(&format!("open-{name}"));
+		std::fs::write(dir.path.join("policy"), "write = false").unwrap();
+		if name != "creat" {
+			std::fs::write(dir.path.join("out.txt"), "data").unwrap();
+		}
+		let program = format!("\"out.txt\" {flags} 420\nsyscall(2)");
+		assert_eq!(
+			stopped(&dir, &program, &["--policy", "policy"]),
+			"ERROR: [test.ssmpl:2:1]: Syscall policy violation: opening `out.txt` for \
+			 writing is not allowed (`--policy`)",
+			"{name}"
+		);
+		let content = std::fs::read_to_string(dir.path.join("out.txt")).ok();
+		let expected = (name != "creat").then(|| "data".to_string());
+		assert_eq!(content, expected, "{name}");
+	}
+}
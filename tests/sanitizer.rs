@@ -0,0 +1,71 @@
+mod common;
+
+use common::{cli, program};
+use ssmpl::{simulator::Simulator, Mode};
+
+/// Findings of `--sanitize` in the simulation of `input`
+fn findings(input: &str) -> Vec<String> {
+	let mut cli = cli(Mode::Sim);
+	cli.sanitize = true;
+	let program = program(input, &cli);
+	let mut simulator = Simulator::new(&cli);
+	simulator.run(&program).unwrap();
+	simulator.findings().to_vec()
+}
+
+#[test]
+fn reports_accesses_crossing_regions() {
+	assert_eq!(findings("decla a 4\ndecla b 4\nmem(a) 2 + 0 |>32"), ["[test.ssmpl:3:\
+	                                                                  14]: Sanitizer: \
+	                                                                  Store32 of 4 \
+	                                                                  byte(s) at \
+	                                                                  address 2 crosses \
+	                                                                  from decla \
+	                                                                  region `a` at \
+	                                                                  [0, 4) into \
+	                                                                  decla region \
+	                                                                  `b` at [4, \
+	                                                                  8)\n\tNever \
+	                                                                  written"]);
+}
+
+#[test]
+fn reports_uninitialized_reads_once_per_op() {
+	assert_eq!(
+		findings("decla a 8\nmem(a) 1 |>8\n0 while dup 3 != do mem(a) <|16 drop ++ end"),
+		["[test.ssmpl:3:28]: Sanitizer: Load16 of 2 byte(s) at address 0 reads the \
+		  uninitialized byte 1 of decla region `a` at [0, 8)\n\tNever written"]
+	);
+}
+
+#[test]
+fn reports_pointers_loaded_as_integers() {
+	assert_eq!(findings("decla a 8\ndecla p 8\nmem(p) mem(a) |>64\nmem(p) <|64 drop"), [
+		"[test.ssmpl:4:8]: Sanitizer: Load64 at address 8 reads a value stored as ptr \
+		 as an integer, use `<|64 cast(Ptr)` to load a pointer\n\tLast written at \
+		 [test.ssmpl:3:15]"
+	]);
+}
+
+#[test]
+fn reports_integers_loaded_as_pointers() {
+	assert_eq!(findings("decla a 8\nmem(a) 8 |>64\nmem(a) <|64 cast(Ptr) drop"), [
+		"[test.ssmpl:3:8]: Sanitizer: Load64 at address 0 reads as a ptr a value that \
+		 was not stored as ptr\n\tLast written at [test.ssmpl:2:10]"
+	]);
+}
+
+#[test]
+fn reports_writes_into_string_literals() {
+	assert_eq!(findings("\"hi\" 'H' |>8"), ["[test.ssmpl:1:10]: Sanitizer: Store8 of \
+	                                         1 byte(s) at address 0 writes into \
+	                                         string literal \"hi\" at [0, \
+	                                         3)\n\tInitialized with a string literal"]);
+}
+
+#[test]
+fn accepts_correct_accesses() {
+	let input = "decla a 8\ndecla p 8\nmem(p) mem(a) |>64\nmem(p) <|64 cast(Ptr) 7 \
+	             |>64\nmem(a) <|64 drop \"hi\" <|8 drop";
+	assert_eq!(findings(input), [] as [String; 0]);
+}
@@ -0,0 +1,175 @@
+mod common;
+
+use common::{cli, load_quietly, program};
+use ssmpl::{
+	annotation::Type,
+	simulator::{RuntimeError, Simulator},
+	Mode,
+};
+
+#[test]
+fn runs_on_in_memory_streams() {
+	let cli = cli(Mode::Sim);
+	let program = program(
+		"decla buf 8\n0 mem(buf) 5 syscall(0)\n1 mem(buf) 5 syscall(1) drop\nmem(buf) 7 \
+		 dump 42",
+		&cli,
+	);
+	let mut stdout = vec![];
+	let mut simulator =
+		Simulator::new(&cli).with_stdin(&b"hello world"[..]).with_stdout(&mut stdout);
+	let status = simulator.run(&program).unwrap();
+	assert!(status.success());
+
+	let [read, buf, answer] = simulator.stack() else {
+		panic!("unexpected stack: {:?}", simulator.stack())
+	};
+	assert_eq!((*read, *answer), (5, 42));
+	let buf = *buf as usize;
+	assert_eq!(&simulator.memory()[buf..buf + 8], b"hello\0\0\0");
+	assert_eq!(simulator.memory().len(), cli.sim_mem);
+	drop(simulator);
+	assert_eq!(stdout, b"hello7\n");
+}
+
+#[test]
+fn returns_the_exit_status() {
+	let cli = cli(Mode::Sim);
+	let program = program("1 3 exit 2", &cli);
+	let mut simulator = Simulator::new(&cli);
+	let status = simulator.run(&program).unwrap();
+	assert_eq!(status.code, 3);
+	assert_eq!(simulator.stack(), [1]);
+}
+
+#[test]
+fn returns_runtime_errors() {
+	let cli = cli(Mode::Sim);
+	let program = program("0 cast(Ptr) 1 - <|8", &cli);
+	let mut simulator = Simulator::new(&cli);
+	match simulator.run(&program) {
+		| Err(RuntimeError::Fault(msg)) => {
+			assert!(msg.starts_with("[test.ssmpl:1:17]: Out of bounds"), "{msg}")
+		}
+		| other => panic!("expected an out of bounds fault but got {other:?}"),
+	}
+}
+
+#[test]
+fn returns_the_failures_to_write_the_output() {
+	let cli = cli(Mode::Sim);
+	let program = program("1 dump", &cli);
+	let mut full = std::fs::OpenOptions::new().write(true).open("/dev/full").unwrap();
+	let result = Simulator::new(&cli).with_stdout(&mut full).run(&program);
+	match result {
+		| Err(RuntimeError::Io(msg)) => {
+			assert_eq!(
+				msg,
+				"Failed to write the output of the program: No space left on device (os \
+				 error 28)"
+			)
+		}
+		| other => panic!("expected a failure to write but got {other:?}"),
+	}
+}
+
+#[test]
+fn returns_the_errors_of_the_program() {
+	let errors = load_quietly("1 +", &cli(Mode::Sim)).err().unwrap();
+	assert_eq!(errors.len(), 1);
+	assert!(errors[0].contains("Add requires at least 2 values"), "{errors:?}");
+
+	let errors = load_quietly("undefined_macro", &cli(Mode::Sim)).err().unwrap();
+	assert!(errors[0].contains("Undefined macro: undefined_macro"), "{errors:?}");
+}
+
+#[test]
+fn calls_host_functions() {
+	let cli = cli(Mode::Sim);
+	let program = program(
+		"decla key 3\nmem(key) 'a' |>8\nmem(key) 1 host \"db_get\" (ptr i64 -- i64)",
+		&cli,
+	);
+	let mut simulator = Simulator::new(&cli).with_host(
+		"db_get",
+		&[Type::Ptr, Type::I64],
+		&[Type::I64],
+		|args, memory| {
+			let key = &memory[args[0] as usize..(args[0] + args[1]) as usize];
+			Ok(vec![if key == b"a" { 7 } else { u64::MAX }])
+		},
+	);
+	assert!(simulator.run(&program).unwrap().success());
+	assert_eq!(simulator.stack(), [7]);
+}
+
+#[test]
+fn writes_the_folded_stacks_to_the_given_path() {
+	let path = std::env::temp_dir().join(format!("ssmpl-folded-{}", std::process::id()));
+	let mut cli = cli(Mode::Sim);
+	cli.profile_folded = Some(path.display().to_string());
+	let program = program("macro twice { 2 * }\n1 twice twice drop", &cli);
+	let mut stderr = vec![];
+	let mut simulator = Simulator::new(&cli).with_stderr(&mut stderr);
+	assert!(simulator.run(&program).unwrap().success());
+	drop(simulator);
+	// Without `--profile` nothing is reported
+	assert!(stderr.is_empty(), "{}", String::from_utf8_lossy(&stderr));
+	let folded = std::fs::read_to_string(&path).unwrap();
+	std::fs::remove_file(&path).unwrap();
+	assert_eq!(folded, "test.ssmpl:2 2\ntwice@test.ssmpl:2;test.ssmpl:1 2\n");
+}
+
+#[test]
+fn traps_integer_overflows_with_overflow_checks() {
+	let (unchecked, mut cli) = (cli(Mode::Sim), cli(Mode::Sim));
+	cli.overflow_checks = true;
+	for (input, op) in [
+		("9223372036854775807 1 +", "Add"),
+		("-9223372036854775807 2 -", "Sub"),
+		("4611686018427387904 2 *", "Mul"),
+	] {
+		let checked = program(input, &cli);
+		match Simulator::new(&cli).run(&checked) {
+			| Err(RuntimeError::Fault(msg)) => {
+				let col = input.len();
+				assert_eq!(msg, format!("[test.ssmpl:1:{col}]: Integer overflow in {op}"))
+			}
+			| other => panic!("expected an overflow in `{input}` but got {other:?}"),
+		}
+		// Without the checks they wrap around
+		let wrapping = program(input, &unchecked);
+		assert!(Simulator::new(&unchecked).run(&wrapping).unwrap().success());
+	}
+
+	// The ops saying what happens on overflow never trap
+	let program = program(
+		"9223372036854775807 1 +% 9223372036854775807 1 +| 9223372036854775807 1 +?",
+		&cli,
+	);
+	let mut simulator = Simulator::new(&cli);
+	assert!(simulator.run(&program).unwrap().success());
+	assert_eq!(simulator.stack(), [i64::MIN as u64, i64::MAX as u64, i64::MIN as u64, 1]);
+}
+
+#[test]
+fn stops_on_integer_divisions_by_zero() {
+	let cli = cli(Mode::Sim);
+	for (input, op) in [
+		("7 0 divmod", "DivMod"),
+		("-7 0 divmod_floor", "DivModFloor"),
+		("0 0 /", "Div"),
+		("7 0 %", "Mod"),
+	] {
+		let program = program(input, &cli);
+		match Simulator::new(&cli).run(&program) {
+			| Err(RuntimeError::Fault(msg)) => {
+				let col = input.rfind(' ').unwrap() + 2;
+				assert_eq!(msg, format!("[test.ssmpl:1:{col}]: Division by zero in {op}"))
+			}
+			| other => {
+				panic!("expected a division by zero in `{input}` but got {other:?}")
+			}
+		}
+	}
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use common::{sim, Output, TestDir};
+
+/// Exit code, stdout and stderr of the simulation of `program` in `dir` on the
+/// stdin `stdin`
+fn simulate(dir: &TestDir, program: &str, stdin: &str) -> (i32, String, String) {
+	let Output { code, stdout, stderr } = sim(&dir.path, program, &[], stdin);
+	(code, stdout, stderr)
+}
+
+#[test]
+fn reads_stdin_and_writes_stdout_and_stderr() {
+	let dir = TestDir::new("std");
+	let program = "decla buf 8\n0 mem(buf) 5 syscall(0) dump\n1 mem(buf) 5 syscall(1) \
+	               dump\n2 \"err\" 3 syscall(1) drop";
+	assert_eq!(
+		simulate(&dir, program, "hello world"),
+		(0, "5\nhello5\n".into(), "err".into())
+	);
+}
+
+#[test]
+fn opens_writes_and_reads_back_files() {
+	let dir = TestDir::new("files");
+	// O_WRONLY | O_CREAT | O_TRUNC, then O_RDONLY
+	let program = "decla buf 8\n\"out.txt\" 577 420 syscall(2) dump\n3 \"data\" 4 \
+	               syscall(1) dump\n3 syscall(3) dump\n\"out.txt\" 0 0 syscall(2) \
+	               dump\n3 mem(buf) 8 syscall(0) dump\n1 mem(buf) 4 syscall(1) drop";
+	assert_eq!(simulate(&dir, program, ""), (0, "3\n4\n0\n3\n4\ndata".into(), "".into()));
+	assert_eq!(std::fs::read_to_string(dir.path.join("out.txt")).unwrap(), "data");
+}
+
+#[test]
+fn returns_the_errors_as_negative_errno() {
+	let dir = TestDir::new("errno");
+	let program = "\"missing.txt\" 0 0 syscall(2) dump\n7 syscall(3) dump\n7 \"a\" 1 \
+	               syscall(1) dump";
+	assert_eq!(simulate(&dir, program, ""), (0, "-2\n-9\n-9\n".into(), "".into()));
+}
+
+#[test]
+fn exits_with_the_status_of_exit() {
+	let dir = TestDir::new("exit");
+	assert_eq!(
+		simulate(&dir, "1 \"a\" 1 syscall(1) drop\n3 syscall(60) drop\n1 dump", ""),
+		(3, "a".into(), "".into())
+	);
+}
+
+#[test]
+fn stops_on_the_syscalls_not_emulated() {
+	let dir = TestDir::new("unknown");
+	assert_eq!(
+		simulate(&dir, "0 syscall(39)", ""),
+		(
+			1,
+			"ERROR: [test.ssmpl:1:3]: Syscall 39 not implemented in simulation mode\n"
+				.into(),
+			"".into()
+		)
+	);
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use std::path::PathBuf;
+
+use common::cli;
+use ssmpl::{
+	policy::SANDBOX_EXIT_CODE,
+	tester::{run_test, Outcome},
+	Mode,
+};
+
+/// Writes the test program `name` and its expected output, if any, to a
+/// directory of its own
+fn test_file(name: &str, program: &str, expected: Option<&str>) -> PathBuf {
+	let dir = std::env::temp_dir().join(format!("ssmpl-tester-{}", std::process::id()));
+	std::fs::create_dir_all(&dir).unwrap();
+	let path = dir.join(format!("{name}.ssmpl"));
+	std::fs::write(&path, program).unwrap();
+	if let Some(expected) = expected {
+		std::fs::write(path.with_extension("expected"), expected).unwrap();
+	}
+	path
+}
+
+#[test]
+fn passes_with_the_expected_output() {
+	let test = test_file("pass", "1 2 + dump", Some("3\n"));
+	let Outcome { failure, .. } = run_test(&cli(Mode::Test), &test);
+	assert_eq!(failure, None);
+}
+
+#[test]
+fn fails_when_the_output_differs() {
+	let test = test_file("differs", "1 2 + dump", Some("4\n"));
+	let Outcome { failure, .. } = run_test(&cli(Mode::Test), &test);
+	assert!(failure.unwrap().starts_with("output differs"));
+}
+
+#[test]
+fn fails_with_the_exit_status() {
+	let test = test_file("status", "3 exit", None);
+	let Outcome { failure, .. } = run_test(&cli(Mode::Test), &test);
+	assert_eq!(failure.unwrap(), "exited with 3");
+
+	let test = test_file("fault", "0 cast(Ptr) 1 - <|8", None);
+	let Outcome { failure, stderr, .. } = run_test(&cli(Mode::Test), &test);
+	assert_eq!(failure.unwrap(), "exited with 1");
+	assert!(stderr.contains("Out of bounds"), "{stderr}");
+}
+
+#[test]
+fn fails_on_invalid_programs() {
+	let test = test_file("invalid", "1 +", None);
+	let Outcome { failure, stderr, .. } = run_test(&cli(Mode::Test), &test);
+	assert_eq!(failure.unwrap(), "invalid program");
+	assert!(stderr.contains("Add requires at least 2 values"), "{stderr}");
+}
+
+#[test]
+fn uses_the_options_of_the_command_line() {
+	let test = test_file("limit", "while true do end", None);
+	let mut cli = cli(Mode::Test);
+	cli.max_steps = Some(100);
+	let Outcome { failure, stderr, .. } = run_test(&cli, &test);
+	assert_eq!(failure.unwrap(), format!("exited with {SANDBOX_EXIT_CODE}"));
+	assert!(stderr.contains("--max-steps"), "{stderr}");
+}
+
+#[test]
+fn records_the_coverage() {
+	let test = test_file("coverage", "if 1 then\n\t2 dump\nend", Some("2\n"));
+	let mut cli = cli(Mode::Test);
+	cli.coverage = Some("unused.info".into());
+	let Outcome { failure, coverage, .. } = run_test(&cli, &test);
+	assert_eq!(failure, None);
+	let lcov = coverage.unwrap().to_lcov();
+	assert!(lcov.contains("DA:2,1\n"), "{lcov}");
+	assert!(lcov.contains("BRDA:1,6,0,1\nBRDA:1,6,1,0\n"), "{lcov}");
+	assert!(!std::path::Path::new("unused.info").exists());
+}
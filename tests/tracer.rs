@@ -0,0 +1,102 @@
+mod common;
+
+use common::{sim, TestDir};
+
+const PROGRAM: &str =
+	"decla x 8\nmacro put { |>8 }\nmem(x) 42 put\n1.0 0.0 / 2 3 < \"a\\tb\"";
+
+/// Records of `--trace` for the simulation of `PROGRAM`, its string literal is
+/// at the address 0 and `x` at 4
+fn trace(filter: Option<&str>) -> Vec<String> {
+	let dir = TestDir::new(&format!("trace-{}", filter.is_some()));
+	let mut args = vec!["--trace", "trace.json"];
+	if let Some(filter) = filter {
+		args.extend(["--trace-filter", filter]);
+	}
+	assert_eq!(sim(&dir.path, PROGRAM, &args, "").code, 0);
+	let trace = std::fs::read_to_string(dir.path.join("trace.json")).unwrap();
+	trace.lines().map(str::to_string).collect()
+}
+
+#[test]
+fn records_every_op_executed() {
+	let pos = |line, col| {
+		format!("\"pos\":{{\"file\":\"test.ssmpl\",\"line\":{line},\"col\":{col}}}")
+	};
+	assert_eq!(trace(None), [
+		format!(r#"{{"index":0,{},"op":"Mem","stack":[4],"writes":[]}}"#, pos(3, 1)),
+		format!(
+			r#"{{"index":1,{},"op":"PushI(42)","stack":[4,42],"writes":[]}}"#,
+			pos(3, 8)
+		),
+		format!(
+			r#"{{"index":2,{},"op":"Store8","stack":[],"writes":[{{"addr":4,"bytes":[42]}}]}}"#,
+			pos(2, 13)
+		),
+		format!(
+			r#"{{"index":3,{},"op":"PushF(1)","stack":[1.0],"writes":[]}}"#,
+			pos(4, 1)
+		),
+		format!(
+			r#"{{"index":4,{},"op":"PushF(0)","stack":[1.0,0.0],"writes":[]}}"#,
+			pos(4, 5)
+		),
+		format!(r#"{{"index":5,{},"op":"Div","stack":["inf"],"writes":[]}}"#, pos(4, 9)),
+		format!(
+			r#"{{"index":6,{},"op":"PushI(2)","stack":["inf",2],"writes":[]}}"#,
+			pos(4, 11)
+		),
+		format!(
+			r#"{{"index":7,{},"op":"PushI(3)","stack":["inf",2,3],"writes":[]}}"#,
+			pos(4, 13)
+		),
+		format!(
+			r#"{{"index":8,{},"op":"Lt","stack":["inf",true],"writes":[]}}"#,
+			pos(4, 15)
+		),
+		format!(
+			r#"{{"index":9,{},"op":"PushStr(a\tb)","stack":["inf",true,0],"writes":[]}}"#,
+			pos(4, 17)
+		),
+	]);
+}
+
+#[test]
+fn keeps_the_ops_of_the_filtered_lines_and_their_expansions() {
+	let ops: Vec<String> = trace(Some("test.ssmpl:3"))
+		.iter()
+		.map(|record| {
+			record.split("\"op\":").nth(1).unwrap().split(',').next().unwrap().to_string()
+		})
+		.collect();
+	assert_eq!(ops, ["\"Mem\"", "\"PushI(42)\"", "\"Store8\""]);
+}
+
+#[test]
+fn records_the_bytes_read_by_syscalls() {
+	let dir = TestDir::new("trace-read");
+	let program = "decla buf 4\n0 mem(buf) 4 syscall(0) drop";
+	assert_eq!(sim(&dir.path, program, &["--trace", "trace.json"], "hi").code, 0);
+	let trace = std::fs::read_to_string(dir.path.join("trace.json")).unwrap();
+	let record = trace.lines().nth(3).unwrap();
+	assert!(
+		record.ends_with(
+			r#""op":"Syscall(0)","stack":[2],"writes":[{"addr":0,"bytes":[104,105]}]}"#
+		),
+		"{record}"
+	);
+}
+
+#[test]
+fn reports_the_failures_to_write_the_trace() {
+	let dir = TestDir::new("trace-full");
+	let output = sim(&dir.path, "1 drop", &["--trace", "/dev/full"], "");
+	assert_eq!(
+		(output.code, output.stdout.as_str()),
+		(
+			1,
+			"ERROR: Failed to write the trace file: No space left on device (os error \
+			 28)\n"
+		)
+	);
+}